@@ -0,0 +1,29 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use aws_sdk_bedrockruntime::types::ContentBlock;
+
+use crate::tool::image_content::{image_block_from_path, is_image_path};
+
+
+// Claude accepts up to 20 images in a request
+pub const MAX_ATTACHMENTS: usize = 20;
+
+// Images mentioned as `@./screenshot.png` in the message. Other `@` words, such as `@someone`, are left alone.
+pub fn mentioned_images(input: &str) -> Vec<PathBuf> {
+    input.split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|path| path.trim_end_matches([',', '.', ';', ':', '!', '?', ')', '"', '\'']))
+        .map(PathBuf::from)
+        .filter(|path| is_image_path(path))
+        .collect()
+}
+
+// Reads and downscales the image so it can be sent with the next message.
+pub fn load_attachment(path: &Path) -> Result<ContentBlock> {
+    if !is_image_path(path) {
+        bail!("{} is not an image. Supported images: png, jpg, jpeg, gif, webp, svg.", path.display())
+    }
+    let image = image_block_from_path(path).context(format!("cannot attach {}", path.display()))?;
+    Ok(ContentBlock::Image(image))
+}
@@ -1,25 +1,45 @@
 
 use core::str;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
-use anyhow::{bail, Context, Result};
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, bail, Context, Result};
 use aws_sdk_bedrockruntime::types::{ContentBlockDelta, ConverseStreamOutput, StopReason};
-use aws_smithy_types::{Blob, Document};
+use aws_smithy_types::Document;
 use aws_sdk_bedrockruntime::Client;
-use aws_sdk_bedrockruntime::types::{ContentBlock, Message, SystemContentBlock, Tool, ToolConfiguration, ToolInputSchema, ToolSpecification, ConversationRole::{User, Assistant}, ToolResultBlock, ToolResultStatus, ToolUseBlock};
+use aws_sdk_bedrockruntime::types::{ContentBlock, Message, SystemContentBlock, ToolConfiguration, ConversationRole::{User, Assistant}, ToolResultBlock, ToolResultStatus, ToolUseBlock};
 use aws_sdk_bedrockruntime::operation::converse::ConverseOutput;
 use serde_json::Value;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinSet;
 
 use crate::tool::{create_tool_result_block, ToDocument};
-use crate::tool::read_file::{read_file, read_file_schema, READ_FILE_DESCRIPTION, READ_FILE_NAME};
-use crate::tool::image_generator_parameter::{IamgeQuality, ImageGenerationConfig, ImageGeneratorParameter, ImageGeneratorResponse};
-use crate::tool::generate_image::{generate_image_schema, save_generated_image, DEFAULT_HEIGHT, DEFAULT_WIDTH, GENERATE_IMAGE_DESCRIPTION, GENERATE_IMAGE_NAME};
-use crate::tool::run_python::{run_python, run_python_schema, RUN_PYTHON_DESCRIPTION, RUN_PYTHON_NAME};
-
+use crate::tool::context::ToolContext;
+use crate::tool::journal::Change;
+use crate::tool::registry::ToolRegistry;
+use crate::tool::permission::{Approval, PermissionManager};
+use crate::tool::python_kernel::PythonKernel;
+use crate::tool::read_file::READ_FILE_NAME;
+use crate::tool::write_file::WRITE_FILE_NAME;
+use crate::tool::edit_file::EDIT_FILE_NAME;
+use crate::tool::generate_image::GENERATE_IMAGE_NAME;
+use crate::tool::edit_image::EDIT_IMAGE_NAME;
+use crate::tool::run_python::RUN_PYTHON_NAME;
+use crate::tool::run_shell::RUN_SHELL_NAME;
+
+use crate::attachment::{load_attachment, mentioned_images, MAX_ATTACHMENTS};
+use crate::config::Config;
+use crate::session::{Role, Session, SessionContent};
+use crate::export::{export_session, unique_export_path, write_export, ExportFormat};
 use crate::terminal_service::TerminalService;
-use crate::model_constants::{CHAT_MODEL_ID, CHAT_MODEL_KEY, IMAGE_MODEL_ID, IMAGE_MODEL_KEY};
+use crate::model_constants::{CHAT_MODEL_ID, CHAT_MODEL_KEY};
 
-fn get_system_prompt() -> String {
-    return format!("
+fn get_system_prompt(tools: &ToolRegistry) -> String {
+    let prompt = format!("
         You are Claude, an AI assistant and an exceptional designer and software engineer with vast knowledge across multiple programming languages, frameworks, and best practices.
         You strictly follow the following rules.
 
@@ -28,6 +48,9 @@ fn get_system_prompt() -> String {
         2. Answer User's questions on files
         3. Create/Generate new Image based on user's prompt
         4. Perform data analysis/math using python and run Python code to solve the user's task.
+        5. Edit existing images or create variations of them.
+        6. Create and edit files.
+        7. Run shell commands.
 
         You are familiar with the following python libraries.
         - pandas
@@ -44,14 +67,24 @@ fn get_system_prompt() -> String {
         - Use {GENERATE_IMAGE_NAME} tool to generate an image.
         - Verify for the file path to save the image. If not provided, ask for it.
 
+        When asked to edit an existing image, replace or remove part of it, change its background, or create variations of it:
+        - Use {EDIT_IMAGE_NAME} tool with the path of the existing image.
+
         When asked to perform data analysis or math:
         - If you need the file content to perform analysis on, use the {READ_FILE_NAME} tool first
         - Use {RUN_PYTHON_NAME} tool to run python code for analysis
 
+        For quick commands such as `ls`, `git log` or `du`, use {RUN_SHELL_NAME} instead of running them from python.
+
         You can read files from local disk using {READ_FILE_NAME} tool. Use these capabilities when:
         - The user asks questions regarding to existing files
         - You need to examine the contents of an existing file
 
+        When asked to create or change a file:
+        - Use {EDIT_FILE_NAME} to change part of an existing file. Read the file first so the text to replace matches exactly.
+        - Use {WRITE_FILE_NAME} to create a new file or to replace the whole content of a file.
+        - The user reviews every change before it is written. If a change is denied, ask the user how to proceed.
+
         To use the tools provided,
         - Strictly apply the provided tool specification.
         - Never guess or make up information. If not enough information provided, ask for it.
@@ -59,93 +92,402 @@ fn get_system_prompt() -> String {
         - When generating code, don NOT write the code that contains code to read the data or file. Your code will be run in a seperate sandbox.
         - Add constructive comments when writing code.
     ");
+
+    let instructions = tools.instructions();
+    if instructions.is_empty() {
+        return prompt;
+    }
+    format!("{prompt}
+        You also have the following tools:
+{instructions}
+    ")
+}
+
+
+
+pub const COMMANDS_HELP: &str = "Available commands:
+  /help            Show this message.
+  /attach <path>   Attach an image to the next message. Images can also be attached with @<path> in the message.
+  /attach          List the images attached to the next message.
+  /attach clear    Remove the attached images.
+  /undo            Revert the files created or modified by the last tool that changed files.
+  /artifacts       List the files and folders created or modified by the tools in this conversation.
+  /sessions        List the saved conversations. Resume one with --resume <id>.
+  /export [--force] [format] [path]  Export the conversation to markdown (default), html or json.
+                   An existing file is only replaced with --force.
+  /python reset    Clear the variables and imports of the python kernel.
+  /python restart  Restart the python kernel.";
+
+
+// A content block of a streamed assistant message that is still receiving deltas.
+#[derive(Debug)]
+enum StreamedContentBlock {
+    Text(String),
+    ToolUse {
+        tool_use_id: String,
+        name: String,
+        input: String
+    },
+}
+
+impl StreamedContentBlock {
+    // empty text blocks are rejected by Converse, so they are dropped.
+    // A tool input that is not valid JSON is stored as `{}` and the tool use is added to `rejected`.
+    fn into_content_block(self, rejected: &mut HashMap<String, String>) -> Result<Option<ContentBlock>> {
+        match self {
+            StreamedContentBlock::Text(text) => {
+                if text.is_empty() {
+                    return Ok(None);
+                }
+                Ok(Some(ContentBlock::Text(text)))
+            },
+            StreamedContentBlock::ToolUse { tool_use_id, name, input } => {
+                let input = if input.trim().is_empty() {
+                    Document::Object(HashMap::new())
+                } else {
+                    match serde_json::from_str::<Value>(&input) {
+                        Ok(value) if value.is_object() => value.to_document(),
+                        _ => {
+                            rejected.insert(tool_use_id.clone(), format!("The tool input is not a valid JSON object, so the tool was not executed: {}", input));
+                            Document::Object(HashMap::new())
+                        },
+                    }
+                };
+
+                let tool_use_block = ToolUseBlock::builder()
+                    .tool_use_id(tool_use_id)
+                    .name(name)
+                    .input(input)
+                    .build()?;
+                Ok(Some(ContentBlock::ToolUse(tool_use_block)))
+            },
+        }
+    }
+}
+
+
+// The tool uses of an assistant message, and why some of them cannot be executed, keyed by tool use id.
+#[derive(Debug, Default)]
+struct ToolRequests {
+    tool_uses: Vec<ToolUseBlock>,
+    rejected: HashMap<String, String>,
+}
+
+impl ToolRequests {
+    // tool uses are only executed when the model stopped to use them, a tool use cut off by the token limit is incomplete
+    fn check_stop_reason(&mut self, stop_reason: &StopReason) {
+        if *stop_reason == StopReason::ToolUse {
+            return;
+        }
+        for tool_use in &self.tool_uses {
+            self.rejected.entry(tool_use.tool_use_id().to_owned()).or_insert(format!(
+                "The response stopped before the tool use was complete ({}), so the tool was not executed.",
+                stop_reason.as_str()
+            ));
+        }
+    }
 }
 
 
+// Progress reported by a tool running in the background.
+enum ToolProgress {
+    Started(usize),
+    Finished(usize, Result<ToolResultBlock>, Duration),
+}
+
 
 #[derive(Debug)]
 pub struct BedrockService {
     bedrock_client: Client,
     chat_model_id: String,
-    image_model_id: String,
     system_prmopt: SystemContentBlock,
     conversation: Vec<Message>,
+    tools: ToolRegistry,
+    permissions: PermissionManager,
     tool_config: ToolConfiguration,
-    terminal: TerminalService
+    terminal: TerminalService,
+    max_tool_iterations: usize,
+    tool_concurrency: usize,
+    python_kernel: Option<Arc<PythonKernel>>,
+    tool_context: ToolContext,
+    // images sent with the next message
+    attachments: Vec<(PathBuf, ContentBlock)>,
+    // the saved copy of the conversation
+    session: Session,
 }
 
 // public impl
 impl BedrockService {
-    pub fn new(client: &Client) -> Result<Self> {
-        let system_prmopt = SystemContentBlock::Text(get_system_prompt());
-
-        let generate_image_tool = Tool::ToolSpec(
-            ToolSpecification::builder()
-                .name(GENERATE_IMAGE_NAME)
-                .description(GENERATE_IMAGE_DESCRIPTION)
-                .input_schema(ToolInputSchema::Json(generate_image_schema()?))
-                .build()?
-        );
-
-        let read_file_tool = Tool::ToolSpec(
-            ToolSpecification::builder()
-                .name(READ_FILE_NAME)
-                .description(READ_FILE_DESCRIPTION)
-                .input_schema(ToolInputSchema::Json(read_file_schema()?))
-                .build()?
-        );
-
-        let run_python_tool = Tool::ToolSpec(
-            ToolSpecification::builder()
-                .name(RUN_PYTHON_NAME)
-                .description(RUN_PYTHON_DESCRIPTION)
-                .input_schema(ToolInputSchema::Json(run_python_schema()?))
-                .build()?
-        );
-
-        let tool_configuration = ToolConfiguration::builder()
-            .set_tools(Some(vec![read_file_tool, generate_image_tool, run_python_tool]))
-            // .tool_choice(ToolChoice::Tool(SpecificToolChoice::builder().name(CREATE_FILE_NAME).build()?))
-            .build()?;
+    pub fn new(client: &Client, config: &Config, tools: ToolRegistry) -> Result<Self> {
+        let system_prmopt = SystemContentBlock::Text(get_system_prompt(&tools));
+        let tool_configuration = tools.tool_configuration()?;
+        let chat_model_id = env::var(CHAT_MODEL_KEY).unwrap_or(CHAT_MODEL_ID.to_owned());
+        let conversation_id = new_conversation_id();
 
         Ok(
             Self {
                 bedrock_client: client.to_owned(),
-                chat_model_id: env::var(CHAT_MODEL_KEY).unwrap_or(CHAT_MODEL_ID.to_owned()),
-                image_model_id: env::var(IMAGE_MODEL_KEY).unwrap_or(IMAGE_MODEL_ID.to_owned()),
+                session: Session::new(&conversation_id, &chat_model_id),
+                chat_model_id,
                 system_prmopt,
                 conversation: vec![],
+                tools,
+                permissions: PermissionManager::new(config.tool_policies.clone(), config.assume_yes),
                 tool_config: tool_configuration,
-                terminal: TerminalService::new()
+                terminal: TerminalService::new(),
+                max_tool_iterations: config.max_tool_iterations,
+                tool_concurrency: config.tool_concurrency,
+                python_kernel: None,
+                tool_context: ToolContext::new(&conversation_id, config),
+                attachments: vec![],
             }
         )
     }
 
+    pub fn conversation_id(&self) -> &str {
+        &self.tool_context.conversation_id
+    }
+
+    // Continues a saved conversation with the current model, printing its text messages.
+    pub fn resume(&mut self, session: Session) -> Result<()> {
+        self.conversation = session.to_messages()?;
+        self.tool_context.conversation_id = session.id.clone();
+        self.tool_context.journal.restore(session.journal.clone());
+        self.terminal.log_info(&format!("Resuming conversation {} from {}.", session.id, session.updated_at.format("%Y-%m-%d %H:%M")))?;
+        for message in &session.messages {
+            for content in &message.content {
+                let SessionContent::Text { text } = content else {
+                    continue;
+                };
+                match message.role {
+                    Role::User => self.terminal.log_user(text)?,
+                    Role::Assistant => self.terminal.log_ai(text)?,
+                }
+            }
+        }
+        self.session = session;
+        Ok(())
+    }
+
+    // the kernel used by RUN_PYTHON, so it can be reset or restarted with commands
+    pub fn set_python_kernel(&mut self, python_kernel: Arc<PythonKernel>) {
+        self.python_kernel = Some(python_kernel);
+    }
+
+    // commands typed by the user starting with `/`
+    pub async fn run_command(&mut self, input: &str) -> Result<()> {
+        let words: Vec<&str> = input.trim().trim_start_matches('/').split_whitespace().collect();
+        match words.as_slice() {
+            ["help"] => {
+                self.terminal.log_info(COMMANDS_HELP)?;
+            },
+            ["python", action @ ("reset" | "restart")] => {
+                let Some(python_kernel) = &self.python_kernel else {
+                    self.terminal.log_error("The python kernel is not enabled. Start the app with --python-kernel to use it.")?;
+                    return Ok(());
+                };
+                if *action == "reset" {
+                    if let Err(err) = python_kernel.reset().await {
+                        self.terminal.log_error(&err.to_string())?;
+                        return Ok(());
+                    }
+                    self.terminal.log_info("Python variables and imports cleared.")?;
+                } else {
+                    python_kernel.restart().await;
+                    self.terminal.log_info("Python kernel restarted.")?;
+                }
+            },
+            ["attach"] => {
+                if self.attachments.is_empty() {
+                    self.terminal.log_info("No image attached.")?;
+                }
+                for (path, _) in &self.attachments {
+                    self.terminal.log_info(&format!("Attached: {}", path.display()))?;
+                }
+            },
+            ["attach", "clear"] => {
+                self.attachments.clear();
+                self.terminal.log_info("Attached images removed.")?;
+            },
+            ["attach", ..] => {
+                // the path may contain spaces
+                let path = Path::new(input.trim().trim_start_matches('/').trim_start_matches("attach").trim());
+                if self.attachments.len() >= MAX_ATTACHMENTS {
+                    self.terminal.log_error(&format!("Up to {MAX_ATTACHMENTS} images can be attached to a message."))?;
+                    return Ok(());
+                }
+                match load_attachment(path) {
+                    Ok(image) => {
+                        self.attachments.push((path.to_owned(), image));
+                        self.terminal.log_info(&format!("Attached {}. It will be sent with your next message.", path.display()))?;
+                    },
+                    Err(err) => self.terminal.log_error(&format!("{:#}", err))?,
+                }
+            },
+            ["undo"] => {
+                let Some((tool_use_id, results)) = self.tool_context.journal.undo_last() else {
+                    self.terminal.log_info("No changes to undo.")?;
+                    return Ok(());
+                };
+                self.terminal.log_info(&format!("Reverted the changes of tool use {}:", tool_use_id))?;
+                for result in results {
+                    self.terminal.log_info(&format!("  {}", result))?;
+                }
+            },
+            ["artifacts"] => {
+                let entries = self.tool_context.journal.entries();
+                if entries.is_empty() {
+                    self.terminal.log_info("No files created or modified in this conversation.")?;
+                }
+                for entry in entries {
+                    let mut line = format!("{}  {:<14}  {}", entry.time.format("%H:%M:%S"), entry.change.to_string(), entry.path.display());
+                    if let Change::ModifiedFile(Some(backup)) = &entry.change {
+                        line.push_str(&format!(" (previous version: {})", backup.display()));
+                    }
+                    self.terminal.log_info(&line)?;
+                }
+            },
+            ["export", arguments @ ..] => {
+                if self.conversation.is_empty() {
+                    self.terminal.log_info("Nothing to export yet.")?;
+                    return Ok(());
+                }
+                let force = arguments.first() == Some(&"--force");
+                let arguments = if force { &arguments[1..] } else { arguments };
+                // the format can be left out, and the path may contain spaces
+                let (format, path) = match arguments.split_first().map(|(first, rest)| (first.parse::<ExportFormat>(), rest)) {
+                    Some((Ok(format), rest)) => (Some(format), rest.join(" ")),
+                    _ => (None, arguments.join(" ")),
+                };
+                let path = PathBuf::from(path);
+                let format = format.or(ExportFormat::from_path(&path)).unwrap_or_default();
+                let path = match path.as_os_str().is_empty() {
+                    true => unique_export_path(&format!("conversation-{}", self.session.id), format.extension()),
+                    false => path,
+                };
+                self.save_session()?;
+                let exported = export_session(&self.session, format)
+                    .and_then(|exported| write_export(&path, &exported, force));
+                match exported {
+                    Ok(_) => self.terminal.log_info(&format!("Exported the conversation to {} as {format}.", path.display()))?,
+                    Err(err) => self.terminal.log_error(&format!("Failed to export the conversation: {:#}", err))?,
+                }
+            },
+            ["sessions"] => {
+                let sessions = match Session::list() {
+                    Ok(sessions) => sessions,
+                    Err(err) => {
+                        self.terminal.log_error(&format!("Failed to list the saved conversations: {:#}", err))?;
+                        return Ok(());
+                    },
+                };
+                if sessions.is_empty() {
+                    self.terminal.log_info("No saved conversation.")?;
+                }
+                for session in sessions {
+                    // the current conversation is marked with `*`
+                    let current = if session.id == self.session.id { "*" } else { " " };
+                    self.terminal.log_info(&format!(
+                        "{current} {}  {}  {:>3} messages  {}",
+                        session.id,
+                        session.updated_at.format("%Y-%m-%d %H:%M"),
+                        session.messages.len(),
+                        session.title()
+                    ))?;
+                }
+            },
+            _ => {
+                self.terminal.log_error(&format!("Unknown command: {}", input.trim()))?;
+                self.terminal.log_info(COMMANDS_HELP)?;
+            },
+        }
+        Ok(())
+    }
+
     // non streaming
     pub async fn run(&mut self, input: &str) -> Result<()> {
+        if let Err(err) = self.append_user_message(input) {
+            self.terminal.log_error(&format!("{:#}", err))?;
+            return Ok(());
+        }
+
+        if let Err(err) = self.converse(false).await {
+            self.terminal.clear_line()?;
+            self.terminal.log_error(&err.root_cause().to_string())?;
+        }
+        self.save_session()?;
+        Ok(())
+    }
+
+    pub async fn run_stream(&mut self, input: &str) -> Result<()> {
+        if let Err(err) = self.append_user_message(input) {
+            self.terminal.log_error(&format!("{:#}", err))?;
+            return Ok(());
+        }
 
-        self.append_user_message(input)?;
+        if let Err(err) = self.converse(true).await {
+            self.terminal.clear_line()?;
+            self.terminal.log_error(&err.root_cause().to_string())?;
+        }
+        self.save_session()?;
+        Ok(())
+    }
 
-        let response = match self.send().await {
-            Ok(response) => response,
-            Err(err) => {
+    // A conversation that cannot be saved can still go on, so the error is only shown.
+    fn save_session(&mut self) -> Result<()> {
+        if self.conversation.is_empty() {
+            return Ok(());
+        }
+        let entries = self.tool_context.journal.entries();
+        self.session.record_artifacts(&entries);
+        self.session.record_journal(&entries);
+        let saved = self.session.update(&self.conversation, &self.chat_model_id)
+            .and_then(|_| self.session.save());
+        if let Err(err) = saved {
+            self.terminal.log_error(&format!("Failed to save the conversation: {:#}", err))?;
+        }
+        Ok(())
+    }
+
+    // Keeps sending the conversation and executing the requested tools until the model ends its turn.
+    async fn converse(&mut self, stream: bool) -> Result<()> {
+        let mut iterations = 0;
+
+        loop {
+            let requests = if stream {
+                let response = self.send_stream().await?;
                 self.terminal.clear_line()?;
-                self.terminal.log_error(&err.root_cause().to_string())?;
-                return Ok(());
-            },
-        };
-        self.terminal.clear_line()?;
-        match self.process_output(response).await {
-            Ok(_) => {},
-            Err(err) => {
+                self.process_output_stream(response).await?
+            } else {
+                let response = self.send().await?;
                 self.terminal.clear_line()?;
-                self.terminal.log_error(&err.root_cause().to_string())?;
+                self.process_output(response).await?
+            };
+
+            if requests.tool_uses.is_empty() {
                 return Ok(());
-            },
-        };
-        Ok(())
-    }
+            }
+
+            if iterations >= self.max_tool_iterations {
+                let message = format!(
+                    "Stopped after {} tool iterations without a final answer. The tool was not executed. Ask to continue, or raise the limit with --max-tool-iterations.",
+                    self.max_tool_iterations
+                );
+                let mut tool_results: Vec<ContentBlock> = vec![];
+                for tool_use in &requests.tool_uses {
+                    let result = create_tool_result_block(tool_use.tool_use_id(), &message, ToolResultStatus::Error)?;
+                    tool_results.push(ContentBlock::ToolResult(result));
+                }
+                self.append_user_content(tool_results)?;
+                self.terminal.log_error(&message)?;
+                return Ok(());
+            }
+            iterations += 1;
 
+            let tool_results = self.use_tools(&requests).await?;
+            self.append_user_content(tool_results)?;
+        }
+    }
 
     async fn send(&mut self) -> Result<ConverseOutput> {
 
@@ -164,8 +506,9 @@ impl BedrockService {
         Ok(response)
     }
 
-    async fn process_output(&mut self, output: ConverseOutput) -> Result<()> {
-        let output = output.output().context("Error getting output")?;
+    // returns the tool uses requested by the model
+    async fn process_output(&mut self, response: ConverseOutput) -> Result<ToolRequests> {
+        let output = response.output().context("Error getting output")?;
         let message = match output.as_message() {
             Ok(message) => message,
             Err(_) => {
@@ -176,7 +519,7 @@ impl BedrockService {
 
         let contents = message.content();
         // println!("contents count: {}", contents.len());
-        let mut tool_results: Vec<ContentBlock> = vec![];
+        let mut requests = ToolRequests::default();
 
         for content in contents {
             match content {
@@ -189,74 +532,19 @@ impl BedrockService {
                     let name = tool_use.name();
                     let input = tool_use.input();
                     self.terminal.log_tool(name, input)?;
-                    let result = self.use_tool(tool_use).await?;
-                    tool_results.push(ContentBlock::ToolResult(result))
+                    if !input.is_object() {
+                        requests.rejected.insert(tool_use.tool_use_id().to_owned(), "The tool input is not a JSON object, so the tool was not executed.".to_owned());
+                    }
+                    requests.tool_uses.push(tool_use.clone())
                 },
                 _ => {
-                    break
+                    continue
                 },
             }
         }
 
-        if !tool_results.is_empty() {
-            let tool_results_messgae = Message::builder()
-                .role(User)
-                .set_content(Some(tool_results))
-                .build()?;
-            self.conversation.push(tool_results_messgae);
-
-            let tool_response = self.send().await?;
-            let tool_response_output = tool_response.output().context("Error getting output")?;
-            // println!("tool_response_output: {:?}", tool_response_output);
-            let tool_response_message = match tool_response_output.as_message() {
-                Ok(message) => message,
-                Err(_) => {
-                    bail!("Output is not a message")
-                },
-            };
-            self.conversation.push(tool_response_message.clone());
-            let tool_response_contents = tool_response_message.content();
-
-            for tool_response_content in tool_response_contents {
-                match tool_response_content {
-                    ContentBlock::Text(text_content) => {
-                        self.terminal.log_ai(text_content)?;
-                        // println!("\x1b[0;90mThe model's response:\x1b[0m\n{text_content}");
-                    },
-                    _ => {
-                        break
-                    },
-                }
-            }
-        }
-
-        Ok(())
-    }
-
-
-    pub async fn run_stream(&mut self, input: &str) -> Result<()> {
-
-        self.append_user_message(input)?;
-
-        let response = match self.send_stream().await {
-            Ok(response) => response,
-            Err(err) => {
-                self.terminal.clear_line()?;
-                self.terminal.log_error(&err.root_cause().to_string())?;
-                return Ok(());
-            },
-        };
-        self.terminal.clear_line()?;
-        match self.process_output_stream(response).await {
-            Ok(_) => {},
-            Err(err) => {
-                self.terminal.clear_line()?;
-                self.terminal.log_error(&err.root_cause().to_string())?;
-                return Ok(());
-            },
-        };
-
-        Ok(())
+        requests.check_stop_reason(response.stop_reason());
+        Ok(requests)
     }
 
     async fn send_stream(&mut self) -> Result<aws_sdk_bedrockruntime::operation::converse_stream::ConverseStreamOutput> {
@@ -276,32 +564,38 @@ impl BedrockService {
         Ok(response)
     }
 
-    async fn process_output_stream(&mut self, output: aws_sdk_bedrockruntime::operation::converse_stream::ConverseStreamOutput) -> Result<()> {
+    // returns the tool uses requested by the model
+    async fn process_output_stream(&mut self, output: aws_sdk_bedrockruntime::operation::converse_stream::ConverseStreamOutput) -> Result<ToolRequests> {
         let mut stream = output.stream;
-        let mut tool_input = "".to_owned();
-        let mut tool_id = "".to_owned();
-        let mut tool_name = "".to_owned();
-        let mut assistant_message = "".to_owned();
+        // blocks still receiving deltas, and finished blocks in message order, keyed by content_block_index
+        let mut pending_blocks: HashMap<i32, StreamedContentBlock> = HashMap::new();
+        let mut completed_blocks: BTreeMap<i32, ContentBlock> = BTreeMap::new();
 
-        let mut tool_results: Vec<ContentBlock> = vec![];
+        let mut requests = ToolRequests::default();
 
         loop {
             let token = stream.recv().await?;
             match token {
                 Some(output) => {
-                    // self.process_stream_token(output)?
                     // println!("token: {:?}", output);
                     match output {
                         ConverseStreamOutput::ContentBlockDelta(event) => {
+                            let index = event.content_block_index;
                             let delta = event.delta.context("delta in event not found")?;
                             match delta {
                                 ContentBlockDelta::Text(text) => {
                                     self.terminal.log_ai_inline(&text)?;
-                                    assistant_message = format!("{}{}", assistant_message, text)
+                                    let block = pending_blocks.entry(index)
+                                        .or_insert(StreamedContentBlock::Text("".to_owned()));
+                                    if let StreamedContentBlock::Text(assistant_message) = block {
+                                        assistant_message.push_str(&text);
+                                    }
                                 },
                                 ContentBlockDelta::ToolUse(tool) => {
-                                    self.terminal.log_info_inline(&tool.input())?;
-                                    tool_input = format!("{}{}", tool_input, tool.input())
+                                    self.terminal.log_info_inline(tool.input())?;
+                                    if let Some(StreamedContentBlock::ToolUse { input, .. }) = pending_blocks.get_mut(&index) {
+                                        input.push_str(tool.input());
+                                    }
                                 },
                                 _ => {
                                     continue;
@@ -310,23 +604,22 @@ impl BedrockService {
 
                         },
                         ConverseStreamOutput::ContentBlockStart(event) => {
-                            match event.start {
-                                Some(start) => {
-                                    match start {
-                                        aws_sdk_bedrockruntime::types::ContentBlockStart::ToolUse(tool_use) => {
-                                            tool_id = tool_use.tool_use_id;
-                                            tool_name = tool_use.name;
-                                            self.terminal.log_info(&format!("\n\rTool used: {tool_name}"))?;
-                                            self.terminal.log_info_inline("\rTool Input: ")?;
-                                        },
-                                        _ => {
-                                            continue;
-                                        },
-                                    }
-                                },
-                                None => {
-                                    continue;
-                                },
+                            if let Some(aws_sdk_bedrockruntime::types::ContentBlockStart::ToolUse(tool_use)) = event.start {
+                                self.terminal.log_info(&format!("\n\rTool used: {}", tool_use.name))?;
+                                self.terminal.log_info_inline("\rTool Input: ")?;
+                                pending_blocks.insert(event.content_block_index, StreamedContentBlock::ToolUse {
+                                    tool_use_id: tool_use.tool_use_id,
+                                    name: tool_use.name,
+                                    input: "".to_owned()
+                                });
+                            }
+                        },
+                        ConverseStreamOutput::ContentBlockStop(event) => {
+                            let index = event.content_block_index;
+                            if let Some(block) = pending_blocks.remove(&index) {
+                                if let Some(content) = block.into_content_block(&mut requests.rejected)? {
+                                    completed_blocks.insert(index, content);
+                                }
                             }
                         },
                         ConverseStreamOutput::MessageStart(_) => {
@@ -335,47 +628,28 @@ impl BedrockService {
                         ConverseStreamOutput::MessageStop(event) => {
                             self.terminal.log_info("\r")?;
 
-                            if event.stop_reason == StopReason::ToolUse {
-
-                                let tool_input = match serde_json::from_str::<Value>(&tool_input)  {
-                                    Ok(value) => value.to_document(),
-                                    Err(_) => Document::String(tool_input.clone()),
-                                };
-
-                                let tool_use_block = ToolUseBlock::builder()
-                                    .name(tool_name.clone())
-                                    .input(tool_input)
-                                    .tool_use_id(tool_id.clone())
-                                    .build()?;
-
-                                let mut message_buider = Message::builder()
-                                    .role(Assistant);
-
-                                if !assistant_message.is_empty() {
-                                    message_buider = message_buider.content(ContentBlock::Text(assistant_message.clone()))
+                            // blocks without a stop event are kept rather than dropped
+                            for (index, block) in pending_blocks.drain() {
+                                if let Some(content) = block.into_content_block(&mut requests.rejected)? {
+                                    completed_blocks.insert(index, content);
                                 }
-                                message_buider = message_buider
-                                    .content(ContentBlock::ToolUse(tool_use_block.clone()));
-
-                                let message = message_buider.build()?;
-
-                                self.conversation.push(message.clone());
+                            }
 
-                                let result = self.use_tool(&tool_use_block).await?;
-                                tool_results.push(ContentBlock::ToolResult(result))
+                            let contents: Vec<ContentBlock> = std::mem::take(&mut completed_blocks).into_values().collect();
+                            for content in &contents {
+                                if let ContentBlock::ToolUse(tool_use) = content {
+                                    requests.tool_uses.push(tool_use.clone());
+                                }
+                            }
+                            requests.check_stop_reason(&event.stop_reason);
 
-                            } else {
+                            if !contents.is_empty() {
                                 let message = Message::builder()
                                     .role(Assistant)
-                                    .content(ContentBlock::Text(assistant_message.clone()))
+                                    .set_content(Some(contents))
                                     .build()?;
-                                self.conversation.push(message.clone());
+                                self.conversation.push(message);
                             }
-
-                            tool_id = "".to_string();
-                            tool_input = "".to_string();
-                            tool_name = "".to_string();
-                            assistant_message = "".to_string();
                         }
                         _ => {
                             continue;
@@ -386,66 +660,51 @@ impl BedrockService {
             }
         }
 
-        if !tool_results.is_empty() {
-            let tool_results_messgae = Message::builder()
-                .role(User)
-                .set_content(Some(tool_results.clone()))
-                .build()?;
-            self.conversation.push(tool_results_messgae);
-
-            let tool_response = self.send_stream().await?;
-            let mut tool_stream = tool_response.stream;
-            let mut tool_assistant_message = "".to_owned();
-
-            loop {
-                let token = tool_stream.recv().await?;
-                match token {
-                    Some(output) => {
-                        match output {
-                            ConverseStreamOutput::ContentBlockDelta(event) => {
-                                let delta = event.delta.context("delta in event not found")?;
-                                match delta {
-                                    ContentBlockDelta::Text(text) => {
-                                        self.terminal.log_ai_inline(&text)?;
-                                        tool_assistant_message = format!("{}{}", tool_assistant_message, text)
-                                    },
-                                    _ => {
-                                        continue;
-                                    },
-                                }
+        Ok(requests)
+    }
 
-                            },
-                            ConverseStreamOutput::MessageStart(_) => {
-                                self.terminal.log_info("AI:\r")?;
-                            }
-                            ConverseStreamOutput::MessageStop(event) => {
-                                self.terminal.log_info("\r")?;
-                                if event.stop_reason == StopReason::EndTurn {
-                                    let message = Message::builder()
-                                        .role(Assistant)
-                                        .content(ContentBlock::Text(tool_assistant_message.clone()))
-                                        .build()?;
-                                    self.conversation.push(message.clone());
-                                }
-                                tool_assistant_message = "".to_string();
-                            }
-                            _ => {
-                                continue;
-                            },
-                        };
-                    },
-                    None => break,
-                }
-            }
+    // The text with the images mentioned in it and the attached images, which are sent before the text.
+    // Nothing is sent if an image cannot be read.
+    fn append_user_message(&mut self, input: &str) -> Result<()> {
+        let mut content: Vec<ContentBlock> = vec![];
+        let mut names: Vec<String> = vec![];
+        for path in mentioned_images(input) {
+            content.push(load_attachment(&path)?);
+            names.push(path.display().to_string());
+        }
+        if content.len() + self.attachments.len() > MAX_ATTACHMENTS {
+            bail!("Up to {MAX_ATTACHMENTS} images can be attached to a message.")
+        }
+        for (path, image) in mem::take(&mut self.attachments) {
+            content.push(image);
+            names.push(path.display().to_string());
+        }
+        if !names.is_empty() {
+            self.terminal.log_info(&format!("Images sent: {}", names.join(", ")))?;
         }
 
-        Ok(())
+        content.push(ContentBlock::Text(input.to_owned()));
+        self.append_user_content(content)
     }
 
-    fn append_user_message(&mut self, input: &str) -> Result<()> {
+    // Converse rejects consecutive user messages, so content is merged into a trailing user message,
+    // for example tool results left behind by an interrupted or capped turn.
+    fn append_user_content(&mut self, content: Vec<ContentBlock>) -> Result<()> {
+        if let Some(last_message) = self.conversation.last_mut() {
+            if *last_message.role() == User {
+                let mut merged_content = last_message.content().to_vec();
+                merged_content.extend(content);
+                *last_message = Message::builder()
+                    .role(User)
+                    .set_content(Some(merged_content))
+                    .build()?;
+                return Ok(());
+            }
+        }
+
         let message = Message::builder()
             .role(User)
-            .content(ContentBlock::Text(input.to_owned()))
+            .set_content(Some(content))
             .build()?;
 
         self.conversation.push(message);
@@ -453,97 +712,99 @@ impl BedrockService {
     }
 
 
-    async fn use_tool(&mut self, tool_use: &ToolUseBlock) -> Result<ToolResultBlock> {
-
-        let id = tool_use.tool_use_id();
-        let name = tool_use.name();
-        let input = tool_use.input();
-        match name {
-            READ_FILE_NAME => {
-                let tool_result = read_file(id, input)?;
-                Ok(tool_result)
+    // Runs the tools concurrently, up to the configured limit, and returns their results in request order.
+    async fn use_tools(&mut self, requests: &ToolRequests) -> Result<Vec<ContentBlock>> {
+        let tool_uses = &requests.tool_uses;
+        let total = tool_uses.len();
+        let semaphore = Arc::new(Semaphore::new(self.tool_concurrency));
+        let (sender, mut receiver) = mpsc::unbounded_channel::<ToolProgress>();
+        let mut tasks = JoinSet::new();
+        let mut results: Vec<Option<ToolResultBlock>> = vec![None; total];
+
+        // every approval is asked before any tool starts, so their output does not mix with the prompts
+        let mut approved = vec![];
+        for (index, tool_use) in tool_uses.iter().enumerate() {
+            if let Some(reason) = requests.rejected.get(tool_use.tool_use_id()) {
+                self.terminal.log_tool_status(index + 1, total, tool_use.name(), "not executed")?;
+                results[index] = Some(create_tool_result_block(tool_use.tool_use_id(), reason, ToolResultStatus::Error)?);
+                continue;
             }
-            GENERATE_IMAGE_NAME => {
-                let generate_image_result = self.generate_image_from_prompt(input).await;
-                if generate_image_result.is_err() {
-                    let message = &generate_image_result.err().unwrap().to_string();
-                    let tool_result = create_tool_result_block(id, message , ToolResultStatus::Error)?;
-                    return Ok(tool_result)
+            let tool = self.tools.get(tool_use.name());
+            if let Some(tool) = &tool {
+                if let Approval::Denied(reason) = self.permissions.check(tool.as_ref(), tool_use.input(), &mut self.terminal)? {
+                    self.terminal.log_tool_status(index + 1, total, tool_use.name(), "denied")?;
+                    results[index] = Some(create_tool_result_block(tool_use.tool_use_id(), &reason, ToolResultStatus::Error)?);
+                    continue;
                 }
-
-                let tool_result = save_generated_image(id, input, generate_image_result.unwrap())?;
-                Ok(tool_result)
-            }
-            RUN_PYTHON_NAME => {
-                let tool_result = run_python(id, input)?;
-                Ok(tool_result)
-            }
-            _ => {
-                bail!("The requested tool with name {} does not exist", name)
             }
+            approved.push((index, tool));
         }
-    }
-
-
-    // return an array of base64 image string
-    async fn generate_image_from_prompt(&mut self, input: &Document) -> Result<Vec<String>> {
-        let input_object = input.as_object().context("failed to convert input to object.")?;
-        let prompt = input_object.get("prompt")
-            .context("prompt is not provided.")?
-            .as_string().context("prompt is not string")?;
-
-        let _ = input_object.get("path")
-            .context("path is not provided.")?
-            .as_string().context("path is not string")?;
-
-        let number_of_images = match input_object.get("numberOfImages").unwrap_or(&Document::Null).as_number() {
-            Some(count) => count.to_f32_lossy() as u8,
-            None => 1,
-        };
-        let quality = match input_object.get("quality").unwrap_or(&Document::Null).as_string() {
-            Some(q) => serde_json::from_str(q).unwrap_or(IamgeQuality::Standard),
-            None => IamgeQuality::Standard,
-        };
-
-        let height = match input_object.get("height").unwrap_or(&Document::Null).as_number() {
-            Some(height) => height.to_f32_lossy() as u128,
-            None => DEFAULT_HEIGHT,
-        };
-
-        let width = match input_object.get("width").unwrap_or(&Document::Null).as_number() {
-            Some(width) => width.to_f32_lossy() as u128,
-            None => DEFAULT_WIDTH,
-        };
-
-        let image_config = ImageGenerationConfig {
-            number_of_images: Some(number_of_images),
-            quality: Some(quality),
-            height: Some(height),
-            width: Some(width),
-        };
-        let parameters: ImageGeneratorParameter = ImageGeneratorParameter::new_generate_image_params(
-            prompt,
-            Some(image_config));
-        // println!("{:?}", parameters);
 
-        let parameter_string = serde_json::to_string(&parameters)?;
-
-        let builder = self.bedrock_client
-            .invoke_model()
-            .model_id(&self.image_model_id)
-            .content_type("application/json")
-            .body(Blob::new(parameter_string.as_bytes()));
-
-        let response = builder
-            .send()
-            .await?;
-        let body = response.body().clone().into_inner();
-        let body_string = str::from_utf8(&body)?;
-        let body_value: ImageGeneratorResponse = serde_json::from_str(body_string)?;
-        let base64_image_array = body_value.images;
+        let spawned = approved.len();
+        for (index, tool) in approved {
+            let semaphore = semaphore.clone();
+            let sender = sender.clone();
+            let tool_use = tool_uses[index].clone();
+            let context = self.tool_context.clone();
+
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let _ = sender.send(ToolProgress::Started(index));
+                let start = Instant::now();
+                let result = match tool {
+                    Some(tool) => tool.execute(tool_use.tool_use_id(), tool_use.input(), &context).await,
+                    None => Err(anyhow!("The requested tool with name {} does not exist", tool_use.name())),
+                };
+                let _ = sender.send(ToolProgress::Finished(index, result, start.elapsed()));
+            });
+        }
+        drop(sender);
+
+        let mut running = 0;
+        let mut finished = 0;
+        while let Some(progress) = receiver.recv().await {
+            match progress {
+                ToolProgress::Started(index) => {
+                    running += 1;
+                    self.terminal.log_tool_status(index + 1, total, tool_uses[index].name(), "running")?;
+                },
+                ToolProgress::Finished(index, result, elapsed) => {
+                    running -= 1;
+                    finished += 1;
+                    let tool_use = &tool_uses[index];
+                    let result = match result {
+                        Ok(result) => result,
+                        Err(err) => create_tool_result_block(tool_use.tool_use_id(), &err.to_string(), ToolResultStatus::Error)?,
+                    };
+                    let outcome = match result.status() {
+                        Some(ToolResultStatus::Error) => "failed",
+                        _ => "finished",
+                    };
+                    let status = format!(
+                        "{} in {:.1}s ({} running, {} waiting)",
+                        outcome, elapsed.as_secs_f32(), running, spawned - finished - running
+                    );
+                    self.terminal.log_tool_status(index + 1, total, tool_use.name(), &status)?;
+                    results[index] = Some(result);
+                },
+            }
+        }
 
-        // println!("Image count: {}", base64_image_array.clone().len());
-        Ok(base64_image_array)
+        let mut tool_results: Vec<ContentBlock> = vec![];
+        for (result, tool_use) in results.into_iter().zip(tool_uses) {
+            let result = match result {
+                Some(result) => result,
+                None => create_tool_result_block(tool_use.tool_use_id(), "The tool stopped unexpectedly.", ToolResultStatus::Error)?,
+            };
+            tool_results.push(ContentBlock::ToolResult(result));
+        }
+        Ok(tool_results)
     }
 
-}
\ No newline at end of file
+}
+
+// the local time the conversation started, with a random suffix, for example `20240815-093012-3fa9c1`
+fn new_conversation_id() -> String {
+    let suffix = RandomState::new().build_hasher().finish() & 0xffffff;
+    format!("{}-{:06x}", chrono::Local::now().format("%Y%m%d-%H%M%S"), suffix)
+}
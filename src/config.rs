@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use clap::{value_parser, Arg, ArgMatches, Command};
+use serde::Deserialize;
+
+use crate::model_constants::{
+    MAX_TOOL_ITERATIONS, MAX_TOOL_ITERATIONS_KEY, TOOL_CONCURRENCY, TOOL_CONCURRENCY_KEY, TOOL_POLICY_KEY,
+    PYTHON_TIMEOUT_SECS, PYTHON_TIMEOUT_KEY, PYTHON_MEMORY_LIMIT_MB, PYTHON_MEMORY_LIMIT_KEY,
+    PYTHON_CPU_LIMIT_SECS, PYTHON_CPU_LIMIT_KEY, PYTHON_DENY_NETWORK_KEY, PYTHON_KERNEL_KEY,
+    IMAGE_PREVIEW_KEY, OPEN_ARTIFACTS_KEY, CONFIG_FILE_KEY, SHELL_TIMEOUT_SECS, SHELL_TIMEOUT_KEY,
+    SHELL_ALLOW_KEY, SHELL_DENY_KEY,
+};
+use crate::export::{ExportFormat, ExportRequest};
+use crate::image_preview::ImagePreview;
+use crate::tool::context::OpenArtifacts;
+use crate::tool::permission::{parse_tool_policies, ToolPolicy};
+use crate::tool::run_shell::{parse_program_list, ShellConfig, DEFAULT_SHELL_DENY};
+use crate::tool::sandbox::SandboxConfig;
+
+const NON_STREAM_ID: &str = "non-stream";
+const MAX_TOOL_ITERATIONS_ID: &str = "max-tool-iterations";
+const TOOL_CONCURRENCY_ID: &str = "tool-concurrency";
+const YES_ID: &str = "yes";
+const TOOL_POLICY_ID: &str = "tool-policy";
+const PYTHON_TIMEOUT_ID: &str = "python-timeout";
+const PYTHON_MEMORY_LIMIT_ID: &str = "python-memory-mb";
+const PYTHON_CPU_LIMIT_ID: &str = "python-cpu-seconds";
+const PYTHON_DENY_NETWORK_ID: &str = "python-deny-network";
+const PYTHON_KERNEL_ID: &str = "python-kernel";
+const IMAGE_PREVIEW_ID: &str = "image-preview";
+const OPEN_ARTIFACTS_ID: &str = "open-artifacts";
+const CONFIG_FILE_ID: &str = "config";
+const SHELL_TIMEOUT_ID: &str = "shell-timeout";
+const SHELL_ALLOW_ID: &str = "shell-allow";
+const SHELL_DENY_ID: &str = "shell-deny";
+const RESUME_ID: &str = "resume";
+const CONTINUE_ID: &str = "continue";
+const EXPORT_ID: &str = "export";
+const EXPORT_SESSION_ID: &str = "id";
+const EXPORT_FORMAT_ID: &str = "format";
+const EXPORT_OUTPUT_ID: &str = "output";
+const EXPORT_FORCE_ID: &str = "force";
+
+// `~/.config/bedrock_assistant/config.toml` on Linux
+const CONFIG_DIRECTORY: &str = "bedrock_assistant";
+// `~/.local/share/bedrock_assistant` on Linux
+const DATA_DIRECTORY: &str = "bedrock_assistant";
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+// Settings resolved from the command line, falling back to environment variables and defaults.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub should_stream: bool,
+    pub max_tool_iterations: usize,
+    pub tool_concurrency: usize,
+    // approve every tool that would otherwise ask
+    pub assume_yes: bool,
+    pub tool_policies: HashMap<String, ToolPolicy>,
+    pub python_sandbox: SandboxConfig,
+    // keep one python process per conversation
+    pub python_kernel: bool,
+    // how generated images are shown in the terminal
+    pub image_preview: ImagePreview,
+    // which outputs are opened with the default app
+    pub open_artifacts: OpenArtifacts,
+    pub shell: ShellConfig,
+    // id of the saved conversation to resume
+    pub resume: Option<String>,
+    // resume the most recently saved conversation
+    pub continue_session: bool,
+    // set by the `export` subcommand, which exports a saved conversation instead of starting the chat
+    pub export: Option<ExportRequest>,
+}
+
+// Settings read from the config file, used when neither the command line nor an environment variable sets them.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ConfigFile {
+    open_artifacts: Option<OpenArtifacts>,
+    shell_allow: Option<Vec<String>>,
+    shell_deny: Option<Vec<String>>,
+}
+
+impl ConfigFile {
+    // A missing file is only an error when its path is given.
+    fn load(matches: &ArgMatches) -> Result<Self> {
+        let given = matches.get_one::<PathBuf>(CONFIG_FILE_ID).cloned()
+            .or(env::var(CONFIG_FILE_KEY).ok().map(PathBuf::from));
+        let path = match &given {
+            Some(path) => path.to_owned(),
+            None => match dirs::config_dir() {
+                Some(directory) => directory.join(CONFIG_DIRECTORY).join(CONFIG_FILE_NAME),
+                None => return Ok(Self::default()),
+            },
+        };
+        if given.is_none() && !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path).context(format!("failed to read the config file {}", path.display()))?;
+        toml::from_str(&content).context(format!("invalid config file {}", path.display()))
+    }
+}
+
+impl Config {
+    pub fn from_args() -> Result<Self> {
+        let matches = Self::command().get_matches();
+        Self::from_matches(&matches)
+    }
+
+    fn command() -> Command {
+        Command::new("bedrock_assistant")
+            .arg(
+                Arg::new(NON_STREAM_ID)
+                    .long(NON_STREAM_ID)
+                    .action(clap::ArgAction::SetTrue)
+            )
+            .arg(
+                Arg::new(MAX_TOOL_ITERATIONS_ID)
+                    .long(MAX_TOOL_ITERATIONS_ID)
+                    .help("Maximum number of tool round-trips for a single message.")
+                    .value_parser(value_parser!(usize))
+            )
+            .arg(
+                Arg::new(TOOL_CONCURRENCY_ID)
+                    .long(TOOL_CONCURRENCY_ID)
+                    .help("Maximum number of tools running at the same time.")
+                    .value_parser(value_parser!(usize))
+            )
+            .arg(
+                Arg::new(YES_ID)
+                    .long(YES_ID)
+                    .short('y')
+                    .help("Run tools without asking for approval. Tools with a deny policy stay disabled.")
+                    .action(clap::ArgAction::SetTrue)
+            )
+            .arg(
+                Arg::new(TOOL_POLICY_ID)
+                    .long(TOOL_POLICY_ID)
+                    .value_name("NAME=POLICY")
+                    .help("Policy for a tool: allow, ask or deny. For example RUN_PYTHON=deny. Can be repeated.")
+                    .action(clap::ArgAction::Append)
+            )
+            .arg(
+                Arg::new(PYTHON_TIMEOUT_ID)
+                    .long(PYTHON_TIMEOUT_ID)
+                    .value_name("SECONDS")
+                    .help("Wall-clock time limit for running python code.")
+                    .value_parser(value_parser!(u64))
+            )
+            .arg(
+                Arg::new(PYTHON_MEMORY_LIMIT_ID)
+                    .long(PYTHON_MEMORY_LIMIT_ID)
+                    .value_name("MB")
+                    .help("Memory limit for running python code. 0 for no limit.")
+                    .value_parser(value_parser!(u64))
+            )
+            .arg(
+                Arg::new(PYTHON_CPU_LIMIT_ID)
+                    .long(PYTHON_CPU_LIMIT_ID)
+                    .value_name("SECONDS")
+                    .help("CPU time limit for running python code. 0 for no limit.")
+                    .value_parser(value_parser!(u64))
+            )
+            .arg(
+                Arg::new(PYTHON_DENY_NETWORK_ID)
+                    .long(PYTHON_DENY_NETWORK_ID)
+                    .help("Run python code without network access. Requires Linux network namespaces.")
+                    .action(clap::ArgAction::SetTrue)
+            )
+            .arg(
+                Arg::new(PYTHON_KERNEL_ID)
+                    .long(PYTHON_KERNEL_ID)
+                    .help("Keep variables, imports and loaded data between python runs, like a notebook.")
+                    .action(clap::ArgAction::SetTrue)
+            )
+            .arg(
+                Arg::new(IMAGE_PREVIEW_ID)
+                    .long(IMAGE_PREVIEW_ID)
+                    .value_name("MODE")
+                    .help("How generated images are shown in the terminal: auto, kitty, iterm, sixel, blocks or off.")
+                    .value_parser(ImagePreview::from_str)
+            )
+            .arg(
+                Arg::new(OPEN_ARTIFACTS_ID)
+                    .long(OPEN_ARTIFACTS_ID)
+                    .value_name("POLICY")
+                    .help("Which outputs are opened with the default app: never, images-only or always.")
+                    .value_parser(OpenArtifacts::from_str)
+            )
+            .arg(
+                Arg::new(CONFIG_FILE_ID)
+                    .long(CONFIG_FILE_ID)
+                    .value_name("PATH")
+                    .help("Path of the config file.")
+                    .value_parser(value_parser!(PathBuf))
+            )
+            .arg(
+                Arg::new(SHELL_TIMEOUT_ID)
+                    .long(SHELL_TIMEOUT_ID)
+                    .value_name("SECONDS")
+                    .help("Wall-clock time limit for running shell commands.")
+                    .value_parser(value_parser!(u64))
+            )
+            .arg(
+                Arg::new(SHELL_ALLOW_ID)
+                    .long(SHELL_ALLOW_ID)
+                    .value_name("PROGRAMS")
+                    .help("Only allow these programs in shell commands, for example git,ls,du. Can be repeated.")
+                    .action(clap::ArgAction::Append)
+            )
+            .arg(
+                Arg::new(SHELL_DENY_ID)
+                    .long(SHELL_DENY_ID)
+                    .value_name("PROGRAMS")
+                    .help("Never allow these programs in shell commands, replacing the default list. Can be repeated.")
+                    .action(clap::ArgAction::Append)
+            )
+            .arg(
+                Arg::new(RESUME_ID)
+                    .long(RESUME_ID)
+                    .value_name("ID")
+                    .help("Resume a saved conversation. Use /sessions to list them.")
+                    .conflicts_with(CONTINUE_ID)
+            )
+            .arg(
+                Arg::new(CONTINUE_ID)
+                    .long(CONTINUE_ID)
+                    .short('c')
+                    .help("Resume the most recent conversation.")
+                    .action(clap::ArgAction::SetTrue)
+            )
+            .subcommand(
+                Command::new(EXPORT_ID)
+                    .about("Export a saved conversation to Markdown, HTML or JSON.")
+                    .arg(
+                        Arg::new(EXPORT_SESSION_ID)
+                            .value_name("ID")
+                            .help("Id of the conversation. Default to the most recent one.")
+                    )
+                    .arg(
+                        Arg::new(EXPORT_FORMAT_ID)
+                            .long(EXPORT_FORMAT_ID)
+                            .short('f')
+                            .value_name("FORMAT")
+                            .help("markdown, html or json. Default to the extension of the output file, or markdown.")
+                            .value_parser(ExportFormat::from_str)
+                    )
+                    .arg(
+                        Arg::new(EXPORT_OUTPUT_ID)
+                            .long(EXPORT_OUTPUT_ID)
+                            .short('o')
+                            .value_name("PATH")
+                            .help("File to write. Default to the standard output.")
+                            .value_parser(value_parser!(PathBuf))
+                    )
+                    .arg(
+                        Arg::new(EXPORT_FORCE_ID)
+                            .long(EXPORT_FORCE_ID)
+                            .help("Overwrite the output file if it exists.")
+                            .action(clap::ArgAction::SetTrue)
+                    )
+            )
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Result<Self> {
+        let config_file = ConfigFile::load(matches)?;
+        let max_tool_iterations = arg_or_env(matches, MAX_TOOL_ITERATIONS_ID, MAX_TOOL_ITERATIONS_KEY, MAX_TOOL_ITERATIONS)?;
+        let tool_concurrency = arg_or_env(matches, TOOL_CONCURRENCY_ID, TOOL_CONCURRENCY_KEY, TOOL_CONCURRENCY)?;
+
+        let mut tool_policies = match env::var(TOOL_POLICY_KEY) {
+            Ok(policies) => parse_tool_policies(&policies)?,
+            Err(_) => HashMap::new(),
+        };
+        if let Some(policies) = matches.get_many::<String>(TOOL_POLICY_ID) {
+            for policy in policies {
+                tool_policies.extend(parse_tool_policies(policy)?);
+            }
+        }
+
+        Ok(
+            Self {
+                should_stream: !matches.get_flag(NON_STREAM_ID),
+                max_tool_iterations,
+                tool_concurrency: tool_concurrency.max(1),
+                assume_yes: matches.get_flag(YES_ID),
+                tool_policies,
+                python_sandbox: SandboxConfig {
+                    timeout: Duration::from_secs(arg_or_env(matches, PYTHON_TIMEOUT_ID, PYTHON_TIMEOUT_KEY, PYTHON_TIMEOUT_SECS)?),
+                    memory_limit_mb: arg_or_env(matches, PYTHON_MEMORY_LIMIT_ID, PYTHON_MEMORY_LIMIT_KEY, PYTHON_MEMORY_LIMIT_MB)?,
+                    cpu_limit_secs: arg_or_env(matches, PYTHON_CPU_LIMIT_ID, PYTHON_CPU_LIMIT_KEY, PYTHON_CPU_LIMIT_SECS)?,
+                    deny_network: flag_or_env(matches, PYTHON_DENY_NETWORK_ID, PYTHON_DENY_NETWORK_KEY),
+                },
+                python_kernel: flag_or_env(matches, PYTHON_KERNEL_ID, PYTHON_KERNEL_KEY),
+                image_preview: arg_or_env(matches, IMAGE_PREVIEW_ID, IMAGE_PREVIEW_KEY, ImagePreview::Auto)?,
+                open_artifacts: arg_or_env(matches, OPEN_ARTIFACTS_ID, OPEN_ARTIFACTS_KEY, config_file.open_artifacts.unwrap_or_default())?,
+                shell: ShellConfig {
+                    timeout: Duration::from_secs(arg_or_env(matches, SHELL_TIMEOUT_ID, SHELL_TIMEOUT_KEY, SHELL_TIMEOUT_SECS)?),
+                    allow: list_or_env(matches, SHELL_ALLOW_ID, SHELL_ALLOW_KEY, config_file.shell_allow.unwrap_or_default()),
+                    deny: list_or_env(
+                        matches, SHELL_DENY_ID, SHELL_DENY_KEY,
+                        config_file.shell_deny.unwrap_or(DEFAULT_SHELL_DENY.map(str::to_owned).to_vec())
+                    ),
+                },
+                resume: matches.get_one::<String>(RESUME_ID).cloned(),
+                continue_session: matches.get_flag(CONTINUE_ID),
+                export: matches.subcommand_matches(EXPORT_ID).map(|matches| ExportRequest {
+                    id: matches.get_one::<String>(EXPORT_SESSION_ID).cloned(),
+                    format: matches.get_one::<ExportFormat>(EXPORT_FORMAT_ID).copied(),
+                    output: matches.get_one::<PathBuf>(EXPORT_OUTPUT_ID).cloned(),
+                    force: matches.get_flag(EXPORT_FORCE_ID),
+                }),
+            }
+        )
+    }
+}
+
+// where backups and conversations are saved
+pub fn data_directory() -> PathBuf {
+    dirs::data_local_dir().unwrap_or(env::temp_dir()).join(DATA_DIRECTORY)
+}
+
+fn arg_or_env<T>(matches: &ArgMatches, id: &str, key: &str, default: T) -> Result<T>
+where
+    T: FromStr + Clone + Send + Sync + 'static,
+{
+    if let Some(value) = matches.get_one::<T>(id) {
+        return Ok(value.clone());
+    }
+    match env::var(key) {
+        Ok(value) => value.parse().ok().context(format!("{key} is not a valid value.")),
+        Err(_) => Ok(default),
+    }
+}
+
+// comma separated values, from all the occurrences of the argument or from the environment variable
+fn list_or_env(matches: &ArgMatches, id: &str, key: &str, default: Vec<String>) -> Vec<String> {
+    if let Some(values) = matches.get_many::<String>(id) {
+        return values.flat_map(|value| parse_program_list(value)).collect();
+    }
+    match env::var(key) {
+        Ok(value) => parse_program_list(&value),
+        Err(_) => default,
+    }
+}
+
+// a flag is also turned on by setting its environment variable to `1` or `true`
+fn flag_or_env(matches: &ArgMatches, id: &str, key: &str) -> bool {
+    if matches.get_flag(id) {
+        return true;
+    }
+    match env::var(key) {
+        Ok(value) => matches!(value.to_lowercase().as_str(), "1" | "true"),
+        Err(_) => false,
+    }
+}
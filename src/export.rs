@@ -0,0 +1,435 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use base64::prelude::*;
+
+use crate::session::{Role, Session, SessionArtifact, SessionContent};
+use crate::tool::image_content::is_image_path;
+use crate::tool::list_directory::format_size;
+
+
+// generated images larger than this are linked instead of embedded in HTML
+const MAX_EMBEDDED_IMAGE_BYTES: u64 = 10_000_000;
+
+const HTML_STYLE: &str = "
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; max-width: 900px; margin: 2em auto; padding: 0 1em; color: #1f2328; }
+header { border-bottom: 1px solid #d0d7de; margin-bottom: 1.5em; }
+section { margin-bottom: 1.5em; }
+h2 { font-size: 1.1em; margin-bottom: 0.3em; }
+h2 time { font-weight: normal; color: #656d76; font-size: 0.9em; margin-left: 0.5em; }
+.user h2 { color: #0969da; }
+.assistant h2 { color: #8250df; }
+.text { white-space: pre-wrap; line-height: 1.5; }
+pre { background: #f6f8fa; padding: 0.8em; overflow-x: auto; border-radius: 6px; }
+details { border: 1px solid #d0d7de; border-radius: 6px; padding: 0.5em 0.8em; margin: 0.5em 0; }
+details.error { border-color: #cf222e; }
+summary { cursor: pointer; font-family: monospace; }
+img { max-width: 100%; border-radius: 6px; margin: 0.5em 0; }
+.note { color: #656d76; font-style: italic; }
+";
+
+// What a conversation is exported to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Markdown,
+    // a single file, with the images embedded
+    Html,
+    // the saved conversation itself
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "markdown" | "md" => Ok(ExportFormat::Markdown),
+            "html" | "htm" => Ok(ExportFormat::Html),
+            "json" => Ok(ExportFormat::Json),
+            other => bail!("Unknown export format: {other}. Possible value: markdown, html, json."),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ExportFormat::Markdown => "markdown",
+            ExportFormat::Html => "html",
+            ExportFormat::Json => "json",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Html => "html",
+            ExportFormat::Json => "json",
+        }
+    }
+
+    // the format matching the extension of the output file
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+// The `export` subcommand.
+#[derive(Clone, Debug)]
+pub struct ExportRequest {
+    // the most recent conversation when not given
+    pub id: Option<String>,
+    pub format: Option<ExportFormat>,
+    // the standard output when not given
+    pub output: Option<PathBuf>,
+    // overwrite the output file if it exists
+    pub force: bool,
+}
+
+impl ExportRequest {
+    pub fn run(&self) -> Result<()> {
+        let session = match &self.id {
+            Some(id) => Session::load(id)?,
+            None => Session::latest()?,
+        };
+        let format = self.format
+            .or(self.output.as_deref().and_then(ExportFormat::from_path))
+            .unwrap_or_default();
+        let exported = export_session(&session, format)?;
+        match &self.output {
+            Some(path) => {
+                write_export(path, &exported, self.force)?;
+                eprintln!("Exported conversation {} to {}.", session.id, path.display());
+            },
+            None => io::stdout().write_all(exported.as_bytes())?,
+        }
+        Ok(())
+    }
+}
+
+// Writes the export, failing if the file exists unless `force` is set.
+pub fn write_export(path: &Path, exported: &str, force: bool) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true);
+    match force {
+        true => options.create(true).truncate(true),
+        false => options.create_new(true),
+    };
+    let mut file = match options.open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => bail!("{} already exists. Use --force to overwrite it.", path.display()),
+        Err(err) => return Err(err).context(format!("failed to write {}", path.display())),
+    };
+    file.write_all(exported.as_bytes()).context(format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+// `<stem>.<extension>` in the current folder, with `-2`, `-3`... added if the file already exists
+pub fn unique_export_path(stem: &str, extension: &str) -> PathBuf {
+    let mut path = PathBuf::from(format!("{stem}.{extension}"));
+    let mut count = 2;
+    while path.exists() {
+        path = PathBuf::from(format!("{stem}-{count}.{extension}"));
+        count += 1;
+    }
+    path
+}
+
+pub fn export_session(session: &Session, format: ExportFormat) -> Result<String> {
+    let exported = match format {
+        ExportFormat::Markdown => to_markdown(session),
+        ExportFormat::Html => to_html(session),
+        ExportFormat::Json => serde_json::to_string_pretty(session)?,
+    };
+    Ok(exported)
+}
+
+// the name of the tool of each tool use id, so results can say which tool they come from
+fn tool_names(session: &Session) -> HashMap<&str, &str> {
+    session.messages.iter()
+        .flat_map(|message| &message.content)
+        .filter_map(|content| match content {
+            SessionContent::ToolUse { tool_use_id, name, .. } => Some((tool_use_id.as_str(), name.as_str())),
+            _ => None,
+        })
+        .collect()
+}
+
+// messages sent by the app with the results of the tools are not from the user
+fn message_title(role: Role, content: &[SessionContent]) -> &'static str {
+    let only_results = content.iter().all(|content| matches!(content, SessionContent::ToolResult { .. }));
+    match role {
+        Role::User if only_results && !content.is_empty() => "Tool results",
+        Role::User => "You",
+        Role::Assistant => "Assistant",
+    }
+}
+
+fn decoded_size(data: &str) -> String {
+    format_size(BASE64_STANDARD.decode(data).map(|bytes| bytes.len()).unwrap_or(0) as u64)
+}
+
+fn pretty_json(value: &serde_json::Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or(value.to_string())
+}
+
+// a code fence longer than any run of backticks in the text
+fn markdown_fence(text: &str, language: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{fence}{language}\n{}\n{fence}\n\n", text.trim_end())
+}
+
+fn to_markdown(session: &Session) -> String {
+    let tool_names = tool_names(session);
+    let mut markdown = format!("# {}\n\n", session.title());
+    markdown.push_str(&format!("- Conversation: `{}`\n", session.id));
+    markdown.push_str(&format!("- Model: `{}`\n", session.model_id));
+    markdown.push_str(&format!("- Started: {}\n", session.created_at.format("%Y-%m-%d %H:%M")));
+    markdown.push_str(&format!("- Updated: {}\n\n", session.updated_at.format("%Y-%m-%d %H:%M")));
+
+    for message in &session.messages {
+        markdown.push_str(&format!(
+            "## {} ({})\n\n",
+            message_title(message.role, &message.content),
+            message.timestamp.format("%Y-%m-%d %H:%M:%S")
+        ));
+        for content in &message.content {
+            match content {
+                SessionContent::ToolUse { tool_use_id, name, input } => {
+                    markdown.push_str(&format!("**Tool call** `{name}` (`{tool_use_id}`)\n\n"));
+                    markdown.push_str(&markdown_fence(&pretty_json(input), "json"));
+                },
+                SessionContent::ToolResult { tool_use_id, status, content } => {
+                    let name = tool_names.get(tool_use_id.as_str()).copied().unwrap_or("unknown tool");
+                    let status = status.as_deref().unwrap_or("success");
+                    markdown.push_str(&format!("**Result of** `{name}` (`{tool_use_id}`, {status})\n\n"));
+                    for content in content {
+                        match content {
+                            SessionContent::Text { text } => markdown.push_str(&markdown_fence(text, "")),
+                            content => markdown.push_str(&markdown_content(content)),
+                        }
+                    }
+                    markdown.push_str(&markdown_artifacts(&session.artifacts_of(tool_use_id)));
+                },
+                content => markdown.push_str(&markdown_content(content)),
+            }
+        }
+    }
+    markdown
+}
+
+fn markdown_content(content: &SessionContent) -> String {
+    match content {
+        SessionContent::Text { text } => format!("{}\n\n", text.trim_end()),
+        SessionContent::Json { value } => markdown_fence(&pretty_json(value), "json"),
+        SessionContent::Image { format, data } => format!("*Image ({format}, {})*\n\n", decoded_size(data)),
+        SessionContent::Document { name, format, data } => format!("*Document: {name} ({format}, {})*\n\n", decoded_size(data)),
+        SessionContent::ToolUse { .. } | SessionContent::ToolResult { .. } => String::new(),
+    }
+}
+
+fn markdown_artifacts(artifacts: &[&SessionArtifact]) -> String {
+    if artifacts.is_empty() {
+        return String::new();
+    }
+    let mut markdown = "Files:\n\n".to_owned();
+    for artifact in artifacts {
+        markdown.push_str(&format!("- {}: [{}](<{}>)\n", artifact.change, artifact.path.display(), encode_path(&artifact.path)));
+    }
+    markdown.push('\n');
+    for artifact in artifacts.iter().filter(|artifact| is_image_path(&artifact.path)) {
+        markdown.push_str(&format!("![{}](<{}>)\n\n", file_name(&artifact.path), encode_path(&artifact.path)));
+    }
+    markdown
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or(path.display().to_string())
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn file_url(path: &Path) -> String {
+    let path = encode_path(path);
+    match path.starts_with('/') {
+        true => format!("file://{path}"),
+        false => format!("file:///{path}"),
+    }
+}
+
+// The path with `/` separators, and the characters that have a meaning in URLs, such as spaces, `#` or `%`, percent-encoded.
+fn encode_path(path: &Path) -> String {
+    let path = path.display().to_string().replace('\\', "/");
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn image_mime_type(format: &str) -> String {
+    match format.to_lowercase().as_str() {
+        "jpg" | "jpeg" => "image/jpeg".to_owned(),
+        "svg" => "image/svg+xml".to_owned(),
+        other => format!("image/{other}"),
+    }
+}
+
+fn to_html(session: &Session) -> String {
+    let tool_names = tool_names(session);
+    let title = escape_html(&session.title());
+    let mut body = format!(
+        "<header>\n<h1>{title}</h1>\n<p>Conversation <code>{}</code> with <code>{}</code>, from {} to {}.</p>\n</header>\n",
+        escape_html(&session.id),
+        escape_html(&session.model_id),
+        session.created_at.format("%Y-%m-%d %H:%M"),
+        session.updated_at.format("%Y-%m-%d %H:%M")
+    );
+
+    for message in &session.messages {
+        let class = match message.role {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        };
+        body.push_str(&format!(
+            "<section class=\"{class}\">\n<h2>{}<time>{}</time></h2>\n",
+            message_title(message.role, &message.content),
+            message.timestamp.format("%Y-%m-%d %H:%M:%S")
+        ));
+        for content in &message.content {
+            match content {
+                SessionContent::ToolUse { tool_use_id, name, input } => {
+                    body.push_str(&format!(
+                        "<details open>\n<summary>Tool call {} ({})</summary>\n<pre>{}</pre>\n</details>\n",
+                        escape_html(name),
+                        escape_html(tool_use_id),
+                        escape_html(&pretty_json(input))
+                    ));
+                },
+                SessionContent::ToolResult { tool_use_id, status, content } => {
+                    let name = tool_names.get(tool_use_id.as_str()).copied().unwrap_or("unknown tool");
+                    let status = status.as_deref().unwrap_or("success");
+                    let class = if status == "error" { " class=\"error\"" } else { "" };
+                    body.push_str(&format!(
+                        "<details{class} open>\n<summary>Result of {} ({}, {})</summary>\n",
+                        escape_html(name),
+                        escape_html(tool_use_id),
+                        escape_html(status)
+                    ));
+                    for content in content {
+                        body.push_str(&html_content(content, true));
+                    }
+                    body.push_str(&html_artifacts(&session.artifacts_of(tool_use_id)));
+                    body.push_str("</details>\n");
+                },
+                content => body.push_str(&html_content(content, false)),
+            }
+        }
+        body.push_str("</section>\n");
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>{HTML_STYLE}</style>\n</head>\n<body>\n{body}</body>\n</html>\n"
+    )
+}
+
+// tool outputs are preformatted, while messages keep their line breaks and wrap
+fn html_content(content: &SessionContent, preformatted: bool) -> String {
+    match content {
+        SessionContent::Text { text } if preformatted => format!("<pre>{}</pre>\n", escape_html(text.trim_end())),
+        SessionContent::Text { text } => format!("<div class=\"text\">{}</div>\n", escape_html(text.trim())),
+        SessionContent::Json { value } => format!("<pre>{}</pre>\n", escape_html(&pretty_json(value))),
+        SessionContent::Image { format, data } => format!("<img src=\"data:{};base64,{data}\" alt=\"image\">\n", image_mime_type(format)),
+        SessionContent::Document { name, format, data } => format!(
+            "<p class=\"note\">Document: {} ({}, {})</p>\n",
+            escape_html(name),
+            escape_html(format),
+            decoded_size(data)
+        ),
+        SessionContent::ToolUse { .. } | SessionContent::ToolResult { .. } => String::new(),
+    }
+}
+
+// images that still exist are embedded, so the page can be shared on its own
+fn html_artifacts(artifacts: &[&SessionArtifact]) -> String {
+    if artifacts.is_empty() {
+        return String::new();
+    }
+    let mut html = "<p>Files:</p>\n<ul>\n".to_owned();
+    for artifact in artifacts {
+        html.push_str(&format!(
+            "<li>{}: <a href=\"{}\">{}</a></li>\n",
+            escape_html(&artifact.change),
+            escape_html(&file_url(&artifact.path)),
+            escape_html(&artifact.path.display().to_string())
+        ));
+    }
+    html.push_str("</ul>\n");
+    for artifact in artifacts.iter().filter(|artifact| is_image_path(&artifact.path)) {
+        html.push_str(&html_embedded_image(&artifact.path));
+    }
+    html
+}
+
+fn html_embedded_image(path: &Path) -> String {
+    let name = escape_html(&file_name(path));
+    let too_large = fs::metadata(path).map(|metadata| metadata.len() > MAX_EMBEDDED_IMAGE_BYTES).unwrap_or(true);
+    let bytes = match too_large {
+        true => None,
+        false => fs::read(path).ok(),
+    };
+    let Some(bytes) = bytes else {
+        return format!("<p class=\"note\">{name} could not be embedded.</p>\n");
+    };
+    let extension = path.extension().map(|extension| extension.to_string_lossy().into_owned()).unwrap_or_default();
+    format!(
+        "<img src=\"data:{};base64,{}\" alt=\"{name}\">\n",
+        image_mime_type(&extension),
+        BASE64_STANDARD.encode(bytes)
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_file_urls() {
+        assert_eq!(file_url(Path::new("/home/me/My Images/cat #1 (100%).png")), "file:///home/me/My%20Images/cat%20%231%20%28100%25%29.png");
+        assert_eq!(file_url(Path::new("C:\\Users\\me\\chart.png")), "file:///C:/Users/me/chart.png");
+        assert_eq!(encode_path(Path::new("/tmp/café.md")), "/tmp/caf%C3%A9.md");
+    }
+
+    #[test]
+    fn refuses_to_overwrite_without_force() {
+        let path = std::env::temp_dir().join(format!("bedrock_assistant-export-{}.md", std::process::id()));
+        let _ = fs::remove_file(&path);
+        write_export(&path, "first", false).unwrap();
+        let err = write_export(&path, "second", false).unwrap_err();
+        assert_eq!(err.to_string(), format!("{} already exists. Use --force to overwrite it.", path.display()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first");
+        write_export(&path, "second", true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        let _ = fs::remove_file(&path);
+    }
+}
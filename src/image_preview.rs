@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::{self, Write};
+use std::io::{stdout, Cursor, IsTerminal};
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use base64::prelude::*;
+use crossterm::terminal;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+
+
+// width of a preview in terminal columns
+pub const PREVIEW_COLUMNS: u32 = 40;
+// used when the terminal does not report its size in pixels
+const DEFAULT_CELL_WIDTH: u32 = 8;
+// kitty expects the image data in chunks of at most 4096 bytes
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+// How images are shown in the terminal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImagePreview {
+    // detected from the terminal
+    Auto,
+    Kitty,
+    ITerm,
+    Sixel,
+    // colored half blocks, works in any terminal with true color
+    Blocks,
+    Off,
+}
+
+impl FromStr for ImagePreview {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "auto" => Ok(ImagePreview::Auto),
+            "kitty" => Ok(ImagePreview::Kitty),
+            "iterm" => Ok(ImagePreview::ITerm),
+            "sixel" => Ok(ImagePreview::Sixel),
+            "blocks" => Ok(ImagePreview::Blocks),
+            "off" => Ok(ImagePreview::Off),
+            other => bail!("Unknown image preview: {other}. Possible value: auto, kitty, iterm, sixel, blocks, off."),
+        }
+    }
+}
+
+impl fmt::Display for ImagePreview {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ImagePreview::Auto => "auto",
+            ImagePreview::Kitty => "kitty",
+            ImagePreview::ITerm => "iterm",
+            ImagePreview::Sixel => "sixel",
+            ImagePreview::Blocks => "blocks",
+            ImagePreview::Off => "off",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ImagePreview {
+    // the protocol to use, Off when the output is not a terminal
+    pub fn resolve(self) -> ImagePreview {
+        if self == ImagePreview::Off || !stdout().is_terminal() {
+            return ImagePreview::Off;
+        }
+        if self != ImagePreview::Auto {
+            return self;
+        }
+
+        let term = env::var("TERM").unwrap_or_default();
+        let term_program = env::var("TERM_PROGRAM").unwrap_or_default();
+        if term == "xterm-kitty" || env::var("KITTY_WINDOW_ID").is_ok() || term_program == "ghostty" {
+            return ImagePreview::Kitty;
+        }
+        // LC_TERMINAL is forwarded over ssh
+        if term_program == "iTerm.app" || term_program == "WezTerm" || env::var("LC_TERMINAL").is_ok_and(|terminal| terminal == "iTerm2") {
+            return ImagePreview::ITerm;
+        }
+        if term.contains("sixel") || term.starts_with("foot") || term.starts_with("mlterm") {
+            return ImagePreview::Sixel;
+        }
+        ImagePreview::Blocks
+    }
+
+    // The escape sequences drawing the image, ending with a new line. None when previews are off.
+    pub fn render(self, image: &DynamicImage) -> Result<Option<String>> {
+        let columns = match terminal::size() {
+            Ok((width, _)) => PREVIEW_COLUMNS.min((width as u32).saturating_sub(2)).max(1),
+            Err(_) => PREVIEW_COLUMNS,
+        };
+        let rendered = match self.resolve() {
+            ImagePreview::Off | ImagePreview::Auto => return Ok(None),
+            ImagePreview::Kitty => kitty(image, columns)?,
+            ImagePreview::ITerm => iterm(image, columns)?,
+            ImagePreview::Sixel => sixel(&image.resize(columns * cell_width(), u32::MAX, FilterType::Triangle)),
+            ImagePreview::Blocks => blocks(image, columns),
+        };
+        Ok(Some(rendered))
+    }
+}
+
+fn cell_width() -> u32 {
+    match terminal::window_size() {
+        Ok(size) if size.width > 0 && size.columns > 0 => (size.width / size.columns) as u32,
+        _ => DEFAULT_CELL_WIDTH,
+    }
+}
+
+fn encode_png(image: &DynamicImage) -> Result<Vec<u8>> {
+    // a preview does not need more than this
+    let image = image.resize(PREVIEW_COLUMNS * 20, PREVIEW_COLUMNS * 20, FilterType::Triangle);
+    let mut bytes: Vec<u8> = vec![];
+    image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?;
+    Ok(bytes)
+}
+
+// https://sw.kovidgoyal.net/kitty/graphics-protocol/
+fn kitty(image: &DynamicImage, columns: u32) -> Result<String> {
+    let data = BASE64_STANDARD.encode(encode_png(image)?);
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+    let mut out = String::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let more = if index + 1 < chunks.len() { 1 } else { 0 };
+        let chunk = std::str::from_utf8(chunk)?;
+        if index == 0 {
+            write!(out, "\x1b_Gf=100,a=T,c={columns},m={more};{chunk}\x1b\\")?;
+        } else {
+            write!(out, "\x1b_Gm={more};{chunk}\x1b\\")?;
+        }
+    }
+    out.push('\n');
+    Ok(out)
+}
+
+// https://iterm2.com/documentation-images.html
+fn iterm(image: &DynamicImage, columns: u32) -> Result<String> {
+    let bytes = encode_png(image)?;
+    Ok(format!(
+        "\x1b]1337;File=inline=1;size={};width={columns};preserveAspectRatio=1:{}\x07\n",
+        bytes.len(), BASE64_STANDARD.encode(&bytes)
+    ))
+}
+
+// Sixel with a 6x6x6 color cube, each band of 6 rows drawn once per color used in it.
+fn sixel(image: &DynamicImage) -> String {
+    let image = image.to_rgb8();
+    let (width, height) = image.dimensions();
+    let level = |value: u8| (value as usize * 5 + 127) / 255;
+
+    let mut out = format!("\x1bPq\"1;1;{width};{height}");
+    for color in 0..216 {
+        let percent = |level: usize| level * 100 / 5;
+        let _ = write!(out, "#{color};2;{};{};{}", percent(color / 36), percent(color / 6 % 6), percent(color % 6));
+    }
+    for top in (0..height).step_by(6) {
+        let mut bands: BTreeMap<usize, Vec<u8>> = BTreeMap::new();
+        for y in top..(top + 6).min(height) {
+            for x in 0..width {
+                let pixel = image.get_pixel(x, y);
+                let color = level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2]);
+                bands.entry(color).or_insert(vec![0; width as usize])[x as usize] |= 1 << (y - top);
+            }
+        }
+        for (index, (color, bits)) in bands.iter().enumerate() {
+            if index > 0 {
+                out.push('$');
+            }
+            let _ = write!(out, "#{color}");
+            let mut x = 0;
+            while x < bits.len() {
+                let mut run = 1;
+                while x + run < bits.len() && bits[x + run] == bits[x] {
+                    run += 1;
+                }
+                let sixel = (63 + bits[x]) as char;
+                if run > 3 {
+                    let _ = write!(out, "!{run}{sixel}");
+                } else {
+                    out.extend(std::iter::repeat_n(sixel, run));
+                }
+                x += run;
+            }
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\\n");
+    out
+}
+
+// Each cell shows two pixels, the upper half block in the foreground color and the lower one in the background color.
+fn blocks(image: &DynamicImage, columns: u32) -> String {
+    let image = image.resize_exact(
+        columns,
+        (columns * image.height() / image.width().max(1)).max(2),
+        FilterType::Triangle
+    ).to_rgb8();
+    let mut out = String::new();
+    for y in (0..image.height()).step_by(2) {
+        for x in 0..image.width() {
+            let upper = image.get_pixel(x, y);
+            let lower = if y + 1 < image.height() { image.get_pixel(x, y + 1) } else { upper };
+            let _ = write!(
+                out, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                upper[0], upper[1], upper[2], lower[0], lower[1], lower[2]
+            );
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
@@ -1,21 +1,31 @@
 pub mod tool;
 pub mod bedrock_service;
 pub mod terminal_service;
+pub mod image_preview;
+pub mod attachment;
 pub mod model_constants;
+pub mod config;
+pub mod session;
+pub mod export;
 
 use aws_config::meta::region::RegionProviderChain;
 use aws_config::Region;
 use aws_sdk_bedrockruntime::Client;
 use bedrock_service::BedrockService;
-use clap::{Arg, Command};
+use config::Config;
+use session::Session;
 use crossterm::event::{self, Event, KeyCode, KeyModifiers};
 use crossterm::terminal::Clear;
 use crossterm::{terminal, ExecutableCommand};
 use model_constants::{CLAUDE_REGION, REGION_KEY};
 use terminal_service::TerminalService;
+use tool::registry::ToolRegistry;
+use tool::python_kernel::PythonKernel;
+use tool::run_python::python_executable;
 use core::str;
 use std::env;
 use std::io::{stdout, Stdout};
+use std::sync::Arc;
 use anyhow::Result;
 
 
@@ -39,6 +49,7 @@ Example queries for data analysis, data exploration, math, and etc:
 Example queries for questioning regarding files:
 - Summarize the content in ./test/test.pdf.
 
+Type `/help` to see the available commands.
 To exit the program, simply type `ESC` or `Ctrl+C`.
 
 *****
@@ -62,15 +73,10 @@ https://github.com/0Itsuki0/itsuki_assistant_with_bedrock
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let flag_id = "non-stream";
-    let command = Command::new("mycmd")
-        .arg(
-            Arg::new(flag_id)
-                .long("non-stream")
-                .action(clap::ArgAction::SetTrue)
-        );
-    let matches = command.get_matches();
-    let should_stream = !matches.get_flag(&flag_id);
+    let app_config = Config::from_args()?;
+    if let Some(export) = &app_config.export {
+        return export.run();
+    }
 
     let region_string = env::var(REGION_KEY).unwrap_or(CLAUDE_REGION.to_owned());
     let region = Region::new(region_string);
@@ -80,13 +86,29 @@ async fn main() -> Result<()> {
     let client = Client::new(&config);
     let mut stdout: Stdout = stdout();
 
-    let mut bedrock_service = BedrockService::new(&client)?;
+    let python_kernel = match app_config.python_kernel {
+        true => Some(Arc::new(PythonKernel::new(&python_executable(), app_config.python_sandbox.clone()))),
+        false => None,
+    };
+    let tools = ToolRegistry::with_builtin_tools(&client, &app_config, python_kernel.clone())?;
+    let mut bedrock_service = BedrockService::new(&client, &app_config, tools)?;
+    if let Some(python_kernel) = python_kernel {
+        bedrock_service.set_python_kernel(python_kernel);
+    }
+    let saved_session = match (&app_config.resume, app_config.continue_session) {
+        (Some(id), _) => Some(Session::load(id)?),
+        (None, true) => Some(Session::latest()?),
+        (None, false) => None,
+    };
     // bedrock_service.run("Draw a graph of y=x.").await?;
     // bedrock_service.run("a hello world image in the current folder").await?;
 
     let mut terminal_service = TerminalService::new();
 
     terminal_service.log_info(INTRODUCTION)?;
+    if let Some(session) = saved_session {
+        bedrock_service.resume(session)?;
+    }
     terminal::enable_raw_mode()?;
     terminal_service.log_info("You:\r")?;
 
@@ -122,9 +144,19 @@ async fn main() -> Result<()> {
                         continue;
                     }
 
+                    if user_input.starts_with('/') {
+                        terminal_service.log_info_inline("\n\r")?;
+                        terminal::disable_raw_mode()?;
+                        bedrock_service.run_command(&user_input).await?;
+                        terminal::enable_raw_mode()?;
+                        terminal_service.log_info("\rYou:\r")?;
+                        user_input = String::from("");
+                        continue;
+                    }
+
                     terminal_service.log_info_inline("\n\r..... Please wait!\r")?;
                     terminal::disable_raw_mode()?;
-                    if should_stream {
+                    if app_config.should_stream {
                         bedrock_service.run_stream(&user_input).await?;
                     } else {
                         bedrock_service.run(&user_input).await?;
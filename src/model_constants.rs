@@ -6,5 +6,32 @@ pub const BEDROCK_ASSISTANT_PYTHON: &str = "python3.11";
 
 pub const REGION_KEY: &str = "BEDROCK_REGION";
 pub const CHAT_MODEL_KEY: &str = "BEDROCK_CHAT_MODEL_ID";
-pub const IMAGE_MODEL_KEY: &str = "BEDROCK_IAMGE_MODEL_ID";
-pub const BEDROCK_ASSISTANT_PYTHON_KEY: &str = "BEDROCK_ASSISTANT_PYTHON";
\ No newline at end of file
+pub const IMAGE_MODEL_KEY: &str = "BEDROCK_IMAGE_MODEL_ID";
+// the misspelled name used by earlier versions, still read when the new one is not set
+pub const LEGACY_IMAGE_MODEL_KEY: &str = "BEDROCK_IAMGE_MODEL_ID";
+pub const BEDROCK_ASSISTANT_PYTHON_KEY: &str = "BEDROCK_ASSISTANT_PYTHON";
+pub const MAX_TOOL_ITERATIONS: usize = 10;
+pub const MAX_TOOL_ITERATIONS_KEY: &str = "BEDROCK_ASSISTANT_MAX_TOOL_ITERATIONS";
+
+pub const TOOL_CONCURRENCY: usize = 4;
+pub const TOOL_CONCURRENCY_KEY: &str = "BEDROCK_ASSISTANT_TOOL_CONCURRENCY";
+
+pub const TOOL_POLICY_KEY: &str = "BEDROCK_ASSISTANT_TOOL_POLICY";
+
+pub const PYTHON_TIMEOUT_SECS: u64 = 120;
+pub const PYTHON_TIMEOUT_KEY: &str = "BEDROCK_ASSISTANT_PYTHON_TIMEOUT";
+pub const PYTHON_MEMORY_LIMIT_MB: u64 = 2048;
+pub const PYTHON_MEMORY_LIMIT_KEY: &str = "BEDROCK_ASSISTANT_PYTHON_MEMORY_MB";
+pub const PYTHON_CPU_LIMIT_SECS: u64 = 120;
+pub const PYTHON_CPU_LIMIT_KEY: &str = "BEDROCK_ASSISTANT_PYTHON_CPU_SECONDS";
+pub const PYTHON_DENY_NETWORK_KEY: &str = "BEDROCK_ASSISTANT_PYTHON_DENY_NETWORK";
+pub const PYTHON_KERNEL_KEY: &str = "BEDROCK_ASSISTANT_PYTHON_KERNEL";
+
+pub const IMAGE_PREVIEW_KEY: &str = "BEDROCK_ASSISTANT_IMAGE_PREVIEW";
+pub const OPEN_ARTIFACTS_KEY: &str = "BEDROCK_ASSISTANT_OPEN_ARTIFACTS";
+pub const CONFIG_FILE_KEY: &str = "BEDROCK_ASSISTANT_CONFIG";
+
+pub const SHELL_TIMEOUT_SECS: u64 = 30;
+pub const SHELL_TIMEOUT_KEY: &str = "BEDROCK_ASSISTANT_SHELL_TIMEOUT";
+pub const SHELL_ALLOW_KEY: &str = "BEDROCK_ASSISTANT_SHELL_ALLOW";
+pub const SHELL_DENY_KEY: &str = "BEDROCK_ASSISTANT_SHELL_DENY";
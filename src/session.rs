@@ -0,0 +1,475 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::path::{self, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use aws_sdk_bedrockruntime::types::{
+    ContentBlock, ConversationRole, DocumentBlock, DocumentFormat, DocumentSource, ImageBlock, ImageFormat, ImageSource,
+    Message, ToolResultBlock, ToolResultContentBlock, ToolResultStatus, ToolUseBlock,
+};
+use aws_smithy_types::Blob;
+use base64::prelude::*;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::data_directory;
+use crate::tool::{ToDocument, ToValue};
+use crate::tool::journal::{Change, JournalEntry};
+
+
+// bumped when the format changes in a way older versions cannot read
+pub const SESSION_VERSION: u32 = 1;
+// characters of the first message used as the title
+const TITLE_LENGTH: usize = 60;
+
+// A conversation saved to `<data directory>/sessions/<id>.json`.
+// The Bedrock types are not serializable, so messages are stored in a format of our own.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub version: u32,
+    pub id: String,
+    pub model_id: String,
+    pub created_at: DateTime<Local>,
+    pub updated_at: DateTime<Local>,
+    pub messages: Vec<SessionMessage>,
+    // files created or modified by the tools, missing in conversations saved before they were recorded
+    #[serde(default)]
+    pub artifacts: Vec<SessionArtifact>,
+    // the changes `/undo` can still revert, with absolute paths
+    #[serde(default)]
+    pub journal: Vec<JournalEntry>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionArtifact {
+    pub tool_use_id: String,
+    // absolute, so the links of an export work from any folder
+    pub path: PathBuf,
+    // `created` or `modified`
+    pub change: String,
+    pub time: DateTime<Local>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionMessage {
+    pub role: Role,
+    // when the message was first saved
+    pub timestamp: DateTime<Local>,
+    pub content: Vec<SessionContent>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+// Images and documents are stored as base64.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SessionContent {
+    Text { text: String },
+    Image { format: String, data: String },
+    Document { name: String, format: String, data: String },
+    #[serde(rename_all = "camelCase")]
+    ToolUse { tool_use_id: String, name: String, input: Value },
+    #[serde(rename_all = "camelCase")]
+    ToolResult { tool_use_id: String, status: Option<String>, content: Vec<SessionContent> },
+    Json { value: Value },
+}
+
+impl Session {
+    pub fn new(id: &str, model_id: &str) -> Self {
+        let now = Local::now();
+        Self {
+            version: SESSION_VERSION,
+            id: id.to_owned(),
+            model_id: model_id.to_owned(),
+            created_at: now,
+            updated_at: now,
+            messages: vec![],
+            artifacts: vec![],
+            journal: vec![],
+        }
+    }
+
+    pub fn directory() -> PathBuf {
+        data_directory().join("sessions")
+    }
+
+    pub fn path(id: &str) -> PathBuf {
+        Self::path_in(&Self::directory(), id)
+    }
+
+    pub fn path_in(directory: &Path, id: &str) -> PathBuf {
+        directory.join(format!("{id}.json"))
+    }
+
+    pub fn load(id: &str) -> Result<Self> {
+        Self::load_from(&Self::directory(), id)
+    }
+
+    pub fn load_from(directory: &Path, id: &str) -> Result<Self> {
+        // ids are made of the characters of generated ids, so they cannot point outside of the sessions folder
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            bail!("{id} is not a valid conversation id. Use /sessions to list them.")
+        }
+        let path = Self::path_in(directory, id);
+        if !path.exists() {
+            bail!("No saved conversation with id {id}. Use /sessions to list them.")
+        }
+        let content = fs::read_to_string(&path).context(format!("failed to read {}", path.display()))?;
+        let session: Session = serde_json::from_str(&content).context(format!("invalid conversation file {}", path.display()))?;
+        if session.version > SESSION_VERSION {
+            bail!("{} was saved by a newer version of the app.", path.display())
+        }
+        Ok(session)
+    }
+
+    // the most recently updated conversation
+    pub fn latest() -> Result<Self> {
+        match Self::list()?.into_iter().next() {
+            Some(session) => Ok(session),
+            None => bail!("No saved conversation to continue."),
+        }
+    }
+
+    // saved conversations, most recently updated first. Files that cannot be read are skipped.
+    pub fn list() -> Result<Vec<Self>> {
+        let directory = Self::directory();
+        if !directory.exists() {
+            return Ok(vec![]);
+        }
+        let mut sessions: Vec<Session> = fs::read_dir(&directory)?
+            .flatten()
+            .filter(|entry| entry.path().extension().is_some_and(|extension| extension == "json"))
+            .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+            .filter_map(|content| serde_json::from_str(&content).ok())
+            .collect();
+        sessions.sort_by_key(|session| Reverse(session.updated_at));
+        Ok(sessions)
+    }
+
+    // Replaces the messages with the conversation, keeping the timestamps of the messages already saved.
+    pub fn update(&mut self, conversation: &[Message], model_id: &str) -> Result<()> {
+        let now = Local::now();
+        let mut messages: Vec<SessionMessage> = vec![];
+        for (index, message) in conversation.iter().enumerate() {
+            let timestamp = self.messages.get(index).map(|message| message.timestamp).unwrap_or(now);
+            messages.push(SessionMessage::from_message(message, timestamp)?);
+        }
+        self.messages = messages;
+        self.model_id = model_id.to_owned();
+        self.updated_at = now;
+        Ok(())
+    }
+
+    // Adds the files recorded by the journal. Folders are left out, and files undone stay listed.
+    pub fn record_artifacts(&mut self, entries: &[JournalEntry]) {
+        for entry in entries {
+            if entry.change == Change::CreatedDirectory {
+                continue;
+            }
+            let path = path::absolute(&entry.path).unwrap_or(entry.path.clone());
+            let recorded = self.artifacts.iter()
+                .any(|artifact| artifact.tool_use_id == entry.tool_use_id && artifact.path == path);
+            if !recorded {
+                self.artifacts.push(SessionArtifact {
+                    tool_use_id: entry.tool_use_id.clone(),
+                    path,
+                    change: entry.change.to_string(),
+                    time: entry.time,
+                });
+            }
+        }
+    }
+
+    // Replaces the saved journal, so `/undo` still works after resuming.
+    pub fn record_journal(&mut self, entries: &[JournalEntry]) {
+        self.journal = entries.iter().map(|entry| JournalEntry {
+            path: path::absolute(&entry.path).unwrap_or(entry.path.clone()),
+            ..entry.clone()
+        }).collect();
+    }
+
+    pub fn artifacts_of(&self, tool_use_id: &str) -> Vec<&SessionArtifact> {
+        self.artifacts.iter().filter(|artifact| artifact.tool_use_id == tool_use_id).collect()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.save_in(&Self::directory())
+    }
+
+    pub fn save_in(&self, directory: &Path) -> Result<()> {
+        let path = Self::path_in(directory, &self.id);
+        fs::create_dir_all(directory)?;
+        // written to a temporary file first, so an interrupted save does not lose the conversation
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_string(self)?)?;
+        fs::rename(&temporary, &path)?;
+        Ok(())
+    }
+
+    pub fn to_messages(&self) -> Result<Vec<Message>> {
+        self.messages.iter().map(|message| message.to_message()).collect()
+    }
+
+    // the start of the first text typed by the user
+    pub fn title(&self) -> String {
+        let text = self.messages.iter()
+            .filter(|message| message.role == Role::User)
+            .flat_map(|message| &message.content)
+            .find_map(|content| match content {
+                SessionContent::Text { text } => Some(text.trim()),
+                _ => None,
+            })
+            .unwrap_or("");
+        let title: String = text.chars().take(TITLE_LENGTH).collect();
+        if title.len() < text.len() {
+            return format!("{title}...");
+        }
+        title
+    }
+}
+
+impl SessionMessage {
+    pub fn from_message(message: &Message, timestamp: DateTime<Local>) -> Result<Self> {
+        let role = match message.role() {
+            ConversationRole::User => Role::User,
+            ConversationRole::Assistant => Role::Assistant,
+            other => bail!("unknown role: {}", other.as_str()),
+        };
+        let content = message.content().iter().filter_map(SessionContent::from_content_block).collect();
+        Ok(Self { role, timestamp, content })
+    }
+
+    pub fn to_message(&self) -> Result<Message> {
+        let role = match self.role {
+            Role::User => ConversationRole::User,
+            Role::Assistant => ConversationRole::Assistant,
+        };
+        let content = self.content.iter().map(|content| content.to_content_block()).collect::<Result<Vec<ContentBlock>>>()?;
+        Ok(Message::builder().role(role).set_content(Some(content)).build()?)
+    }
+}
+
+impl SessionContent {
+    // None for blocks the app does not create, such as guardrail content
+    pub fn from_content_block(block: &ContentBlock) -> Option<Self> {
+        match block {
+            ContentBlock::Text(text) => Some(SessionContent::Text { text: text.to_owned() }),
+            ContentBlock::Image(image) => Self::from_image(image),
+            ContentBlock::Document(document) => Self::from_document(document),
+            ContentBlock::ToolUse(tool_use) => Some(SessionContent::ToolUse {
+                tool_use_id: tool_use.tool_use_id().to_owned(),
+                name: tool_use.name().to_owned(),
+                input: tool_use.input().to_value(),
+            }),
+            ContentBlock::ToolResult(tool_result) => Some(SessionContent::ToolResult {
+                tool_use_id: tool_result.tool_use_id().to_owned(),
+                status: tool_result.status().map(|status| status.as_str().to_owned()),
+                content: tool_result.content().iter().filter_map(|content| match content {
+                    ToolResultContentBlock::Text(text) => Some(SessionContent::Text { text: text.to_owned() }),
+                    ToolResultContentBlock::Json(json) => Some(SessionContent::Json { value: json.to_value() }),
+                    ToolResultContentBlock::Image(image) => Self::from_image(image),
+                    ToolResultContentBlock::Document(document) => Self::from_document(document),
+                    _ => None,
+                }).collect(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn from_image(image: &ImageBlock) -> Option<Self> {
+        let bytes = image.source()?.as_bytes().ok()?;
+        Some(SessionContent::Image {
+            format: image.format().as_str().to_owned(),
+            data: BASE64_STANDARD.encode(bytes.as_ref()),
+        })
+    }
+
+    fn from_document(document: &DocumentBlock) -> Option<Self> {
+        let bytes = document.source()?.as_bytes().ok()?;
+        Some(SessionContent::Document {
+            name: document.name().to_owned(),
+            format: document.format().as_str().to_owned(),
+            data: BASE64_STANDARD.encode(bytes.as_ref()),
+        })
+    }
+
+    pub fn to_content_block(&self) -> Result<ContentBlock> {
+        let block = match self {
+            SessionContent::Text { text } => ContentBlock::Text(text.to_owned()),
+            SessionContent::Image { .. } => ContentBlock::Image(self.to_image()?),
+            SessionContent::Document { .. } => ContentBlock::Document(self.to_document()?),
+            SessionContent::ToolUse { tool_use_id, name, input } => ContentBlock::ToolUse(
+                ToolUseBlock::builder()
+                    .tool_use_id(tool_use_id)
+                    .name(name)
+                    .input(input.to_document())
+                    .build()?
+            ),
+            SessionContent::ToolResult { tool_use_id, status, content } => {
+                let mut builder = ToolResultBlock::builder().tool_use_id(tool_use_id);
+                if let Some(status) = status {
+                    builder = builder.status(ToolResultStatus::from(status.as_str()));
+                }
+                for content in content {
+                    let content = match content {
+                        SessionContent::Text { text } => ToolResultContentBlock::Text(text.to_owned()),
+                        SessionContent::Json { value } => ToolResultContentBlock::Json(value.to_document()),
+                        SessionContent::Image { .. } => ToolResultContentBlock::Image(content.to_image()?),
+                        SessionContent::Document { .. } => ToolResultContentBlock::Document(content.to_document()?),
+                        _ => bail!("a tool result cannot contain a tool use or a tool result"),
+                    };
+                    builder = builder.content(content);
+                }
+                ContentBlock::ToolResult(builder.build()?)
+            },
+            SessionContent::Json { .. } => bail!("json content is only allowed in a tool result"),
+        };
+        Ok(block)
+    }
+
+    fn to_image(&self) -> Result<ImageBlock> {
+        let SessionContent::Image { format, data } = self else {
+            bail!("not an image")
+        };
+        Ok(
+            ImageBlock::builder()
+                .format(ImageFormat::from(format.as_str()))
+                .source(ImageSource::Bytes(Blob::new(BASE64_STANDARD.decode(data)?)))
+                .build()?
+        )
+    }
+
+    fn to_document(&self) -> Result<DocumentBlock> {
+        let SessionContent::Document { name, format, data } = self else {
+            bail!("not a document")
+        };
+        Ok(
+            DocumentBlock::builder()
+                .name(name)
+                .format(DocumentFormat::from(format.as_str()))
+                .source(DocumentSource::Bytes(Blob::new(BASE64_STANDARD.decode(data)?)))
+                .build()?
+        )
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use aws_smithy_types::{Document, Number};
+    use tempfile::TempDir;
+
+    fn conversation() -> Vec<Message> {
+        let user = Message::builder()
+            .role(ConversationRole::User)
+            .content(ContentBlock::Text("Plot the sales in ./data/sales.csv".to_owned()))
+            .content(ContentBlock::Image(
+                ImageBlock::builder()
+                    .format(ImageFormat::Png)
+                    .source(ImageSource::Bytes(Blob::new(vec![0x89, b'P', b'N', b'G'])))
+                    .build()
+                    .unwrap()
+            ))
+            .build()
+            .unwrap();
+        let input = Document::Object(HashMap::from([
+            ("path".to_owned(), Document::String("./data/sales.csv".to_owned())),
+            ("maxBytes".to_owned(), Document::Number(Number::Float(1000.0))),
+        ]));
+        let assistant = Message::builder()
+            .role(ConversationRole::Assistant)
+            .content(ContentBlock::Text("I will read the file first.".to_owned()))
+            .content(ContentBlock::ToolUse(
+                ToolUseBlock::builder().tool_use_id("tooluse_1").name("READ_FILE").input(input).build().unwrap()
+            ))
+            .build()
+            .unwrap();
+        let tool_result = Message::builder()
+            .role(ConversationRole::User)
+            .content(ContentBlock::ToolResult(
+                ToolResultBlock::builder()
+                    .tool_use_id("tooluse_1")
+                    .content(ToolResultContentBlock::Text("month,sales\njan,10".to_owned()))
+                    .content(ToolResultContentBlock::Document(
+                        DocumentBlock::builder()
+                            .name("file_read")
+                            .format(DocumentFormat::Csv)
+                            .source(DocumentSource::Bytes(Blob::new(b"month,sales\njan,10".to_vec())))
+                            .build()
+                            .unwrap()
+                    ))
+                    .status(ToolResultStatus::Success)
+                    .build()
+                    .unwrap()
+            ))
+            .build()
+            .unwrap();
+        vec![user, assistant, tool_result]
+    }
+
+    #[test]
+    fn saves_and_loads_a_conversation() {
+        let directory = TempDir::new().unwrap();
+        let id = "20240815-093012-3fa9c1";
+        let mut session = Session::new(id, "anthropic.claude-3-haiku-20240307-v1:0");
+        session.update(&conversation(), "anthropic.claude-3-haiku-20240307-v1:0").unwrap();
+        let entries = [JournalEntry {
+            tool_use_id: "tooluse_2".to_owned(),
+            path: PathBuf::from("chart.png"),
+            change: Change::ModifiedFile(Some(PathBuf::from("/backups/chart.png"))),
+            time: Local::now(),
+        }];
+        session.record_journal(&entries);
+        session.save_in(directory.path()).unwrap();
+
+        let loaded = Session::load_from(directory.path(), id).unwrap();
+
+        assert_eq!(loaded.to_messages().unwrap(), conversation());
+        assert_eq!(loaded.created_at, session.created_at);
+        assert_eq!(loaded.title(), "Plot the sales in ./data/sales.csv");
+        assert_eq!(loaded.journal.len(), 1);
+        assert!(loaded.journal[0].path.is_absolute());
+        assert_eq!(loaded.journal[0].change, Change::ModifiedFile(Some(PathBuf::from("/backups/chart.png"))));
+    }
+
+    #[test]
+    fn keeps_the_timestamps_of_saved_messages() {
+        let mut session = Session::new("20240815-093012-3fa9c1", "model");
+        let conversation = conversation();
+        session.update(&conversation[..1], "model").unwrap();
+        let first = session.messages[0].timestamp;
+        session.update(&conversation, "model").unwrap();
+        assert_eq!(session.messages.len(), 3);
+        assert_eq!(session.messages[0].timestamp, first);
+    }
+
+    #[test]
+    fn rejects_ids_that_are_not_generated_ids() {
+        for id in ["../config", "sessions/../../x", "/etc/passwd", ""] {
+            let err = Session::load(id).unwrap_err();
+            assert!(err.to_string().ends_with("is not a valid conversation id. Use /sessions to list them."), "{id}");
+        }
+    }
+
+    #[test]
+    fn shortens_long_titles() {
+        let mut session = Session::new("id", "model");
+        session.messages.push(SessionMessage {
+            role: Role::User,
+            timestamp: Local::now(),
+            content: vec![SessionContent::Text { text: "a".repeat(100) }],
+        });
+        assert_eq!(session.title(), format!("{}...", "a".repeat(TITLE_LENGTH)));
+    }
+}
@@ -1,19 +1,32 @@
 use anyhow::Result;
-use std::io::{stdout, Stdout, Write};
+use std::io::{stdin, stdout, Stdout, Write};
+use std::path::Path;
 use aws_smithy_types::Document;
 use crossterm::ExecutableCommand;
 use crossterm::terminal::{self, Clear};
 use crossterm::style::{Color, SetForegroundColor};
 
+use crate::image_preview::ImagePreview;
+use crate::tool::image_content::load_image;
+
+// lines shown per output stream of a process
+const PROCESS_OUTPUT_LINES: usize = 20;
+
 #[derive(Debug)]
 pub struct TerminalService {
     stdout: Stdout
 }
 
+impl Default for TerminalService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TerminalService {
 
     pub fn new() -> Self {
-        return Self {
+        Self {
             stdout: stdout()
         }
     }
@@ -79,4 +92,94 @@ impl TerminalService {
         Ok(())
     }
 
-}
\ No newline at end of file
+    pub fn log_tool_status(&mut self, position: usize, total: usize, tool_name: &str, status: &str) -> Result<()>{
+        writeln!(self.stdout, "\x1b[0;90m[{}/{}] {}: {}", position, total, tool_name, status)?;
+        Ok(())
+    }
+
+    // returns the raw answer typed by the user
+    // None when stdin is closed or cannot be read
+    pub fn ask_approval(&mut self, tool_name: &str, preview: &str) -> Result<Option<String>>{
+        self.stdout.execute(SetForegroundColor(Color::Yellow))?;
+        writeln!(self.stdout, "{} wants to run:", tool_name)?;
+        writeln!(self.stdout, "\x1b[0;90m{}", preview)?;
+        self.stdout.execute(SetForegroundColor(Color::Yellow))?;
+        write!(self.stdout, "Allow? [y]es / [n]o / [a]lways: ")?;
+        self.stdout.flush()?;
+
+        let mut answer = String::new();
+        match stdin().read_line(&mut answer) {
+            Ok(0) | Err(_) => {
+                writeln!(self.stdout)?;
+                Ok(None)
+            },
+            Ok(_) => Ok(Some(answer)),
+        }
+    }
+
+    // the image drawn inline, if the terminal supports it and previews are on
+    pub fn log_image_preview(&mut self, path: &Path, preview: ImagePreview) -> Result<()>{
+        if preview.resolve() == ImagePreview::Off {
+            return Ok(());
+        }
+        let Some(rendered) = preview.render(&load_image(path)?)? else {
+            return Ok(());
+        };
+        // written at once, so it is not mixed up with the output of tools running at the same time
+        let mut stdout = self.stdout.lock();
+        write!(stdout, "\x1b[0;90m{}\n{}", path.display(), rendered)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    // output of a finished process, only the last lines of each stream
+    pub fn log_process_output(&mut self, title: &str, exit_code: Option<i32>, stdout: &str, stderr: &str, error: Option<&str>) -> Result<()>{
+        let exit_code = exit_code.map(|code| code.to_string()).unwrap_or("none".to_owned());
+        writeln!(self.stdout, "\x1b[0;90m{} (exit code: {})", title, exit_code)?;
+        for line in last_lines(stdout, PROCESS_OUTPUT_LINES) {
+            writeln!(self.stdout, "\x1b[0;90m  {}", line)?;
+        }
+        self.stdout.execute(SetForegroundColor(Color::Red))?;
+        for line in last_lines(stderr, PROCESS_OUTPUT_LINES) {
+            writeln!(self.stdout, "  {}", line)?;
+        }
+        if let Some(error) = error {
+            writeln!(self.stdout, "{}", error)?;
+        }
+        self.stdout.execute(SetForegroundColor(Color::Reset))?;
+        Ok(())
+    }
+
+}
+
+fn last_lines(text: &str, count: usize) -> Vec<&str> {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.len() <= count {
+        return lines;
+    }
+    lines[lines.len() - count..].to_vec()
+}
+// A unified diff with added lines in green, removed lines in red and hunk headers in cyan, cut after max_lines.
+pub fn colored_diff(diff: &str, max_lines: usize) -> String {
+    let lines: Vec<&str> = diff.lines().collect();
+    let mut out = String::new();
+    for line in lines.iter().take(max_lines) {
+        let color = if line.starts_with("+++") || line.starts_with("---") {
+            "\x1b[1;37m"
+        } else if line.starts_with('+') {
+            "\x1b[0;32m"
+        } else if line.starts_with('-') {
+            "\x1b[0;31m"
+        } else if line.starts_with("@@") {
+            "\x1b[0;36m"
+        } else {
+            "\x1b[0;90m"
+        };
+        out.push_str(&format!("{color}{line}\n"));
+    }
+    if lines.len() > max_lines {
+        out.push_str(&format!("\x1b[0;90m... {} more lines\n", lines.len() - max_lines));
+    }
+    out.push_str("\x1b[0;90m");
+    out
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::image_preview::ImagePreview;
+use super::image_content::is_image_path;
+use super::journal::Journal;
+
+
+// Which outputs of the tools are opened with the default app.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OpenArtifacts {
+    #[default]
+    Never,
+    // generated images and images created by python code
+    ImagesOnly,
+    // also the output folders and the python scripts
+    Always,
+}
+
+impl FromStr for OpenArtifacts {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "never" => Ok(OpenArtifacts::Never),
+            "images-only" => Ok(OpenArtifacts::ImagesOnly),
+            "always" => Ok(OpenArtifacts::Always),
+            other => bail!("Unknown open artifacts policy: {other}. Possible value: never, images-only, always."),
+        }
+    }
+}
+
+impl fmt::Display for OpenArtifacts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            OpenArtifacts::Never => "never",
+            OpenArtifacts::ImagesOnly => "images-only",
+            OpenArtifacts::Always => "always",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// What a tool knows about the conversation it runs in.
+#[derive(Clone, Debug)]
+pub struct ToolContext {
+    pub conversation_id: String,
+    pub image_preview: ImagePreview,
+    pub open_artifacts: OpenArtifacts,
+    // shared by all the tools of the conversation
+    pub journal: Journal,
+}
+
+impl ToolContext {
+    pub fn new(conversation_id: &str, config: &Config) -> Self {
+        Self {
+            conversation_id: conversation_id.to_owned(),
+            image_preview: config.image_preview,
+            open_artifacts: config.open_artifacts,
+            journal: Journal::new(),
+        }
+    }
+
+    // opens the output folder with the default app, if the policy allows it
+    pub fn open_folder(&self, path: &Path) {
+        if self.open_artifacts == OpenArtifacts::Always {
+            let _ = open::that_detached(path);
+        }
+    }
+
+    // opens an output file with the default app, if the policy allows it
+    pub fn open_file(&self, path: &Path) {
+        let should_open = match self.open_artifacts {
+            OpenArtifacts::Never => false,
+            OpenArtifacts::ImagesOnly => is_image_path(path),
+            OpenArtifacts::Always => true,
+        };
+        if should_open {
+            let _ = open::that_detached(path);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use super::{create_tool_result_block, get_bool, get_string, ToDocument, Tool, ToolJsonSchema};
+use super::context::ToolContext;
+use super::file_change::{read_existing, FileChange, PlannedChanges};
+use super::permission::ToolPolicy;
+
+use std::path::PathBuf;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use aws_sdk_bedrockruntime::types::{ToolResultBlock, ToolResultStatus};
+use aws_smithy_types::Document;
+use diffy::Patch;
+use serde_json::json;
+use tokio::task;
+
+
+// EDIT_FILE tool
+pub const EDIT_FILE_NAME: &str = "EDIT_FILE";
+pub const EDIT_FILE_DESCRIPTION: &str = "Change part of an existing text file, either by replacing an exact text with a new one, or by applying a unified diff. Read the file first so the text to replace matches exactly, including white spaces and indentation. The user reviews the change before it is written, and the previous version is backed up.";
+
+pub fn edit_file_schema() -> Result<Document> {
+    let json_schema = json!({
+        "type": "object",
+        "properties": {
+            "path": {
+                "type": "string",
+                "description": "The path of the file to edit."
+            },
+            "oldText": {
+                "type": "string",
+                "description": "The exact text to replace. It should appear only once in the file unless replaceAll is true, so include enough surrounding lines to make it unique. Required unless patch is given."
+            },
+            "newText": {
+                "type": "string",
+                "description": "The text to replace oldText with. Use an empty string to remove oldText. Required unless patch is given."
+            },
+            "replaceAll": {
+                "type": "boolean",
+                "description": "Replace every occurrence of oldText. Optional. The default value is false."
+            },
+            "patch": {
+                "type": "string",
+                "description": "A unified diff to apply to the file, with `---`/`+++` headers and `@@` hunks. Use it instead of oldText and newText for changes in several places."
+            },
+        },
+        "required": ["path"],
+    });
+    let document = ToolJsonSchema::new(json_schema)?.to_document();
+    Ok(document)
+}
+
+#[derive(Debug, Default)]
+pub struct EditFileTool {
+    // the changes previewed when asking for approval
+    planned: PlannedChanges,
+}
+
+#[async_trait]
+impl Tool for EditFileTool {
+    fn name(&self) -> &str {
+        EDIT_FILE_NAME
+    }
+
+    fn description(&self) -> &str {
+        EDIT_FILE_DESCRIPTION
+    }
+
+    fn input_schema(&self) -> Result<Document> {
+        edit_file_schema()
+    }
+
+    fn default_policy(&self) -> ToolPolicy {
+        ToolPolicy::Ask
+    }
+
+    fn preview(&self, input: &Document) -> String {
+        match planned_change(input) {
+            Ok(change) => {
+                let preview = change.preview();
+                self.planned.insert(input, change);
+                preview
+            },
+            Err(err) => format!("The file cannot be edited: {err}"),
+        }
+    }
+
+    async fn execute(&self, id: &str, input: &Document, context: &ToolContext) -> Result<ToolResultBlock> {
+        let id = id.to_owned();
+        let input = input.clone();
+        let context = context.clone();
+        let planned = self.planned.take(&input);
+        task::spawn_blocking(move || {
+            let change = match planned {
+                Some(change) => Ok(change),
+                None => planned_change(&input),
+            };
+            let result = change.and_then(|change| {
+                let backup = change.write(&id, &context)?;
+                Ok(change.summary(backup.as_deref()))
+            });
+            match result {
+                Ok(summary) => create_tool_result_block(&id, &summary, ToolResultStatus::Success),
+                Err(err) => create_tool_result_block(&id, &err.to_string(), ToolResultStatus::Error),
+            }
+        }).await?
+    }
+}
+
+fn planned_change(input: &Document) -> Result<FileChange> {
+    let path = match get_string(input, "path") {
+        Some(path) if !path.trim().is_empty() => PathBuf::from(path),
+        _ => bail!("path of the file to edit is not provided."),
+    };
+    let Some(original) = read_existing(&path)? else {
+        bail!("{} does not exist. Use WRITE_FILE to create it.", path.display())
+    };
+
+    let content = match (get_string(input, "patch"), get_string(input, "oldText")) {
+        (Some(patch), _) => apply_patch(&original, patch)?,
+        (None, Some(old_text)) => {
+            let Some(new_text) = get_string(input, "newText") else {
+                bail!("newText is not provided.")
+            };
+            replace_text(&original, old_text, new_text, get_bool(input, "replaceAll").unwrap_or(false))?
+        },
+        (None, None) => bail!("Either oldText and newText, or patch should be provided."),
+    };
+    if content == original {
+        bail!("The edit does not change {}.", path.display())
+    }
+    Ok(FileChange { path, original: Some(original), content })
+}
+
+fn replace_text(original: &str, old_text: &str, new_text: &str, replace_all: bool) -> Result<String> {
+    if old_text.is_empty() {
+        bail!("oldText should not be empty.")
+    }
+    match original.matches(old_text).count() {
+        0 => bail!("oldText was not found in the file. Read the file again and copy the text exactly, including white spaces and indentation."),
+        1 => Ok(original.replacen(old_text, new_text, 1)),
+        _ if replace_all => Ok(original.replace(old_text, new_text)),
+        count => bail!("oldText appears {count} times in the file. Include more surrounding lines to make it unique, or set replaceAll to true."),
+    }
+}
+
+fn apply_patch(original: &str, patch: &str) -> Result<String> {
+    // a patch without a trailing new line fails to parse
+    let patch = match patch.ends_with('\n') {
+        true => patch.to_owned(),
+        false => format!("{patch}\n"),
+    };
+    let patch = match Patch::from_str(&patch) {
+        Ok(patch) => patch,
+        Err(err) => bail!("invalid patch: {err}. Use oldText and newText instead."),
+    };
+    match diffy::apply(original, &patch) {
+        Ok(content) => Ok(content),
+        Err(err) => bail!("the patch does not apply to the file: {err}. Read the file again, or use oldText and newText instead."),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_a_unique_text() {
+        let content = replace_text("let a = 1;\nlet b = 2;\n", "b = 2", "b = 3", false).unwrap();
+        assert_eq!(content, "let a = 1;\nlet b = 3;\n");
+    }
+
+    #[test]
+    fn rejects_a_text_found_several_times_unless_replace_all() {
+        let err = replace_text("a a a", "a", "b", false).unwrap_err();
+        assert!(err.to_string().starts_with("oldText appears 3 times in the file."));
+        assert_eq!(replace_text("a a a", "a", "b", true).unwrap(), "b b b");
+    }
+
+    #[test]
+    fn rejects_a_missing_or_empty_text() {
+        let err = replace_text("let a = 1;", "let b", "let c", false).unwrap_err();
+        assert!(err.to_string().starts_with("oldText was not found in the file."));
+        let err = replace_text("let a = 1;", "", "let c", false).unwrap_err();
+        assert_eq!(err.to_string(), "oldText should not be empty.");
+    }
+
+    #[test]
+    fn applies_a_unified_diff() {
+        let original = "one\ntwo\nthree\n";
+        let patch = "--- a/file.txt\n+++ b/file.txt\n@@ -1,3 +1,3 @@\n one\n-two\n+2\n three";
+        assert_eq!(apply_patch(original, patch).unwrap(), "one\n2\nthree\n");
+    }
+
+    #[test]
+    fn rejects_a_patch_that_does_not_apply() {
+        let patch = "--- a/file.txt\n+++ b/file.txt\n@@ -1,3 +1,3 @@\n one\n-zwei\n+2\n three\n";
+        let err = apply_patch("one\ntwo\nthree\n", patch).unwrap_err();
+        assert!(err.to_string().starts_with("the patch does not apply to the file"));
+    }
+
+    #[test]
+    fn rejects_an_invalid_patch() {
+        let err = apply_patch("one\n", "@@ not a hunk @@\n").unwrap_err();
+        assert!(err.to_string().starts_with("invalid patch"));
+    }
+}
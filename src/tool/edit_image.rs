@@ -0,0 +1,292 @@
+use super::{create_tool_result_block, get_string, ToDocument, Tool, ToolJsonSchema};
+use super::permission::ToolPolicy;
+use super::generate_image::{append_seed, image_generation_config, invoke_image_model, save_generated_image};
+use super::image_generator_parameter::{ImageGeneratorParameter, ImageVariationParams, InPaintingParams, OutPaintingMode, OutPaintingParams};
+use super::image_model::{image_model_from_env, ImageModel, TITAN_MAX_SOURCE_DIMENSION};
+use super::image_provenance::ImageProvenance;
+use super::image_output::ImageOutput;
+use super::context::ToolContext;
+
+use std::io::Cursor;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use aws_sdk_bedrockruntime::Client;
+use aws_sdk_bedrockruntime::types::{ToolResultBlock, ToolResultStatus};
+use aws_smithy_types::Document;
+use base64::prelude::*;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use serde_json::json;
+use tokio::task;
+
+
+// EDIT_IMAGE tool
+pub const EDIT_IMAGE_NAME: &str = "EDIT_IMAGE";
+pub const EDIT_IMAGE_DESCRIPTION: &str = "Edit an existing local image. Replace the masked area of the image (inpainting), regenerate everything around the masked area (outpainting), or create variations of the image (variation).";
+
+pub fn edit_image_schema() -> Result<Document> {
+    let json_schema = json!({
+        "type": "object",
+        "properties": {
+            "task": {
+                "type": "string",
+                "description": "The editing task. Required. Possible value: inpainting, outpainting, variation."
+            },
+            "image": {
+                "type": "string",
+                "description": "The path of the image file to edit. Required."
+            },
+            "path": {
+                "type": "string",
+                "description": "The path of the folder where the edited image should be saved. Optional. Default to the folder of the source image."
+            },
+            "prompt": {
+                "type": "string",
+                "description": "Description of what to generate, at most 512 characters. Required for outpainting. Optional for inpainting, where an empty prompt removes the masked content, and for variation."
+            },
+            "negativeText": {
+                "type": "string",
+                "description": "What should not be in the image, for example `blurry, text`, at most 512 characters. Do not use negative words such as `no` in it. Optional."
+            },
+            "maskPrompt": {
+                "type": "string",
+                "description": "Description of the area of the image to mask, for example `the dog`. For inpainting and outpainting, either maskPrompt or maskImage is required."
+            },
+            "maskImage": {
+                "type": "string",
+                "description": "The path of a black and white mask image, black for the masked area. For inpainting and outpainting, either maskPrompt or maskImage is required."
+            },
+            "outPaintingMode": {
+                "type": "string",
+                "description": "For outpainting. Optional. Possible value: default, precise. precise keeps the masked area exactly as it is. The default value is default."
+            },
+            "similarityStrength": {
+                "type": "number",
+                "description": "For variation. How similar the variations are to the source image, from 0.2 to 1.0. Optional."
+            },
+            "numberOfImages": {
+                "type": "number",
+                "description": "The number of images to generate, from 1 to 5. Optional. The default value is 1."
+            },
+            "quality": {
+                "type": "string",
+                "description": "The quality of the image to generate. Optional. Possible value: standard, premium. The default value is standard."
+            },
+            "cfgScale": {
+                "type": "number",
+                "description": "How strongly the image follows the prompt, from 1.1 to 10.0. Optional. The default value is 8.0."
+            },
+            "seed": {
+                "type": "number",
+                "description": "Seed from 0 to 2147483646. The same seed and parameters give the same result. Optional. A random seed is used by default."
+            },
+        },
+        "required": ["task", "image"],
+    });
+    let document = ToolJsonSchema::new(json_schema)?.to_document();
+    Ok(document)
+}
+
+pub struct EditImageTool {
+    bedrock_client: Client,
+    image_model: Box<dyn ImageModel>,
+}
+
+impl EditImageTool {
+    pub fn new(client: &Client) -> Result<Self> {
+        Ok(Self {
+            bedrock_client: client.to_owned(),
+            image_model: image_model_from_env()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Tool for EditImageTool {
+    fn name(&self) -> &str {
+        EDIT_IMAGE_NAME
+    }
+
+    fn description(&self) -> &str {
+        EDIT_IMAGE_DESCRIPTION
+    }
+
+    fn input_schema(&self) -> Result<Document> {
+        edit_image_schema()
+    }
+
+    fn default_policy(&self) -> ToolPolicy {
+        ToolPolicy::Ask
+    }
+
+    fn preview(&self, input: &Document) -> String {
+        format!(
+            "Task: {}\nImage: {}\nPrompt: {}\nSave to: {} (created if missing)",
+            get_string(input, "task").unwrap_or(""),
+            get_string(input, "image").unwrap_or(""),
+            get_string(input, "prompt").unwrap_or(""),
+            output_folder(input)
+        )
+    }
+
+    async fn execute(&self, id: &str, input: &Document, context: &ToolContext) -> Result<ToolResultBlock> {
+        let input = &with_output_folder(input);
+        let parameters = {
+            let input = input.clone();
+            task::spawn_blocking(move || build_parameters(&input)).await?
+        };
+        // the size is checked before calling the model, like GENERATE_IMAGE checks the size to generate
+        let parameters = parameters.and_then(|(parameters, (width, height))| {
+            self.image_model.check_source_size(width, height)?;
+            Ok(parameters)
+        });
+        let parameters = match parameters {
+            Ok(parameters) => parameters,
+            Err(err) => {
+                return create_tool_result_block(id, &err.to_string(), ToolResultStatus::Error)
+            },
+        };
+
+        let images = match invoke_image_model(&self.bedrock_client, self.image_model.as_ref(), &parameters).await {
+            Ok(images) => images,
+            Err(err) => {
+                return create_tool_result_block(id, &err.to_string(), ToolResultStatus::Error)
+            },
+        };
+
+        let provenance = ImageProvenance::new(id, input, self.image_model.model_id(), &parameters, context);
+        let tool_result = {
+            let id = id.to_owned();
+            let input = input.clone();
+            let context = context.clone();
+            task::spawn_blocking(move || save_generated_image(&id, &input, images, &provenance, &ImageOutput::default(), &context)).await??
+        };
+        append_seed(tool_result, &parameters)
+    }
+}
+
+// the folder of the source image when path is not given
+fn output_folder(input: &Document) -> String {
+    if let Some(path) = get_string(input, "path") {
+        return path.to_owned();
+    }
+    match get_string(input, "image").and_then(|image| Path::new(image).parent()) {
+        Some(folder) if !folder.as_os_str().is_empty() => folder.display().to_string(),
+        _ => ".".to_owned(),
+    }
+}
+
+fn with_output_folder(input: &Document) -> Document {
+    let mut input = input.clone();
+    let folder = output_folder(&input);
+    if let Document::Object(object) = &mut input {
+        object.entry("path".to_owned()).or_insert(Document::String(folder));
+    }
+    input
+}
+
+// the parameters, and the size of the source image as it is sent
+fn build_parameters(input: &Document) -> Result<(ImageGeneratorParameter, (u32, u32))> {
+    let input_object = input.as_object().context("failed to convert input to object.")?;
+    let task = get_string(input, "task").context("task is not provided.")?;
+    let image_path = get_string(input, "image").context("image to edit is not provided.")?;
+    let prompt = get_string(input, "prompt").map(|prompt| prompt.to_owned());
+    let negative_text = get_string(input, "negativeText").map(|negative_text| negative_text.to_owned());
+    let mask_prompt = get_string(input, "maskPrompt").map(|mask_prompt| mask_prompt.to_owned());
+
+    let source = load_image(Path::new(image_path))?;
+    let (width, height) = (source.width(), source.height());
+    let image = encode_png_base64(&source)?;
+    let mask_image = match get_string(input, "maskImage") {
+        Some(mask_path) => {
+            // the mask has to be the same size as the image
+            let mask = image::open(mask_path).context(format!("failed to open mask image {mask_path}"))?;
+            Some(encode_png_base64(&mask.resize_exact(width, height, FilterType::Nearest))?)
+        },
+        None => None,
+    };
+
+    let image_config = Some(image_generation_config(input_object)?);
+    let parameters = match task.to_lowercase().as_str() {
+        "inpainting" => {
+            check_mask(&mask_prompt, &mask_image)?;
+            ImageGeneratorParameter::new_inpainting_params(
+                InPaintingParams { image, text: prompt, negative_text, mask_prompt, mask_image },
+                image_config
+            )
+        },
+        "outpainting" => {
+            check_mask(&mask_prompt, &mask_image)?;
+            let text = prompt.context("prompt is required for outpainting.")?;
+            let out_painting_mode = match get_string(input, "outPaintingMode").map(|mode| mode.to_lowercase()) {
+                Some(mode) if mode == "precise" => Some(OutPaintingMode::Precise),
+                Some(mode) if mode == "default" => Some(OutPaintingMode::Default),
+                Some(mode) => bail!("Unknown outPaintingMode: {mode}. Possible value: default, precise."),
+                None => None,
+            };
+            ImageGeneratorParameter::new_outpainting_params(
+                OutPaintingParams { image, text, negative_text, mask_prompt, mask_image, out_painting_mode },
+                image_config
+            )
+        },
+        "variation" => {
+            let similarity_strength = match input_object.get("similarityStrength").and_then(|strength| strength.as_number()) {
+                Some(strength) => {
+                    let strength = strength.to_f32_lossy();
+                    if !(0.2..=1.0).contains(&strength) {
+                        bail!("similarityStrength should be from 0.2 to 1.0.")
+                    }
+                    Some(strength)
+                },
+                None => None,
+            };
+            ImageGeneratorParameter::new_image_variation_params(
+                ImageVariationParams { images: vec![image], text: prompt, negative_text, similarity_strength },
+                image_config
+            )
+        },
+        _ => bail!("Unknown task: {task}. Possible value: inpainting, outpainting, variation."),
+    };
+    Ok((parameters, (width, height)))
+}
+
+fn check_mask(mask_prompt: &Option<String>, mask_image: &Option<String>) -> Result<()> {
+    match (mask_prompt, mask_image) {
+        (None, None) => bail!("Either maskPrompt or maskImage is required."),
+        (Some(_), Some(_)) => bail!("Only one of maskPrompt and maskImage can be used."),
+        _ => Ok(()),
+    }
+}
+
+// downscaled to the largest size Titan accepts
+fn load_image(path: &Path) -> Result<DynamicImage> {
+    let image = image::open(path).context(format!("failed to open image {}", path.display()))?;
+    if image.width() > TITAN_MAX_SOURCE_DIMENSION || image.height() > TITAN_MAX_SOURCE_DIMENSION {
+        return Ok(image.resize(TITAN_MAX_SOURCE_DIMENSION, TITAN_MAX_SOURCE_DIMENSION, FilterType::Lanczos3));
+    }
+    Ok(image)
+}
+
+fn encode_png_base64(image: &DynamicImage) -> Result<String> {
+    let mut bytes: Vec<u8> = vec![];
+    image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?;
+    Ok(BASE64_STANDARD.encode(bytes))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_next_to_the_source_image_by_default() {
+        let input = json!({ "task": "variation", "image": "photos/dog.png" }).to_document();
+        assert_eq!(get_string(&with_output_folder(&input), "path"), Some("photos"));
+        let input = json!({ "task": "variation", "image": "dog.png" }).to_document();
+        assert_eq!(get_string(&with_output_folder(&input), "path"), Some("."));
+        let input = json!({ "task": "variation", "image": "photos/dog.png", "path": "edited" }).to_document();
+        assert_eq!(get_string(&with_output_folder(&input), "path"), Some("edited"));
+    }
+}
@@ -0,0 +1,227 @@
+use super::context::ToolContext;
+use super::journal::Change;
+use super::ToValue;
+
+use std::{collections::VecDeque, fs, path::{Path, PathBuf}, sync::Mutex};
+use anyhow::{bail, Context, Result};
+use aws_smithy_types::Document;
+use diffy::DiffOptions;
+
+use crate::config::data_directory;
+use crate::terminal_service::colored_diff;
+
+
+// lines of the diff shown when asking for approval
+const PREVIEW_DIFF_LINES: usize = 200;
+// changes kept between the approval and the execution, the oldest are planned again if there are more
+const MAX_PLANNED_CHANGES: usize = 64;
+
+// Files are written one at a time, so two tools changing the same file cannot overwrite each other's change.
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+// The content a file will have after a WRITE_FILE or EDIT_FILE, computed before anything is written.
+#[derive(Clone, Debug)]
+pub struct FileChange {
+    pub path: PathBuf,
+    // None when the file does not exist yet
+    pub original: Option<String>,
+    pub content: String,
+}
+
+impl FileChange {
+    pub fn new(path: &Path, content: String) -> Result<Self> {
+        let original = read_existing(path)?;
+        Ok(Self { path: path.to_owned(), original, content })
+    }
+
+    pub fn diff(&self) -> String {
+        let name = self.path.display().to_string();
+        let original_name = match &self.original {
+            Some(_) => name.clone(),
+            None => "/dev/null".to_owned(),
+        };
+        DiffOptions::new()
+            .set_original_filename(original_name)
+            .set_modified_filename(name)
+            .create_patch(self.original.as_deref().unwrap_or(""), &self.content)
+            .to_string()
+    }
+
+    // The colored diff shown when asking for approval.
+    pub fn preview(&self) -> String {
+        if self.original.as_deref() == Some(self.content.as_str()) {
+            return format!("{}: no changes", self.path.display());
+        }
+        let action = match self.original {
+            Some(_) => "Modify",
+            None => "Create",
+        };
+        format!("{} {}\n{}", action, self.path.display(), colored_diff(&self.diff(), PREVIEW_DIFF_LINES))
+    }
+
+    // Writes the file, after copying the file it replaces to the backup folder of the conversation.
+    // Fails if the file changed since the change was planned, so the content written is the one the user reviewed.
+    pub fn write(&self, id: &str, context: &ToolContext) -> Result<Option<PathBuf>> {
+        let _lock = WRITE_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if read_existing(&self.path)? != self.original {
+            bail!("{} changed since this change was planned, so it was not written. Read the file again and retry.", self.path.display())
+        }
+        let backup = match self.original {
+            Some(_) => Some(backup_file(&self.path, id, context)?),
+            None => None,
+        };
+        if let Some(parent) = self.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                context.journal.create_dir_all(id, parent).context(format!("failed to create the folder {}", parent.display()))?;
+            }
+        }
+        fs::write(&self.path, &self.content).context(format!("failed to write {}", self.path.display()))?;
+        let change = match self.original {
+            Some(_) => Change::ModifiedFile(backup.clone()),
+            None => Change::CreatedFile,
+        };
+        context.journal.record(id, &self.path, change);
+        Ok(backup)
+    }
+
+    // what the tool reports back to the model
+    pub fn summary(&self, backup: Option<&Path>) -> String {
+        let (added, removed) = self.diff().lines()
+            .filter(|line| !line.starts_with("+++") && !line.starts_with("---"))
+            .fold((0, 0), |(added, removed), line| match line.chars().next() {
+                Some('+') => (added + 1, removed),
+                Some('-') => (added, removed + 1),
+                _ => (added, removed),
+            });
+        let mut summary = match self.original {
+            Some(_) => format!("File modified: {} ({} lines added, {} lines removed).", self.path.display(), added, removed),
+            None => format!("File created: {} ({} lines).", self.path.display(), self.content.lines().count()),
+        };
+        if let Some(backup) = backup {
+            summary.push_str(&format!(" The previous version is backed up to {}.", backup.display()));
+        }
+        summary
+    }
+}
+
+// The changes shown when asking for approval, keyed by the tool input, so the change written is the one approved.
+#[derive(Debug, Default)]
+pub struct PlannedChanges {
+    changes: Mutex<VecDeque<(String, FileChange)>>,
+}
+
+impl PlannedChanges {
+    pub fn insert(&self, input: &Document, change: FileChange) {
+        let mut changes = self.changes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if changes.len() >= MAX_PLANNED_CHANGES {
+            changes.pop_front();
+        }
+        changes.push_back((input.to_value().to_string(), change));
+    }
+
+    // the change planned for the input, if it was previewed
+    pub fn take(&self, input: &Document) -> Option<FileChange> {
+        let key = input.to_value().to_string();
+        let mut changes = self.changes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let index = changes.iter().position(|(planned, _)| *planned == key)?;
+        changes.remove(index).map(|(_, change)| change)
+    }
+}
+
+// None when the file does not exist. Only text files can be changed.
+pub fn read_existing(path: &Path) -> Result<Option<String>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    if path.is_dir() {
+        bail!("{} is a directory.", path.display())
+    }
+    let bytes = fs::read(path).context(format!("failed to read {}", path.display()))?;
+    match String::from_utf8(bytes) {
+        Ok(text) => Ok(Some(text)),
+        Err(_) => bail!("{} is not a UTF-8 text file and cannot be changed.", path.display()),
+    }
+}
+
+// `~/.local/share/bedrock_assistant/backups/<conversation id>` on Linux
+pub fn backup_directory(context: &ToolContext) -> PathBuf {
+    data_directory().join("backups").join(&context.conversation_id)
+}
+
+// Copies the file to `<backup directory>/<tool use id>/<file name>`, with `2-`, `3-`... added before the name
+// when a tool replaces several files with the same name.
+pub fn backup_file(path: &Path, id: &str, context: &ToolContext) -> Result<PathBuf> {
+    let directory = backup_directory(context).join(id);
+    fs::create_dir_all(&directory).context(format!("failed to create the backup folder {}", directory.display()))?;
+    let file_name = path.file_name().context(format!("{} is not a file", path.display()))?.to_string_lossy();
+    let mut backup = directory.join(file_name.as_ref());
+    let mut count = 2;
+    while backup.exists() {
+        backup = directory.join(format!("{count}-{file_name}"));
+        count += 1;
+    }
+    fs::copy(path, &backup).context(format!("failed to back up {}", path.display()))?;
+    Ok(backup)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    use crate::image_preview::ImagePreview;
+    use crate::tool::context::OpenArtifacts;
+    use crate::tool::journal::Journal;
+
+    fn context() -> ToolContext {
+        ToolContext {
+            conversation_id: "test".to_owned(),
+            image_preview: ImagePreview::Off,
+            open_artifacts: OpenArtifacts::Never,
+            journal: Journal::new(),
+        }
+    }
+
+    #[test]
+    fn writes_the_planned_content() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("notes/todo.md");
+        let change = FileChange::new(&path, "- [ ] review\n".to_owned()).unwrap();
+        let context = context();
+        assert_eq!(change.write("tool_1", &context).unwrap(), None);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "- [ ] review\n");
+        let changes: Vec<Change> = context.journal.entries().into_iter().map(|entry| entry.change).collect();
+        assert_eq!(changes, [Change::CreatedDirectory, Change::CreatedFile]);
+    }
+
+    #[test]
+    fn refuses_to_write_a_file_changed_since_the_change_was_planned() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("todo.md");
+        let first = FileChange::new(&path, "first\n".to_owned()).unwrap();
+        let second = FileChange::new(&path, "second\n".to_owned()).unwrap();
+        let context = context();
+        first.write("tool_1", &context).unwrap();
+
+        let err = second.write("tool_2", &context).unwrap_err();
+        assert!(err.to_string().ends_with("changed since this change was planned, so it was not written. Read the file again and retry."));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first\n");
+        assert_eq!(context.journal.entries().len(), 1);
+    }
+
+    #[test]
+    fn keeps_the_previewed_change_until_it_is_taken() {
+        let input = |content: &str| Document::Object(HashMap::from([
+            ("path".to_owned(), Document::String("todo.md".to_owned())),
+            ("content".to_owned(), Document::String(content.to_owned())),
+        ]));
+        let planned = PlannedChanges::default();
+        planned.insert(&input("a"), FileChange { path: PathBuf::from("todo.md"), original: None, content: "a".to_owned() });
+
+        assert!(planned.take(&input("b")).is_none());
+        assert_eq!(planned.take(&input("a")).unwrap().content, "a");
+        assert!(planned.take(&input("a")).is_none());
+    }
+}
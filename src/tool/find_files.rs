@@ -0,0 +1,262 @@
+use super::{create_tool_result_block, get_bool, get_number, get_string, ToDocument, Tool, ToolJsonSchema};
+use super::context::ToolContext;
+use super::list_directory::{describe_entry, walk_builder, MAX_DEPTH};
+
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use aws_sdk_bedrockruntime::types::{ToolResultBlock, ToolResultStatus};
+use aws_smithy_types::Document;
+use globset::{GlobBuilder, GlobMatcher};
+use serde_json::json;
+use tokio::task;
+
+
+// FIND_FILES tool
+pub const FIND_FILES_NAME: &str = "FIND_FILES";
+pub const FIND_FILES_DESCRIPTION: &str = "Find files matching a glob pattern, such as `*.pdf` or `src/**/*.rs`, in a directory and its sub folders. Returns their sizes and modification times. Use this to find the files to read when their exact paths are not known. Files ignored by .gitignore and hidden files are left out by default.";
+
+pub const DEFAULT_MAX_RESULTS: usize = 200;
+pub const MAX_RESULTS: usize = 1000;
+
+pub fn find_files_schema() -> Result<Document> {
+    let json_schema = json!({
+        "type": "object",
+        "properties": {
+            "pattern": {
+                "type": "string",
+                "description": "The glob pattern. A pattern without `/` matches file names in any sub folder, for example `*.pdf`. A pattern with `/` matches paths relative to the directory, for example `reports/**/*.pdf`. `{a,b}` matches either a or b."
+            },
+            "path": {
+                "type": "string",
+                "description": "The path of the directory to search in. Default to the current working directory."
+            },
+            "maxDepth": {
+                "type": "number",
+                "description": format!("How many levels of sub folders to search. Optional. The default value is {MAX_DEPTH}, which is also the most allowed.")
+            },
+            "maxResults": {
+                "type": "number",
+                "description": format!("The maximum number of files to return. Optional. The default value is {DEFAULT_MAX_RESULTS}, at most {MAX_RESULTS}.")
+            },
+            "includeIgnored": {
+                "type": "boolean",
+                "description": "Include files ignored by .gitignore and hidden files. Optional. The default value is false."
+            },
+        },
+        "required": ["pattern"],
+    });
+    let document = ToolJsonSchema::new(json_schema)?.to_document();
+    Ok(document)
+}
+
+pub struct FindFilesTool;
+
+#[async_trait]
+impl Tool for FindFilesTool {
+    fn name(&self) -> &str {
+        FIND_FILES_NAME
+    }
+
+    fn description(&self) -> &str {
+        FIND_FILES_DESCRIPTION
+    }
+
+    fn input_schema(&self) -> Result<Document> {
+        find_files_schema()
+    }
+
+    async fn execute(&self, id: &str, input: &Document, _context: &ToolContext) -> Result<ToolResultBlock> {
+        let id = id.to_owned();
+        let input = input.clone();
+        task::spawn_blocking(move || {
+            match find_files(&input) {
+                Ok(content) => create_tool_result_block(&id, &content, ToolResultStatus::Success),
+                Err(err) => create_tool_result_block(&id, &err.to_string(), ToolResultStatus::Error),
+            }
+        }).await?
+    }
+}
+
+fn find_files(input: &Document) -> Result<String> {
+    let pattern = match get_string(input, "pattern") {
+        Some(pattern) if !pattern.trim().is_empty() => pattern.trim(),
+        _ => bail!("pattern to find files with is not provided."),
+    };
+    let path = PathBuf::from(get_string(input, "path").unwrap_or("."));
+    if !path.is_dir() {
+        bail!("{} is not a directory.", path.display())
+    }
+    let max_depth = match get_number(input, "maxDepth") {
+        Some(depth) if depth < 1.0 => bail!("maxDepth should be 1 or more."),
+        Some(depth) => (depth as usize).min(MAX_DEPTH),
+        None => MAX_DEPTH,
+    };
+    let max_results = match get_number(input, "maxResults") {
+        Some(max_results) if max_results < 1.0 => bail!("maxResults should be 1 or more."),
+        Some(max_results) => (max_results as usize).min(MAX_RESULTS),
+        None => DEFAULT_MAX_RESULTS,
+    };
+    let include_ignored = get_bool(input, "includeIgnored").unwrap_or(false);
+
+    let pattern = pattern.trim_start_matches("./");
+    let path_pattern = PathPattern::new(pattern)?;
+
+    let mut lines: Vec<String> = vec![];
+    let mut total = 0;
+    for entry in walk_builder(&path, Some(max_depth), include_ignored).build() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(&path).unwrap_or(entry.path());
+        if !path_pattern.is_match(relative) {
+            continue;
+        }
+        total += 1;
+        if lines.len() >= max_results {
+            continue;
+        }
+        if let Ok(metadata) = entry.metadata() {
+            lines.push(describe_entry(&path.join(relative), &metadata));
+        }
+    }
+
+    if total == 0 {
+        return Ok(format!("No files matching {} found in {}.", pattern, path.display()));
+    }
+    let mut content = format!("Found {} files matching {} in {} (size, modified, path).\n{}", total, pattern, path.display(), lines.join("\n"));
+    if total > lines.len() {
+        content.push_str(&format!("\n... {} more files not shown. Use a more specific pattern or a larger maxResults to see them.", total - lines.len()));
+    }
+    Ok(content)
+}
+
+// A glob without `/` matches file names in any folder, one with `/` matches the path relative to the searched directory.
+pub struct PathPattern {
+    matcher: GlobMatcher,
+    match_file_name: bool,
+}
+
+impl PathPattern {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let pattern = pattern.trim().trim_start_matches("./");
+        let matcher = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .context(format!("invalid glob pattern: {pattern}"))?
+            .compile_matcher();
+        Ok(Self { matcher, match_file_name: !pattern.contains('/') })
+    }
+
+    pub fn is_match(&self, relative: &Path) -> bool {
+        match self.match_file_name {
+            true => relative.file_name().is_some_and(|name| self.matcher.is_match(name)),
+            false => self.matcher.is_match(relative),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use serde_json::Value;
+    use tempfile::TempDir;
+
+    // the paths found relative to the directory, without the header and the sizes
+    fn found(content: &str, directory: &Path) -> Vec<String> {
+        let prefix = format!("{}/", directory.display());
+        content.lines().skip(1)
+            .filter(|line| !line.starts_with("..."))
+            .filter_map(|line| line.split("  ").last())
+            .map(|path| path.trim_start_matches(&prefix).to_owned())
+            .collect()
+    }
+
+    fn find(directory: &Path, pattern: &str, mut options: Value) -> Result<String> {
+        options["path"] = json!(directory);
+        options["pattern"] = json!(pattern);
+        find_files(&options.to_document())
+    }
+
+    fn create(directory: &Path, paths: &[&str]) {
+        for path in paths {
+            let path = directory.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+    }
+
+    #[test]
+    fn matches_a_pattern_without_slash_against_file_names() {
+        let directory = TempDir::new().unwrap();
+        create(directory.path(), &["a.pdf", "docs/b.pdf", "docs/b.pdf.txt", "reports/2024/c.pdf"]);
+
+        let content = find(directory.path(), "*.pdf", json!({})).unwrap();
+        assert_eq!(found(&content, directory.path()), ["a.pdf", "docs/b.pdf", "reports/2024/c.pdf"]);
+    }
+
+    #[test]
+    fn matches_a_pattern_with_slash_against_the_relative_path() {
+        let directory = TempDir::new().unwrap();
+        create(directory.path(), &["reports/q1.csv", "reports/2024/q2.csv", "data/reports/q3.csv", "q4.csv"]);
+
+        let content = find(directory.path(), "reports/**/*.csv", json!({})).unwrap();
+        assert_eq!(found(&content, directory.path()), ["reports/2024/q2.csv", "reports/q1.csv"]);
+        let content = find(directory.path(), "./reports/*.csv", json!({})).unwrap();
+        assert_eq!(found(&content, directory.path()), ["reports/q1.csv"]);
+    }
+
+    #[test]
+    fn caps_the_number_of_results() {
+        let directory = TempDir::new().unwrap();
+        let paths: Vec<String> = (0..DEFAULT_MAX_RESULTS + 5).map(|index| format!("{index:04}.csv")).collect();
+        create(directory.path(), &paths.iter().map(String::as_str).collect::<Vec<_>>());
+
+        let content = find(directory.path(), "*.csv", json!({})).unwrap();
+        assert_eq!(found(&content, directory.path()).len(), DEFAULT_MAX_RESULTS);
+        assert!(content.starts_with(&format!("Found {} files", DEFAULT_MAX_RESULTS + 5)));
+        assert!(content.ends_with("... 5 more files not shown. Use a more specific pattern or a larger maxResults to see them."));
+
+        let content = find(directory.path(), "*.csv", json!({ "maxResults": 3 })).unwrap();
+        assert_eq!(found(&content, directory.path()), ["0000.csv", "0001.csv", "0002.csv"]);
+    }
+
+    #[test]
+    fn caps_the_depth() {
+        let directory = TempDir::new().unwrap();
+        create(directory.path(), &["1/top.txt", "1/2/3/deep.txt"]);
+        let deepest: PathBuf = (0..MAX_DEPTH).map(|level| level.to_string()).collect();
+        create(directory.path(), &[deepest.join("too_deep.txt").to_str().unwrap()]);
+
+        let content = find(directory.path(), "*.txt", json!({ "maxDepth": 2 })).unwrap();
+        assert_eq!(found(&content, directory.path()), ["1/top.txt"]);
+        let content = find(directory.path(), "*.txt", json!({ "maxDepth": 100 })).unwrap();
+        assert_eq!(found(&content, directory.path()), ["1/2/3/deep.txt", "1/top.txt"]);
+    }
+
+    #[test]
+    fn leaves_out_hidden_and_ignored_files_by_default() {
+        let directory = TempDir::new().unwrap();
+        fs::write(directory.path().join(".gitignore"), "build/\n").unwrap();
+        create(directory.path(), &["main.rs", "build/out.rs", ".cache/old.rs"]);
+
+        let content = find(directory.path(), "*.rs", json!({})).unwrap();
+        assert_eq!(found(&content, directory.path()), ["main.rs"]);
+        let content = find(directory.path(), "*.rs", json!({ "includeIgnored": true })).unwrap();
+        assert_eq!(found(&content, directory.path()), [".cache/old.rs", "build/out.rs", "main.rs"]);
+    }
+
+    #[test]
+    fn reports_when_nothing_matches() {
+        let directory = TempDir::new().unwrap();
+        create(directory.path(), &["notes.md"]);
+        let content = find(directory.path(), "*.pdf", json!({})).unwrap();
+        assert_eq!(content, format!("No files matching *.pdf found in {}.", directory.path().display()));
+    }
+}
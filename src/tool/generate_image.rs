@@ -1,29 +1,44 @@
-use super::{create_tool_result_block, ToDocument, ToolJsonSchema};
+use super::{append_tool_result_content, create_tool_result_block, get_string, ToDocument, Tool, ToolJsonSchema};
+use super::permission::ToolPolicy;
+use super::image_generator_parameter::{IamgeQuality, ImageGenerationConfig, ImageGeneratorParameter, TaskType, MAX_SEED};
+use super::image_model::{image_model_from_env, ImageModel};
+use super::image_provenance::{unique_image_path, ImageProvenance};
+use super::image_output::{ImageOutput, DEFAULT_JPEG_QUALITY};
+use super::context::ToolContext;
+use super::journal::Change;
 
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher, RandomState};
 use std::path::Path;
-use std::fs::{self};
 
-use anyhow::Result;
-use aws_sdk_bedrockruntime::types::{ToolResultBlock, ToolResultStatus};
-use aws_smithy_types::Document;
-use serde_json::json;
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use aws_sdk_bedrockruntime::Client;
+use aws_sdk_bedrockruntime::types::{ToolResultBlock, ToolResultContentBlock, ToolResultStatus};
+use aws_smithy_types::{Blob, Document};
+use serde_json::{json, Value};
 use base64::prelude::*;
+use tokio::task;
+
+use crate::terminal_service::TerminalService;
 
 
 // GENERATE_IMAGE tool
 pub const GENERATE_IMAGE_NAME: &str = "GENERATE_IMAGE";
 pub const GENERATE_IMAGE_DESCRIPTION: &str = "Generate an image based on user's prompt.";
 
-pub const DEFAULT_HEIGHT: u128 = 512;
-pub const DEFAULT_WIDTH: u128 = 512;
-
-pub fn generate_image_schema() -> Result<Document> {
+// the default size depends on the image model
+pub fn generate_image_schema((default_width, default_height): (u128, u128)) -> Result<Document> {
     let json_schema = json!({
         "type": "object",
         "properties": {
             "prompt": {
                 "type": "string",
-                "description": "Description for the image to generate. Required."
+                "description": "Description for the image to generate, at most 512 characters. Required."
+            },
+            "negativeText": {
+                "type": "string",
+                "description": "What should not be in the image, for example `blurry, text`, at most 512 characters. Do not use negative words such as `no` in it. Optional."
             },
             "path": {
                 "type": "string",
@@ -31,19 +46,47 @@ pub fn generate_image_schema() -> Result<Document> {
             },
             "numberOfImages": {
                 "type": "number",
-                "description": "The number of images to generate. Optional. The default value is 1."
+                "description": "The number of images to generate, from 1 to 5. Optional. The default value is 1."
             },
             "quality": {
                 "type": "string",
                 "description": "The quality of the image to generate. Optional. Possible value: standard, premium. The default value is standard."
             },
+            "cfgScale": {
+                "type": "number",
+                "description": "How strongly the image follows the prompt, from 1.1 to 10.0. Optional. The default value is 8.0."
+            },
+            "seed": {
+                "type": "number",
+                "description": "Seed from 0 to 2147483646. The same seed and parameters generate the same image, so use the seed of an earlier result to regenerate or slightly modify it. Optional. A random seed is used by default."
+            },
             "height": {
                 "type": "number",
-                "description": "The height of the image in pixels. Optional. The default value is ".to_owned() + &DEFAULT_HEIGHT.to_string() + " .",
+                "description": "The height of the image in pixels. Optional. The default value is ".to_owned() + &default_height.to_string() + " .",
             },
             "width": {
                 "type": "number",
-                "description": "The width of the image in pixels. Optional. The default value is ".to_owned() + &DEFAULT_WIDTH.to_string() + " .",
+                "description": "The width of the image in pixels. Optional. The default value is ".to_owned() + &default_width.to_string() + " . Supported sizes depend on the model, for example 512x512, 1024x1024, 1152x768 or 768x1152.",
+            },
+            "format": {
+                "type": "string",
+                "description": "The file format to save the image in. Optional. Possible value: png, jpeg, webp. The default value is png."
+            },
+            "jpegQuality": {
+                "type": "number",
+                "description": "The quality of a jpeg image, from 1 to 100. Optional. The default value is ".to_owned() + &DEFAULT_JPEG_QUALITY.to_string() + " .",
+            },
+            "targetWidth": {
+                "type": "number",
+                "description": "Resize and center crop the generated image to exactly this width in pixels, for sizes the model does not support. Generate at a supported size with a similar aspect ratio. Optional. Requires targetHeight."
+            },
+            "targetHeight": {
+                "type": "number",
+                "description": "Resize and center crop the generated image to exactly this height in pixels. Optional. Requires targetWidth."
+            },
+            "thumbnailSize": {
+                "type": "number",
+                "description": "Also save a thumbnail of at most this many pixels on each side, named after the image with `-thumbnail`. Optional."
             },
 
         },
@@ -53,7 +96,187 @@ pub fn generate_image_schema() -> Result<Document> {
     Ok(document)
 }
 
-pub fn save_generated_image(id: &str, input: &Document, images: Vec<String>) -> Result<ToolResultBlock> {
+pub struct GenerateImageTool {
+    bedrock_client: Client,
+    image_model: Box<dyn ImageModel>,
+}
+
+impl GenerateImageTool {
+    pub fn new(client: &Client) -> Result<Self> {
+        Ok(Self {
+            bedrock_client: client.to_owned(),
+            image_model: image_model_from_env()?,
+        })
+    }
+
+    fn build_parameters(&self, input: &Document) -> Result<ImageGeneratorParameter> {
+        let input_object = input.as_object().context("failed to convert input to object.")?;
+        let prompt = input_object.get("prompt")
+            .context("prompt is not provided.")?
+            .as_string().context("prompt is not string")?;
+
+        let _ = input_object.get("path")
+            .context("path is not provided.")?
+            .as_string().context("path is not string")?;
+
+        let (default_width, default_height) = self.image_model.default_size();
+        let mut image_config = image_generation_config(input_object)?;
+        image_config.height = match input_object.get("height").unwrap_or(&Document::Null).as_number() {
+            Some(height) => Some(height.to_f32_lossy() as u128),
+            None => Some(default_height),
+        };
+        image_config.width = match input_object.get("width").unwrap_or(&Document::Null).as_number() {
+            Some(width) => Some(width.to_f32_lossy() as u128),
+            None => Some(default_width),
+        };
+
+        let negative_text = get_string(input, "negativeText").map(|text| text.to_owned());
+        let parameters: ImageGeneratorParameter = ImageGeneratorParameter::new_generate_image_params(
+            prompt,
+            negative_text,
+            Some(image_config));
+        // println!("{:?}", parameters);
+
+        Ok(parameters)
+    }
+}
+
+// number of images, quality, cfg scale and seed, the size is only used when generating from text
+pub fn image_generation_config(input_object: &HashMap<String, Document>) -> Result<ImageGenerationConfig> {
+    let number_of_images = match input_object.get("numberOfImages").unwrap_or(&Document::Null).as_number() {
+        Some(count) => count.to_f32_lossy() as u8,
+        None => 1,
+    };
+    let quality = match input_object.get("quality").unwrap_or(&Document::Null).as_string() {
+        Some(q) => serde_json::from_value(Value::String(q.to_lowercase())).unwrap_or(IamgeQuality::Standard),
+        None => IamgeQuality::Standard,
+    };
+
+    let cfg_scale = input_object.get("cfgScale")
+        .and_then(|cfg_scale| cfg_scale.as_number())
+        .map(|cfg_scale| cfg_scale.to_f32_lossy());
+    // a seed is always set, so it can be returned to regenerate the same image
+    let seed = match input_object.get("seed").and_then(|seed| seed.as_number()) {
+        Some(seed) => {
+            let seed = seed.to_f64_lossy();
+            if seed.fract() != 0.0 || !(0.0..=MAX_SEED as f64).contains(&seed) {
+                bail!("seed should be an integer from 0 to {MAX_SEED}.")
+            }
+            seed as u32
+        },
+        None => random_seed(),
+    };
+
+    Ok(ImageGenerationConfig {
+        number_of_images: Some(number_of_images),
+        quality: Some(quality),
+        height: None,
+        width: None,
+        cfg_scale,
+        seed: Some(seed),
+    })
+}
+
+fn random_seed() -> u32 {
+    let random = RandomState::new().build_hasher().finish();
+    (random % (MAX_SEED as u64 + 1)) as u32
+}
+
+// return an array of base64 image string
+pub async fn invoke_image_model(client: &Client, model: &dyn ImageModel, parameters: &ImageGeneratorParameter) -> Result<Vec<String>> {
+    let request_body = model.request_body(parameters)?;
+
+    let builder = client
+        .invoke_model()
+        .model_id(model.model_id())
+        .content_type("application/json")
+        .body(Blob::new(request_body));
+
+    let response = builder
+        .send()
+        .await?;
+    let body = response.body().clone().into_inner();
+    let base64_image_array = model.images_from_response(&body)?;
+
+    // println!("Image count: {}", base64_image_array.clone().len());
+    Ok(base64_image_array)
+}
+
+#[async_trait]
+impl Tool for GenerateImageTool {
+    fn name(&self) -> &str {
+        GENERATE_IMAGE_NAME
+    }
+
+    fn description(&self) -> &str {
+        GENERATE_IMAGE_DESCRIPTION
+    }
+
+    fn input_schema(&self) -> Result<Document> {
+        generate_image_schema(self.image_model.default_size())
+    }
+
+    fn default_policy(&self) -> ToolPolicy {
+        ToolPolicy::Ask
+    }
+
+    fn preview(&self, input: &Document) -> String {
+        format!(
+            "Prompt: {}\nSave to: {} (created if missing)",
+            get_string(input, "prompt").unwrap_or(""),
+            get_string(input, "path").unwrap_or("")
+        )
+    }
+
+    async fn execute(&self, id: &str, input: &Document, context: &ToolContext) -> Result<ToolResultBlock> {
+        let parameters = match self.build_parameters(input) {
+            Ok(parameters) => parameters,
+            Err(err) => {
+                return create_tool_result_block(id, &err.to_string(), ToolResultStatus::Error)
+            },
+        };
+        let output = match ImageOutput::from_input(input) {
+            Ok(output) => output,
+            Err(err) => {
+                return create_tool_result_block(id, &err.to_string(), ToolResultStatus::Error)
+            },
+        };
+        let images = match invoke_image_model(&self.bedrock_client, self.image_model.as_ref(), &parameters).await {
+            Ok(images) => images,
+            Err(err) => {
+                return create_tool_result_block(id, &err.to_string(), ToolResultStatus::Error)
+            },
+        };
+
+        let provenance = ImageProvenance::new(id, input, self.image_model.model_id(), &parameters, context);
+        let tool_result = {
+            let id = id.to_owned();
+            let input = input.clone();
+            let context = context.clone();
+            task::spawn_blocking(move || save_generated_image(&id, &input, images, &provenance, &output, &context)).await??
+        };
+        append_seed(tool_result, &parameters)
+    }
+}
+
+// tells the model the seed used, so the image can be regenerated exactly
+pub fn append_seed(tool_result: ToolResultBlock, parameters: &ImageGeneratorParameter) -> Result<ToolResultBlock> {
+    let seed = parameters.image_generation_config.as_ref().and_then(|config| config.seed);
+    let Some(seed) = seed else {
+        return Ok(tool_result);
+    };
+    if tool_result.status() == Some(&ToolResultStatus::Error) {
+        return Ok(tool_result);
+    }
+    let text = match parameters.task_type {
+        TaskType::TextImage => format!("Seed: {seed}. Use the same seed and parameters to regenerate the image."),
+        _ => format!("Seed: {seed}. Use the same seed and parameters to redo the edit."),
+    };
+    append_tool_result_content(tool_result, vec![ToolResultContentBlock::Text(text)])
+}
+
+// Saves the images with file names from the prompt, each with a provenance sidecar.
+pub fn save_generated_image(id: &str, input: &Document, images: Vec<String>, provenance: &ImageProvenance, output: &ImageOutput, context: &ToolContext) -> Result<ToolResultBlock> {
     let input_object = match input.as_object() {
         Some(object) => object,
         None => {
@@ -86,7 +309,7 @@ pub fn save_generated_image(id: &str, input: &Document, images: Vec<String>) ->
     }
 
     if !path.to_str().unwrap_or("").is_empty() {
-        match fs::create_dir_all(path) {
+        match context.journal.create_dir_all(id, path) {
             Ok(_) => {},
             Err(err) => {
                 return create_tool_result_block(id, &err.to_string(), ToolResultStatus::Error)
@@ -94,14 +317,14 @@ pub fn save_generated_image(id: &str, input: &Document, images: Vec<String>) ->
         };
     }
 
-    match open::that_detached(path) {
-        Ok(_) => {},
-        Err(_) => {},
-    };
+    context.open_folder(path);
 
+    let stem = provenance.file_stem();
+    let count = images.len();
+    let mut saved: Vec<String> = vec![];
     for (index, image_string) in images.into_iter().enumerate() {
-        let image_name = Path::new(&format!("{}-{}.png", id, index)).to_owned();
-        let image_path = path.join(image_name);
+        let image_stem = if count > 1 { format!("{}-{}", stem, index + 1) } else { stem.clone() };
+        let image_path = unique_image_path(path, &image_stem, output.format.extension());
 
         let bytes = BASE64_STANDARD.decode(image_string)?;
         let image = match image::load_from_memory(&bytes) {
@@ -111,17 +334,36 @@ pub fn save_generated_image(id: &str, input: &Document, images: Vec<String>) ->
             },
         };
 
-        match image.save(&image_path) {
-            Ok(_) => {},
+        let image = output.process(image);
+        match output.save(&image, &image_path) {
+            Ok(_) => context.journal.record(id, &image_path, Change::CreatedFile),
             Err(err) => {
                 return create_tool_result_block(id, &err.to_string(), ToolResultStatus::Error)
             },
         };
-
-        match open::that_detached(image_path) {
-            Ok(_) => {},
-            Err(_) => {},
+        let thumbnail_path = match output.save_thumbnail(&image, &image_path) {
+            Ok(thumbnail_path) => {
+                if let Some(thumbnail_path) = &thumbnail_path {
+                    context.journal.record(id, thumbnail_path, Change::CreatedFile);
+                }
+                thumbnail_path
+            },
+            Err(err) => {
+                return create_tool_result_block(id, &format!("Image saved to {}, but failed to save its thumbnail: {}", image_path.display(), err), ToolResultStatus::Error)
+            },
         };
+
+        if let Err(err) = provenance.save(&image_path) {
+            return create_tool_result_block(id, &format!("Image saved to {}, but failed to save its provenance: {}", image_path.display(), err), ToolResultStatus::Error)
+        }
+        context.journal.record(id, &image_path.with_extension("json"), Change::CreatedFile);
+
+        match thumbnail_path {
+            Some(thumbnail_path) => saved.push(format!("{} (thumbnail: {})", image_path.display(), thumbnail_path.display())),
+            None => saved.push(image_path.display().to_string()),
+        }
+        let _ = TerminalService::new().log_image_preview(&image_path, context.image_preview);
+        context.open_file(&image_path);
     }
-    return create_tool_result_block(id, "Image generated and saved.", ToolResultStatus::Success)
+    create_tool_result_block(id, &format!("Image generated and saved: {}.", saved.join(", ")), ToolResultStatus::Success)
 }
@@ -0,0 +1,84 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use anyhow::{Context, Result};
+use aws_sdk_bedrockruntime::types::{ImageBlock, ImageFormat, ImageSource};
+use aws_smithy_types::Blob;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageFormat as FileFormat};
+use resvg::{tiny_skia, usvg};
+
+
+// Claude downscales anything larger, and rejects images over 3.75 MB
+pub const MAX_IMAGE_DIMENSION: u32 = 1568;
+pub const MAX_IMAGE_BYTES: usize = 3_750_000;
+
+pub const IMAGE_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "gif", "webp", "svg"];
+
+pub fn is_image_path(path: &Path) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+// Reads an image file, rasterising SVGs, and downscales it so it can be sent to the model.
+pub fn image_block_from_path(path: &Path) -> Result<ImageBlock> {
+    image_block_from_image(&load_image(path)?)
+}
+
+// Reads an image file, rasterising SVGs.
+pub fn load_image(path: &Path) -> Result<DynamicImage> {
+    let bytes = fs::read(path)?;
+    let is_svg = path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"));
+
+    let image = if is_svg {
+        rasterise_svg(&bytes)?
+    } else {
+        image::load_from_memory(&bytes)?
+    };
+    Ok(image)
+}
+
+pub fn image_block_from_image(image: &DynamicImage) -> Result<ImageBlock> {
+    let image = if image.width() > MAX_IMAGE_DIMENSION || image.height() > MAX_IMAGE_DIMENSION {
+        image.resize(MAX_IMAGE_DIMENSION, MAX_IMAGE_DIMENSION, image::imageops::FilterType::Lanczos3)
+    } else {
+        image.clone()
+    };
+
+    let mut bytes: Vec<u8> = vec![];
+    image.write_to(&mut Cursor::new(&mut bytes), FileFormat::Png)?;
+    let mut format = ImageFormat::Png;
+
+    // photos compress much better as jpeg
+    if bytes.len() > MAX_IMAGE_BYTES {
+        bytes.clear();
+        let encoder = JpegEncoder::new_with_quality(&mut bytes, 85);
+        image.to_rgb8().write_with_encoder(encoder)?;
+        format = ImageFormat::Jpeg;
+    }
+
+    let image_block = ImageBlock::builder()
+        .format(format)
+        .source(ImageSource::Bytes(Blob::new(bytes)))
+        .build()?;
+    Ok(image_block)
+}
+
+fn rasterise_svg(bytes: &[u8]) -> Result<DynamicImage> {
+    let tree = usvg::Tree::from_data(bytes, &usvg::Options::default())?;
+    let size = tree.size();
+    let scale = (MAX_IMAGE_DIMENSION as f32 / size.width().max(size.height())).min(1.0);
+    let width = ((size.width() * scale).ceil() as u32).max(1);
+    let height = ((size.height() * scale).ceil() as u32).max(1);
+
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).context("failed to create an image for the svg")?;
+    pixmap.fill(tiny_skia::Color::WHITE);
+    resvg::render(&tree, tiny_skia::Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+
+    let png = pixmap.encode_png()?;
+    Ok(image::load_from_memory(&png)?)
+}
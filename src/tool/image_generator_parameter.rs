@@ -1,19 +1,120 @@
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 
+pub const MAX_SEED: u32 = 2_147_483_646;
+pub const MAX_NUMBER_OF_IMAGES: u8 = 5;
+pub const MIN_CFG_SCALE: f32 = 1.1;
+pub const MAX_CFG_SCALE: f32 = 10.0;
+pub const MAX_TEXT_LENGTH: usize = 512;
+
+// width * height supported by Titan Image Generator v1
+pub const TITAN_V1_SIZES: [(u128, u128); 25] = [
+    (1024, 1024), (768, 768), (512, 512),
+    (768, 1152), (384, 576), (1152, 768), (576, 384),
+    (768, 1280), (384, 640), (1280, 768), (640, 384),
+    (896, 1152), (448, 576), (1152, 896), (576, 448),
+    (768, 1408), (384, 704), (1408, 768), (704, 384),
+    (640, 1408), (320, 704), (1408, 640), (704, 320),
+    (1152, 640), (1173, 640),
+];
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ImageGeneratorParameter {
     pub task_type: TaskType,
-    pub text_to_image_params: TextToImageParams,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_to_image_params: Option<TextToImageParams>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_painting_params: Option<InPaintingParams>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub out_painting_params: Option<OutPaintingParams>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_variation_params: Option<ImageVariationParams>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub image_generation_config: Option<ImageGenerationConfig>
 }
 
 impl ImageGeneratorParameter {
-    pub fn new_generate_image_params(prompt: &str, image_generation_config: Option<ImageGenerationConfig>) -> Self {
+    pub fn new_generate_image_params(prompt: &str, negative_text: Option<String>, image_generation_config: Option<ImageGenerationConfig>) -> Self {
         Self {
             task_type: TaskType::TextImage,
-            text_to_image_params: TextToImageParams { text: prompt.to_owned() },
+            text_to_image_params: Some(TextToImageParams { text: prompt.to_owned(), negative_text }),
+            in_painting_params: None,
+            out_painting_params: None,
+            image_variation_params: None,
+            image_generation_config,
+        }
+    }
+
+    pub fn new_inpainting_params(params: InPaintingParams, image_generation_config: Option<ImageGenerationConfig>) -> Self {
+        Self {
+            task_type: TaskType::Inpainting,
+            text_to_image_params: None,
+            in_painting_params: Some(params),
+            out_painting_params: None,
+            image_variation_params: None,
+            image_generation_config,
+        }
+    }
+
+    pub fn new_outpainting_params(params: OutPaintingParams, image_generation_config: Option<ImageGenerationConfig>) -> Self {
+        Self {
+            task_type: TaskType::Outpainting,
+            text_to_image_params: None,
+            in_painting_params: None,
+            out_painting_params: Some(params),
+            image_variation_params: None,
+            image_generation_config,
+        }
+    }
+
+    // the prompt and the negative text of the task
+    pub fn texts(&self) -> (Option<&str>, Option<&str>) {
+        match self.task_type {
+            TaskType::TextImage => {
+                let params = self.text_to_image_params.as_ref();
+                (params.map(|params| params.text.as_str()), params.and_then(|params| params.negative_text.as_deref()))
+            },
+            TaskType::Inpainting => {
+                let params = self.in_painting_params.as_ref();
+                (params.and_then(|params| params.text.as_deref()), params.and_then(|params| params.negative_text.as_deref()))
+            },
+            TaskType::Outpainting => {
+                let params = self.out_painting_params.as_ref();
+                (params.map(|params| params.text.as_str()), params.and_then(|params| params.negative_text.as_deref()))
+            },
+            TaskType::ImageVariation => {
+                let params = self.image_variation_params.as_ref();
+                (params.and_then(|params| params.text.as_deref()), params.and_then(|params| params.negative_text.as_deref()))
+            },
+        }
+    }
+
+    // Checks the values against the ranges Titan accepts, so mistakes are reported before calling the model.
+    pub fn validate(&self, model_id: &str) -> Result<()> {
+        let (text, negative_text) = self.texts();
+        if text.is_some_and(|text| text.chars().count() > MAX_TEXT_LENGTH) {
+            bail!("prompt should be at most {MAX_TEXT_LENGTH} characters.")
+        }
+        if let Some(negative_text) = negative_text {
+            if negative_text.is_empty() || negative_text.chars().count() > MAX_TEXT_LENGTH {
+                bail!("negativeText should be from 1 to {MAX_TEXT_LENGTH} characters.")
+            }
+        }
+
+        match &self.image_generation_config {
+            Some(config) => config.validate(model_id),
+            None => Ok(()),
+        }
+    }
+
+    pub fn new_image_variation_params(params: ImageVariationParams, image_generation_config: Option<ImageGenerationConfig>) -> Self {
+        Self {
+            task_type: TaskType::ImageVariation,
+            text_to_image_params: None,
+            in_painting_params: None,
+            out_painting_params: None,
+            image_variation_params: Some(params),
             image_generation_config,
         }
     }
@@ -32,10 +133,65 @@ pub enum TaskType {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TextToImageParams {
-    pub text: String
+    pub text: String,
+    // what should not be in the image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub negative_text: Option<String>,
+}
+
+// Images are base64 encoded PNG or JPEG.
+// Exactly one of mask_prompt and mask_image should be set, the masked area is the one that gets edited.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InPaintingParams {
+    pub image: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub negative_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mask_prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mask_image: Option<String>,
+}
+
+// The masked area is kept and everything around it is regenerated.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutPaintingParams {
+    pub image: String,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub negative_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mask_prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mask_image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub out_painting_mode: Option<OutPaintingMode>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OutPaintingMode {
+    Default,
+    Precise
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageVariationParams {
+    pub images: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub negative_text: Option<String>,
+    // 0.2 to 1.0, how close the variations are to the source images
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub similarity_strength: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ImageGenerationConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -46,6 +202,49 @@ pub struct ImageGenerationConfig {
     pub height: Option<u128>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub width: Option<u128>,
+    // how strongly the image follows the prompt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cfg_scale: Option<f32>,
+    // the same seed and parameters generate the same image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u32>,
+}
+
+impl ImageGenerationConfig {
+    fn validate(&self, model_id: &str) -> Result<()> {
+        if let Some(number_of_images) = self.number_of_images {
+            if !(1..=MAX_NUMBER_OF_IMAGES).contains(&number_of_images) {
+                bail!("numberOfImages should be from 1 to {MAX_NUMBER_OF_IMAGES}.")
+            }
+        }
+        if let Some(cfg_scale) = self.cfg_scale {
+            if !(MIN_CFG_SCALE..=MAX_CFG_SCALE).contains(&cfg_scale) {
+                bail!("cfgScale should be from {MIN_CFG_SCALE} to {MAX_CFG_SCALE}.")
+            }
+        }
+        if self.seed.is_some_and(|seed| seed > MAX_SEED) {
+            bail!("seed should be from 0 to {MAX_SEED}.")
+        }
+
+        let (Some(width), Some(height)) = (self.width, self.height) else {
+            return Ok(());
+        };
+        if model_id.contains("titan-image-generator-v1") {
+            if !TITAN_V1_SIZES.contains(&(width, height)) {
+                let sizes: Vec<String> = TITAN_V1_SIZES.iter().map(|(width, height)| format!("{width}x{height}")).collect();
+                bail!("{width}x{height} is not a supported size. Supported width x height: {}.", sizes.join(", "))
+            }
+            return Ok(());
+        }
+        // Titan Image Generator v2
+        if !(320..=4096).contains(&width) || !(320..=4096).contains(&height) || width % 16 != 0 || height % 16 != 0 {
+            bail!("width and height should be from 320 to 4096 pixels, and divisible by 16.")
+        }
+        if width * height > 4_194_304 || width > height * 4 || height > width * 4 {
+            bail!("The image should be at most 4,194,304 pixels, with an aspect ratio from 1:4 to 4:1.")
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -60,4 +259,87 @@ pub enum IamgeQuality {
 #[serde(rename_all = "camelCase")]
 pub struct ImageGeneratorResponse {
     pub images: Vec<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TITAN_V1: &str = "amazon.titan-image-generator-v1";
+    const TITAN_V2: &str = "amazon.titan-image-generator-v2:0";
+
+    fn config(cfg_scale: Option<f32>, seed: Option<u32>, size: Option<(u128, u128)>) -> ImageGenerationConfig {
+        ImageGenerationConfig {
+            cfg_scale,
+            seed,
+            width: size.map(|(width, _)| width),
+            height: size.map(|(_, height)| height),
+            ..Default::default()
+        }
+    }
+
+    fn error(config: ImageGenerationConfig, model_id: &str) -> String {
+        config.validate(model_id).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn accepts_seeds_up_to_the_titan_maximum() {
+        assert!(config(None, Some(0), None).validate(TITAN_V2).is_ok());
+        assert!(config(None, Some(2_147_483_646), None).validate(TITAN_V2).is_ok());
+        assert_eq!(error(config(None, Some(2_147_483_647), None), TITAN_V2), "seed should be from 0 to 2147483646.");
+    }
+
+    #[test]
+    fn accepts_cfg_scales_from_1_1_to_10() {
+        assert!(config(Some(1.1), None, None).validate(TITAN_V2).is_ok());
+        assert!(config(Some(10.0), None, None).validate(TITAN_V2).is_ok());
+        assert_eq!(error(config(Some(1.0), None, None), TITAN_V2), "cfgScale should be from 1.1 to 10.");
+        assert_eq!(error(config(Some(10.5), None, None), TITAN_V2), "cfgScale should be from 1.1 to 10.");
+    }
+
+    #[test]
+    fn rejects_a_number_of_images_out_of_range() {
+        let config = ImageGenerationConfig { number_of_images: Some(6), ..Default::default() };
+        assert_eq!(error(config, TITAN_V2), "numberOfImages should be from 1 to 5.");
+    }
+
+    #[test]
+    fn accepts_texts_up_to_512_characters() {
+        let negative_text = Some("blurry".to_owned());
+        assert!(ImageGeneratorParameter::new_generate_image_params(&"a".repeat(512), negative_text.clone(), None).validate(TITAN_V2).is_ok());
+
+        let long = ImageGeneratorParameter::new_generate_image_params(&"a".repeat(513), None, None);
+        assert_eq!(long.validate(TITAN_V2).unwrap_err().to_string(), "prompt should be at most 512 characters.");
+
+        let long_negative = ImageGeneratorParameter::new_generate_image_params("a cat", Some("b".repeat(513)), None);
+        assert_eq!(long_negative.validate(TITAN_V2).unwrap_err().to_string(), "negativeText should be from 1 to 512 characters.");
+        let empty_negative = ImageGeneratorParameter::new_generate_image_params("a cat", Some("".to_owned()), None);
+        assert!(empty_negative.validate(TITAN_V2).is_err());
+    }
+
+    #[test]
+    fn accepts_only_the_supported_titan_v1_sizes() {
+        for size in TITAN_V1_SIZES {
+            assert!(config(None, None, Some(size)).validate(TITAN_V1).is_ok(), "{size:?}");
+        }
+        let message = error(config(None, None, Some((1024, 768))), TITAN_V1);
+        assert!(message.starts_with("1024x768 is not a supported size. Supported width x height: 1024x1024, 768x768, 512x512,"));
+    }
+
+    #[test]
+    fn checks_titan_v2_sizes() {
+        assert!(config(None, None, Some((1024, 768))).validate(TITAN_V2).is_ok());
+        assert_eq!(
+            error(config(None, None, Some((1000, 1000))), TITAN_V2),
+            "width and height should be from 320 to 4096 pixels, and divisible by 16."
+        );
+        assert_eq!(
+            error(config(None, None, Some((4096, 4096))), TITAN_V2),
+            "The image should be at most 4,194,304 pixels, with an aspect ratio from 1:4 to 4:1."
+        );
+        assert!(config(None, None, Some((320, 1600))).validate(TITAN_V2).is_err());
+    }
 }
@@ -0,0 +1,479 @@
+use super::image_generator_parameter::{IamgeQuality, ImageGeneratorParameter, ImageGeneratorResponse, TaskType};
+
+use std::env;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::model_constants::{IMAGE_MODEL_ID, IMAGE_MODEL_KEY, LEGACY_IMAGE_MODEL_KEY};
+
+
+// An image model on Bedrock.
+// The tools describe what to generate with `ImageGeneratorParameter`, and the model translates it to its own request and response format.
+pub trait ImageModel: Send + Sync {
+    fn model_id(&self) -> &str;
+
+    // width and height used when the model does not give one
+    fn default_size(&self) -> (u128, u128);
+
+    // the body for invoke_model, fails if the model does not support the parameters
+    fn request_body(&self, parameters: &ImageGeneratorParameter) -> Result<Vec<u8>>;
+
+    // fails if the model does not accept an image of this size to edit
+    fn check_source_size(&self, width: u32, height: u32) -> Result<()>;
+
+    // return an array of base64 image string
+    fn images_from_response(&self, body: &[u8]) -> Result<Vec<String>>;
+}
+
+// the model set by `BEDROCK_IMAGE_MODEL_ID`
+pub fn image_model_from_env() -> Result<Box<dyn ImageModel>> {
+    let model_id = env::var(IMAGE_MODEL_KEY)
+        .or_else(|_| env::var(LEGACY_IMAGE_MODEL_KEY))
+        .unwrap_or(IMAGE_MODEL_ID.to_owned());
+    image_model(&model_id)
+}
+
+// The adapter is chosen from the model id. Cross-region inference profiles such as `us.stability.sd3-large-v1:0` are also supported.
+pub fn image_model(model_id: &str) -> Result<Box<dyn ImageModel>> {
+    let model_id = model_id.to_owned();
+    if model_id.contains("amazon.titan-image-generator") {
+        return Ok(Box::new(TitanImageModel { model_id }));
+    }
+    if model_id.contains("stability.stable-diffusion-xl") {
+        return Ok(Box::new(StableDiffusionXlModel { model_id }));
+    }
+    if model_id.contains("stability.") {
+        return Ok(Box::new(StableImageModel { model_id }));
+    }
+    bail!("Unsupported image model: {model_id}. Supported models: Amazon Titan Image Generator v1 and v2, Stability AI SDXL, SD3 and Stable Image.")
+}
+
+
+// Amazon Titan Image Generator v1 and v2
+// `ImageGeneratorParameter` is the Titan request format, so it is sent as it is.
+pub struct TitanImageModel {
+    model_id: String,
+}
+
+// the sides of an image to edit, larger images are downscaled before they are sent
+const TITAN_MIN_SOURCE_DIMENSION: u32 = 256;
+pub const TITAN_MAX_SOURCE_DIMENSION: u32 = 1408;
+
+impl ImageModel for TitanImageModel {
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn default_size(&self) -> (u128, u128) {
+        (512, 512)
+    }
+
+    fn request_body(&self, parameters: &ImageGeneratorParameter) -> Result<Vec<u8>> {
+        parameters.validate(&self.model_id)?;
+        Ok(serde_json::to_vec(parameters)?)
+    }
+
+    fn check_source_size(&self, width: u32, height: u32) -> Result<()> {
+        let sides = TITAN_MIN_SOURCE_DIMENSION..=TITAN_MAX_SOURCE_DIMENSION;
+        if !sides.contains(&width) || !sides.contains(&height) {
+            bail!("The image to edit is {width}x{height}, the width and height should be from {TITAN_MIN_SOURCE_DIMENSION} to {TITAN_MAX_SOURCE_DIMENSION} pixels for {}.", self.model_id)
+        }
+        if width > height * 4 || height > width * 4 {
+            bail!("The image to edit is {width}x{height}, the aspect ratio should be from 1:4 to 4:1 for {}.", self.model_id)
+        }
+        Ok(())
+    }
+
+    fn images_from_response(&self, body: &[u8]) -> Result<Vec<String>> {
+        let response: ImageGeneratorResponse = serde_json::from_slice(body)?;
+        if let Some(error) = response.error {
+            bail!("{} failed to generate the image: {error}", self.model_id)
+        }
+        Ok(response.images)
+    }
+}
+
+
+// Stability AI Stable Diffusion XL
+// Supports text to image, and variation as image to image.
+pub struct StableDiffusionXlModel {
+    model_id: String,
+}
+
+const SDXL_SIZES: [(u128, u128); 9] = [
+    (1024, 1024), (1152, 896), (1216, 832), (1344, 768), (1536, 640),
+    (640, 1536), (768, 1344), (832, 1216), (896, 1152),
+];
+const SDXL_MAX_CFG_SCALE: f32 = 35.0;
+// diffusion steps for premium quality, SDXL uses 30 by default
+const SDXL_PREMIUM_STEPS: u8 = 50;
+
+#[derive(Clone, Debug, Serialize)]
+struct SdxlRequest {
+    text_prompts: Vec<SdxlTextPrompt>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cfg_scale: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    steps: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    init_image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    init_image_mode: Option<String>,
+    // how close the result is to the init image, from 0 to 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image_strength: Option<f32>,
+}
+
+// a negative weight is a negative prompt
+#[derive(Clone, Debug, Serialize)]
+struct SdxlTextPrompt {
+    text: String,
+    weight: f32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SdxlArtifact {
+    base64: String,
+    finish_reason: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct SdxlResponse {
+    artifacts: Vec<SdxlArtifact>,
+}
+
+impl ImageModel for StableDiffusionXlModel {
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn default_size(&self) -> (u128, u128) {
+        (1024, 1024)
+    }
+
+    fn request_body(&self, parameters: &ImageGeneratorParameter) -> Result<Vec<u8>> {
+        let config = parameters.image_generation_config.clone().unwrap_or_default();
+        check_single_image(&self.model_id, config.number_of_images)?;
+        if config.cfg_scale.is_some_and(|cfg_scale| !(0.0..=SDXL_MAX_CFG_SCALE).contains(&cfg_scale)) {
+            bail!("cfgScale should be from 0 to {SDXL_MAX_CFG_SCALE} for {}.", self.model_id)
+        }
+
+        let (text, negative_text) = parameters.texts();
+        let text = text.filter(|text| !text.is_empty())
+            .context(format!("prompt is required for {}.", self.model_id))?;
+        let mut text_prompts = vec![SdxlTextPrompt { text: text.to_owned(), weight: 1.0 }];
+        if let Some(negative_text) = negative_text {
+            text_prompts.push(SdxlTextPrompt { text: negative_text.to_owned(), weight: -1.0 });
+        }
+
+        let mut request = SdxlRequest {
+            text_prompts,
+            cfg_scale: config.cfg_scale,
+            seed: config.seed,
+            steps: match config.quality {
+                Some(IamgeQuality::Premium) => Some(SDXL_PREMIUM_STEPS),
+                _ => None,
+            },
+            width: None,
+            height: None,
+            init_image: None,
+            init_image_mode: None,
+            image_strength: None,
+        };
+        match parameters.task_type {
+            TaskType::TextImage => {
+                if let (Some(width), Some(height)) = (config.width, config.height) {
+                    check_sdxl_size(width, height)?;
+                }
+                request.width = config.width;
+                request.height = config.height;
+            },
+            TaskType::ImageVariation => {
+                // the size of the result is the size of the init image
+                let params = parameters.image_variation_params.as_ref().context("image to create variations of is not provided.")?;
+                request.init_image = params.images.first().cloned();
+                request.init_image_mode = Some("IMAGE_STRENGTH".to_owned());
+                request.image_strength = params.similarity_strength;
+            },
+            _ => bail!("{} does not support {:?}. Use an Amazon Titan image model.", self.model_id, parameters.task_type),
+        }
+        Ok(serde_json::to_vec(&request)?)
+    }
+
+    // the init image should have one of the sizes SDXL generates
+    fn check_source_size(&self, width: u32, height: u32) -> Result<()> {
+        check_sdxl_size(width as u128, height as u128)
+    }
+
+    fn images_from_response(&self, body: &[u8]) -> Result<Vec<String>> {
+        let response: SdxlResponse = serde_json::from_slice(body)?;
+        let mut images: Vec<String> = vec![];
+        for artifact in response.artifacts {
+            match artifact.finish_reason.as_deref() {
+                Some("SUCCESS") | None => images.push(artifact.base64),
+                Some(reason) => bail!("{} failed to generate the image: {reason}", self.model_id),
+            }
+        }
+        Ok(images)
+    }
+}
+
+
+// Stability AI SD3, SD3.5 and Stable Image Core / Ultra
+// Supports text to image, and variation as image to image. The size is mapped to the closest aspect ratio.
+// cfgScale and premium quality have no equivalent, so they are refused instead of silently dropped.
+pub struct StableImageModel {
+    model_id: String,
+}
+
+const STABLE_IMAGE_ASPECT_RATIOS: [(u128, u128); 9] = [
+    (16, 9), (1, 1), (21, 9), (2, 3), (3, 2), (4, 5), (5, 4), (9, 16), (9, 21),
+];
+// Titan uses 0.7 as the default similarity strength
+const DEFAULT_SIMILARITY_STRENGTH: f32 = 0.7;
+const STABLE_IMAGE_MIN_SOURCE_DIMENSION: u32 = 64;
+const STABLE_IMAGE_MAX_SOURCE_PIXELS: u32 = 9_437_184;
+
+#[derive(Clone, Debug, Serialize)]
+struct StableImageRequest {
+    prompt: String,
+    // only SD3 and SD3.5 take a mode, Stable Image Core and Ultra tell the tasks apart by the image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    negative_prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aspect_ratio: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    // how much the image changes, from 0 (identical) to 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    strength: Option<f32>,
+    output_format: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct StableImageResponse {
+    images: Vec<String>,
+    #[serde(default)]
+    finish_reasons: Vec<Option<String>>,
+}
+
+impl ImageModel for StableImageModel {
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn default_size(&self) -> (u128, u128) {
+        (1024, 1024)
+    }
+
+    fn request_body(&self, parameters: &ImageGeneratorParameter) -> Result<Vec<u8>> {
+        let config = parameters.image_generation_config.clone().unwrap_or_default();
+        check_single_image(&self.model_id, config.number_of_images)?;
+        if config.cfg_scale.is_some() {
+            bail!("{} does not support cfgScale. Leave it out, or use an Amazon Titan or SDXL image model.", self.model_id)
+        }
+        if matches!(config.quality, Some(IamgeQuality::Premium)) {
+            bail!("{} does not support premium quality. Leave quality out, or use an Amazon Titan or SDXL image model.", self.model_id)
+        }
+
+        let (text, negative_text) = parameters.texts();
+        let text = text.filter(|text| !text.is_empty())
+            .context(format!("prompt is required for {}.", self.model_id))?;
+        let has_mode = self.model_id.contains("stability.sd3");
+        let mut request = StableImageRequest {
+            prompt: text.to_owned(),
+            mode: has_mode.then(|| "text-to-image".to_owned()),
+            negative_prompt: negative_text.map(|text| text.to_owned()),
+            aspect_ratio: None,
+            seed: config.seed,
+            image: None,
+            strength: None,
+            output_format: "png".to_owned(),
+        };
+        match parameters.task_type {
+            TaskType::TextImage => {
+                if let (Some(width), Some(height)) = (config.width, config.height) {
+                    request.aspect_ratio = Some(closest_aspect_ratio(width, height));
+                }
+            },
+            TaskType::ImageVariation => {
+                let params = parameters.image_variation_params.as_ref().context("image to create variations of is not provided.")?;
+                request.mode = has_mode.then(|| "image-to-image".to_owned());
+                request.image = params.images.first().cloned();
+                request.strength = Some(1.0 - params.similarity_strength.unwrap_or(DEFAULT_SIMILARITY_STRENGTH));
+            },
+            _ => bail!("{} does not support {:?}. Use an Amazon Titan image model.", self.model_id, parameters.task_type),
+        }
+        Ok(serde_json::to_vec(&request)?)
+    }
+
+    fn check_source_size(&self, width: u32, height: u32) -> Result<()> {
+        if width < STABLE_IMAGE_MIN_SOURCE_DIMENSION || height < STABLE_IMAGE_MIN_SOURCE_DIMENSION || width * height > STABLE_IMAGE_MAX_SOURCE_PIXELS {
+            bail!("The image to edit is {width}x{height}, each side should be at least {STABLE_IMAGE_MIN_SOURCE_DIMENSION} pixels and the image at most {STABLE_IMAGE_MAX_SOURCE_PIXELS} pixels for {}.", self.model_id)
+        }
+        // from 1:2.5 to 2.5:1
+        if width * 2 > height * 5 || height * 2 > width * 5 {
+            bail!("The image to edit is {width}x{height}, the aspect ratio should be from 1:2.5 to 2.5:1 for {}.", self.model_id)
+        }
+        Ok(())
+    }
+
+    fn images_from_response(&self, body: &[u8]) -> Result<Vec<String>> {
+        let response: StableImageResponse = serde_json::from_slice(body)?;
+        if let Some(reason) = response.finish_reasons.into_iter().flatten().next() {
+            bail!("{} failed to generate the image: {reason}", self.model_id)
+        }
+        Ok(response.images)
+    }
+}
+
+// Stability models on Bedrock generate one image per request
+fn check_single_image(model_id: &str, number_of_images: Option<u8>) -> Result<()> {
+    if number_of_images.unwrap_or(1) != 1 {
+        bail!("{model_id} generates one image at a time. Set numberOfImages to 1.")
+    }
+    Ok(())
+}
+
+fn check_sdxl_size(width: u128, height: u128) -> Result<()> {
+    if !SDXL_SIZES.contains(&(width, height)) {
+        let sizes: Vec<String> = SDXL_SIZES.iter().map(|(width, height)| format!("{width}x{height}")).collect();
+        bail!("{width}x{height} is not a supported size. Supported width x height: {}.", sizes.join(", "))
+    }
+    Ok(())
+}
+
+fn closest_aspect_ratio(width: u128, height: u128) -> String {
+    let ratio = width as f64 / height as f64;
+    let (width, height) = STABLE_IMAGE_ASPECT_RATIOS.iter()
+        .min_by(|(w1, h1), (w2, h2)| {
+            let d1 = (*w1 as f64 / *h1 as f64 - ratio).abs();
+            let d2 = (*w2 as f64 / *h2 as f64 - ratio).abs();
+            d1.total_cmp(&d2)
+        })
+        .unwrap_or(&(1, 1));
+    format!("{width}:{height}")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::image_generator_parameter::ImageGenerationConfig;
+    use serde_json::Value;
+
+    fn text_to_image(size: Option<(u128, u128)>, number_of_images: Option<u8>) -> ImageGeneratorParameter {
+        let config = ImageGenerationConfig {
+            number_of_images,
+            width: size.map(|(width, _)| width),
+            height: size.map(|(_, height)| height),
+            ..Default::default()
+        };
+        ImageGeneratorParameter::new_generate_image_params("a cat", Some("blurry".to_owned()), Some(config))
+    }
+
+    fn request(model_id: &str, parameters: &ImageGeneratorParameter) -> Value {
+        let body = image_model(model_id).unwrap().request_body(parameters).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    fn chooses_the_adapter_from_the_model_id() {
+        assert_eq!(image_model("amazon.titan-image-generator-v2:0").unwrap().default_size(), (512, 512));
+        assert_eq!(image_model("stability.stable-diffusion-xl-v1").unwrap().model_id(), "stability.stable-diffusion-xl-v1");
+        assert_eq!(image_model("us.stability.sd3-large-v1:0").unwrap().model_id(), "us.stability.sd3-large-v1:0");
+        let err = image_model("openai.dall-e-3").err().unwrap();
+        assert!(err.to_string().starts_with("Unsupported image model: openai.dall-e-3."));
+    }
+
+    #[test]
+    fn titan_validates_the_size() {
+        let model = image_model("amazon.titan-image-generator-v1").unwrap();
+        assert!(model.request_body(&text_to_image(Some((1024, 1024)), None)).is_ok());
+        let err = model.request_body(&text_to_image(Some((1000, 1000)), None)).unwrap_err();
+        assert!(err.to_string().starts_with("1000x1000 is not a supported size."));
+    }
+
+    #[test]
+    fn sdxl_accepts_only_its_sizes_and_one_image() {
+        let body = request("stability.stable-diffusion-xl-v1", &text_to_image(Some((1216, 832)), None));
+        assert_eq!(body["width"], 1216);
+        assert_eq!(body["text_prompts"][1]["weight"], -1.0);
+
+        let model = image_model("stability.stable-diffusion-xl-v1").unwrap();
+        let err = model.request_body(&text_to_image(Some((512, 512)), None)).unwrap_err();
+        assert!(err.to_string().starts_with("512x512 is not a supported size. Supported width x height: 1024x1024, 1152x896,"));
+        let err = model.request_body(&text_to_image(None, Some(2))).unwrap_err();
+        assert_eq!(err.to_string(), "stability.stable-diffusion-xl-v1 generates one image at a time. Set numberOfImages to 1.");
+    }
+
+    #[test]
+    fn checks_the_size_of_the_image_to_edit() {
+        let titan = image_model("amazon.titan-image-generator-v2:0").unwrap();
+        assert!(titan.check_source_size(1408, 1024).is_ok());
+        let err = titan.check_source_size(200, 512).unwrap_err();
+        assert!(err.to_string().starts_with("The image to edit is 200x512, the width and height should be from 256 to 1408 pixels"));
+        assert!(titan.check_source_size(1400, 300).unwrap_err().to_string().contains("the aspect ratio should be from 1:4 to 4:1"));
+
+        let sdxl = image_model("stability.stable-diffusion-xl-v1").unwrap();
+        assert!(sdxl.check_source_size(896, 1152).is_ok());
+        assert!(sdxl.check_source_size(800, 600).unwrap_err().to_string().starts_with("800x600 is not a supported size."));
+
+        let stable_image = image_model("stability.sd3-large-v1:0").unwrap();
+        assert!(stable_image.check_source_size(640, 480).is_ok());
+        assert!(stable_image.check_source_size(1000, 300).unwrap_err().to_string().contains("the aspect ratio should be from 1:2.5 to 2.5:1"));
+        assert!(stable_image.check_source_size(32, 32).is_err());
+    }
+
+    #[test]
+    fn stable_image_maps_the_size_to_the_closest_aspect_ratio() {
+        let body = request("stability.sd3-large-v1:0", &text_to_image(Some((1920, 1080)), None));
+        assert_eq!(body["aspect_ratio"], "16:9");
+        assert_eq!(body["negative_prompt"], "blurry");
+        assert_eq!(closest_aspect_ratio(1000, 1000), "1:1");
+        assert_eq!(closest_aspect_ratio(600, 900), "2:3");
+    }
+
+    #[test]
+    fn stable_image_sends_the_mode_only_to_sd3() {
+        let body = request("stability.sd3-5-large-v1:0", &text_to_image(None, None));
+        assert_eq!(body["mode"], "text-to-image");
+        let body = request("us.stability.stable-image-ultra-v1:0", &text_to_image(None, None));
+        assert!(body.get("mode").is_none());
+    }
+
+    #[test]
+    fn stable_image_refuses_cfg_scale_and_premium_quality() {
+        let model = image_model("stability.stable-image-core-v1:0").unwrap();
+        let mut parameters = text_to_image(None, None);
+        if let Some(config) = parameters.image_generation_config.as_mut() {
+            config.quality = Some(IamgeQuality::Standard);
+        }
+        assert!(model.request_body(&parameters).is_ok());
+
+        if let Some(config) = parameters.image_generation_config.as_mut() {
+            config.quality = Some(IamgeQuality::Premium);
+        }
+        let err = model.request_body(&parameters).unwrap_err();
+        assert!(err.to_string().starts_with("stability.stable-image-core-v1:0 does not support premium quality."));
+
+        let mut parameters = text_to_image(None, None);
+        if let Some(config) = parameters.image_generation_config.as_mut() {
+            config.cfg_scale = Some(7.0);
+        }
+        let err = model.request_body(&parameters).unwrap_err();
+        assert!(err.to_string().starts_with("stability.stable-image-core-v1:0 does not support cfgScale."));
+    }
+}
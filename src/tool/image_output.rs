@@ -0,0 +1,144 @@
+use super::get_string;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use aws_smithy_types::Document;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+
+
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+pub const MAX_TARGET_DIMENSION: u32 = 8192;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    // lossless
+    WebP,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::WebP => "webp",
+        }
+    }
+}
+
+// How a generated image is saved: the file format, an exact size, and a thumbnail.
+#[derive(Clone, Debug)]
+pub struct ImageOutput {
+    pub format: OutputFormat,
+    pub jpeg_quality: u8,
+    // resized and center cropped to exactly this width and height
+    pub target_size: Option<(u32, u32)>,
+    // the largest side of the thumbnail
+    pub thumbnail_size: Option<u32>,
+}
+
+impl Default for ImageOutput {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Png,
+            jpeg_quality: DEFAULT_JPEG_QUALITY,
+            target_size: None,
+            thumbnail_size: None,
+        }
+    }
+}
+
+impl ImageOutput {
+    // Reads `format`, `jpegQuality`, `targetWidth`, `targetHeight` and `thumbnailSize`, so mistakes are reported before calling the model.
+    pub fn from_input(input: &Document) -> Result<Self> {
+        let format = match get_string(input, "format").map(|format| format.to_lowercase()) {
+            None => OutputFormat::Png,
+            Some(format) => match format.as_str() {
+                "png" => OutputFormat::Png,
+                "jpeg" | "jpg" => OutputFormat::Jpeg,
+                "webp" => OutputFormat::WebP,
+                _ => bail!("Unknown format: {format}. Possible value: png, jpeg, webp."),
+            },
+        };
+
+        let jpeg_quality = match get_integer(input, "jpegQuality") {
+            Some(quality) if !(1.0..=100.0).contains(&quality) => bail!("jpegQuality should be from 1 to 100."),
+            Some(quality) => quality as u8,
+            None => DEFAULT_JPEG_QUALITY,
+        };
+
+        let target_size = match (get_integer(input, "targetWidth"), get_integer(input, "targetHeight")) {
+            (None, None) => None,
+            (Some(width), Some(height)) => {
+                let range = 1.0..=MAX_TARGET_DIMENSION as f64;
+                if !range.contains(&width) || !range.contains(&height) {
+                    bail!("targetWidth and targetHeight should be from 1 to {MAX_TARGET_DIMENSION} pixels.")
+                }
+                Some((width as u32, height as u32))
+            },
+            _ => bail!("targetWidth and targetHeight should be set together."),
+        };
+
+        let thumbnail_size = match get_integer(input, "thumbnailSize") {
+            Some(size) if !(1.0..=MAX_TARGET_DIMENSION as f64).contains(&size) => {
+                bail!("thumbnailSize should be from 1 to {MAX_TARGET_DIMENSION} pixels.")
+            },
+            Some(size) => Some(size as u32),
+            None => None,
+        };
+
+        Ok(Self { format, jpeg_quality, target_size, thumbnail_size })
+    }
+
+    // the image resized and cropped to the target size, if any
+    pub fn process(&self, image: DynamicImage) -> DynamicImage {
+        match self.target_size {
+            Some((width, height)) if (image.width(), image.height()) != (width, height) => {
+                image.resize_to_fill(width, height, FilterType::Lanczos3)
+            },
+            _ => image,
+        }
+    }
+
+    pub fn save(&self, image: &DynamicImage, path: &Path) -> Result<()> {
+        match self.format {
+            OutputFormat::Png => image.save_with_format(path, ImageFormat::Png)?,
+            OutputFormat::Jpeg => {
+                // jpeg has no alpha channel
+                let writer = BufWriter::new(File::create(path)?);
+                let encoder = JpegEncoder::new_with_quality(writer, self.jpeg_quality);
+                DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder)?;
+            },
+            OutputFormat::WebP => image.save_with_format(path, ImageFormat::WebP)?,
+        }
+        Ok(())
+    }
+
+    // Saves `<image name>-thumbnail.<extension>` next to the image, and returns its path.
+    pub fn save_thumbnail(&self, image: &DynamicImage, image_path: &Path) -> Result<Option<PathBuf>> {
+        let Some(size) = self.thumbnail_size else {
+            return Ok(None);
+        };
+        let path = thumbnail_path(image_path);
+        self.save(&image.thumbnail(size, size), &path)?;
+        Ok(Some(path))
+    }
+}
+
+// `<image name>-thumbnail.<image extension>`
+pub fn thumbnail_path(image_path: &Path) -> PathBuf {
+    let stem = image_path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = image_path.extension().unwrap_or_default().to_string_lossy();
+    image_path.with_file_name(format!("{stem}-thumbnail.{extension}"))
+}
+
+fn get_integer(input: &Document, key: &str) -> Option<f64> {
+    let number = input.as_object()?.get(key)?.as_number()?;
+    Some(number.to_f64_lossy().round())
+}
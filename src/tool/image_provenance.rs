@@ -0,0 +1,113 @@
+use super::context::ToolContext;
+use super::image_generator_parameter::{ImageGenerationConfig, ImageGeneratorParameter, TaskType};
+use super::image_output::thumbnail_path;
+use super::ToValue;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use aws_smithy_types::Document;
+use serde::Serialize;
+use serde_json::Value;
+
+
+// file names use the first words of the prompt, for example `a-cute-cat-on-the-sofa.png`
+const MAX_FILE_NAME_WORDS: usize = 8;
+const MAX_FILE_NAME_LENGTH: usize = 60;
+
+// How a generated image was made, saved as `<image name>.json` next to the image.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageProvenance {
+    // the file name of the image, set when it is saved
+    pub image: String,
+    pub model_id: String,
+    pub task_type: TaskType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub negative_text: Option<String>,
+    // size, quality, cfg scale and seed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_generation_config: Option<ImageGenerationConfig>,
+    // the tool input as sent by the model, including the source image and mask paths of an edit
+    pub input: Value,
+    pub created_at: String,
+    pub conversation_id: String,
+    pub tool_use_id: String,
+}
+
+impl ImageProvenance {
+    pub fn new(id: &str, input: &Document, model_id: &str, parameters: &ImageGeneratorParameter, context: &ToolContext) -> Self {
+        let (prompt, negative_text) = parameters.texts();
+        Self {
+            image: String::new(),
+            model_id: model_id.to_owned(),
+            task_type: parameters.task_type.clone(),
+            prompt: prompt.map(|prompt| prompt.to_owned()),
+            negative_text: negative_text.map(|text| text.to_owned()),
+            image_generation_config: parameters.image_generation_config.clone(),
+            input: input.to_value(),
+            created_at: chrono::Local::now().to_rfc3339(),
+            conversation_id: context.conversation_id.to_owned(),
+            tool_use_id: id.to_owned(),
+        }
+    }
+
+    // From the prompt, or from the source image and the task for an edit without one.
+    pub fn file_stem(&self) -> String {
+        if let Some(stem) = self.prompt.as_deref().and_then(slug) {
+            return stem;
+        }
+        let task = match self.task_type {
+            TaskType::TextImage => "image",
+            TaskType::Inpainting => "inpainting",
+            TaskType::Outpainting => "outpainting",
+            TaskType::ImageVariation => "variation",
+        };
+        let source = self.source_image().and_then(|path| Path::new(path).file_stem()?.to_str()).and_then(slug);
+        match source {
+            Some(source) => format!("{source}-{task}"),
+            None => task.to_owned(),
+        }
+    }
+
+    fn source_image(&self) -> Option<&str> {
+        self.input.get("image")?.as_str()
+    }
+
+    // saves the sidecar for the image at `image_path`
+    pub fn save(&self, image_path: &Path) -> Result<()> {
+        let mut provenance = self.clone();
+        provenance.image = image_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        fs::write(image_path.with_extension("json"), serde_json::to_string_pretty(&provenance)?)?;
+        Ok(())
+    }
+}
+
+// lowercase words joined by `-`, None if there is no word
+fn slug(text: &str) -> Option<String> {
+    let words: Vec<String> = text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .take(MAX_FILE_NAME_WORDS)
+        .map(|word| word.to_lowercase())
+        .collect();
+    let slug: String = words.join("-").chars().take(MAX_FILE_NAME_LENGTH).collect();
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        return None;
+    }
+    Some(slug.to_owned())
+}
+
+// `<folder>/<stem>.<extension>`, with `-2`, `-3`... added if the image, its sidecar or its thumbnail already exists
+pub fn unique_image_path(folder: &Path, stem: &str, extension: &str) -> PathBuf {
+    let mut path = folder.join(format!("{stem}.{extension}"));
+    let mut count = 2;
+    while path.exists() || path.with_extension("json").exists() || thumbnail_path(&path).exists() {
+        path = folder.join(format!("{stem}-{count}.{extension}"));
+        count += 1;
+    }
+    path
+}
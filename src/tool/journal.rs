@@ -0,0 +1,257 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use super::sandbox::FileSnapshot;
+
+
+// What a tool did to a path.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Change {
+    CreatedDirectory,
+    CreatedFile,
+    // the copy of the previous version, None when it was not backed up
+    ModifiedFile(Option<PathBuf>),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Change::CreatedDirectory => "created folder",
+            Change::CreatedFile => "created",
+            Change::ModifiedFile(_) => "modified",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub tool_use_id: String,
+    pub path: PathBuf,
+    pub change: Change,
+    pub time: DateTime<Local>,
+}
+
+// Files and folders created or modified by the tools during the session, in the order they were touched.
+// Clones share the same entries, so tools running at the same time record to one journal.
+#[derive(Clone, Debug, Default)]
+pub struct Journal {
+    entries: Arc<Mutex<Vec<JournalEntry>>>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<JournalEntry>> {
+        // a tool that panicked while recording leaves the entries usable
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn record(&self, tool_use_id: &str, path: &Path, change: Change) {
+        self.lock().push(JournalEntry {
+            tool_use_id: tool_use_id.to_owned(),
+            path: path.to_owned(),
+            change,
+            time: Local::now(),
+        });
+    }
+
+    // `fs::create_dir_all`, recording each folder it creates.
+    pub fn create_dir_all(&self, tool_use_id: &str, path: &Path) -> io::Result<()> {
+        let missing: Vec<&Path> = path.ancestors()
+            .take_while(|ancestor| !ancestor.as_os_str().is_empty() && !ancestor.exists())
+            .collect();
+        fs::create_dir_all(path)?;
+        for directory in missing.into_iter().rev() {
+            self.record(tool_use_id, directory, Change::CreatedDirectory);
+        }
+        Ok(())
+    }
+
+    // Files a process created or modified in its working directory. Modified files have no backup.
+    pub fn record_snapshot_changes(&self, tool_use_id: &str, before: &FileSnapshot, directory: &Path) {
+        for path in before.changed_files(directory) {
+            let change = match before.contains(&path) {
+                true => Change::ModifiedFile(None),
+                false => Change::CreatedFile,
+            };
+            self.record(tool_use_id, &path, change);
+        }
+    }
+
+    pub fn entries(&self) -> Vec<JournalEntry> {
+        self.lock().clone()
+    }
+
+    // replaces the entries, for example with the ones saved with a resumed conversation
+    pub fn restore(&self, entries: Vec<JournalEntry>) {
+        *self.lock() = entries;
+    }
+
+    // Reverts all the changes of the tool that changed files last, newest first. Returns what was done for each path.
+    // Tools run at the same time, so the entries of the tool can be mixed with the ones of others.
+    pub fn undo_last(&self) -> Option<(String, Vec<String>)> {
+        let mut entries = self.lock();
+        let tool_use_id = entries.last()?.tool_use_id.clone();
+        let (undone, kept): (Vec<JournalEntry>, Vec<JournalEntry>) = entries.drain(..)
+            .partition(|entry| entry.tool_use_id == tool_use_id);
+        *entries = kept;
+        drop(entries);
+
+        let results = undone.iter().rev().map(|entry| {
+            let path = entry.path.display();
+            match undo(entry) {
+                Ok(message) => format!("{path}: {message}"),
+                Err(err) => format!("{path}: not reverted, {err}"),
+            }
+        }).collect();
+        Some((tool_use_id, results))
+    }
+}
+
+fn undo(entry: &JournalEntry) -> io::Result<String> {
+    match &entry.change {
+        Change::CreatedFile => {
+            if !entry.path.exists() {
+                return Ok("already removed".to_owned());
+            }
+            fs::remove_file(&entry.path)?;
+            Ok("removed".to_owned())
+        },
+        Change::ModifiedFile(Some(backup)) => {
+            fs::copy(backup, &entry.path)?;
+            Ok("restored".to_owned())
+        },
+        Change::ModifiedFile(None) => Ok("not reverted, the previous version was not backed up".to_owned()),
+        Change::CreatedDirectory => {
+            if !entry.path.exists() {
+                return Ok("already removed".to_owned());
+            }
+            // files not created by the tool are never deleted
+            match fs::remove_dir(&entry.path) {
+                Ok(_) => Ok("removed".to_owned()),
+                Err(_) => Ok("kept, the folder is not empty".to_owned()),
+            }
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    #[test]
+    fn undo_removes_a_created_file() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("notes.txt");
+        fs::write(&path, "notes").unwrap();
+        let journal = Journal::new();
+        journal.record("tool_1", &path, Change::CreatedFile);
+
+        let (tool_use_id, results) = journal.undo_last().unwrap();
+        assert_eq!(tool_use_id, "tool_1");
+        assert_eq!(results, [format!("{}: removed", path.display())]);
+        assert!(!path.exists());
+        assert!(journal.undo_last().is_none());
+    }
+
+    #[test]
+    fn undo_restores_a_backup() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("notes.txt");
+        let backup = directory.path().join("backup.txt");
+        fs::write(&path, "new").unwrap();
+        fs::write(&backup, "old").unwrap();
+        let journal = Journal::new();
+        journal.record("tool_1", &path, Change::ModifiedFile(Some(backup)));
+        journal.record("tool_1", &directory.path().join("log.txt"), Change::ModifiedFile(None));
+
+        let (_, results) = journal.undo_last().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert!(results[0].ends_with("not reverted, the previous version was not backed up"));
+        assert!(results[1].ends_with("restored"));
+    }
+
+    #[test]
+    fn undo_removes_created_folders_only_when_empty() {
+        let directory = TempDir::new().unwrap();
+        let journal = Journal::new();
+        let empty = directory.path().join("empty/nested");
+        let used = directory.path().join("used");
+        journal.create_dir_all("tool_1", &empty).unwrap();
+        journal.create_dir_all("tool_1", &used).unwrap();
+        fs::write(used.join("user.txt"), "kept").unwrap();
+
+        let created: Vec<PathBuf> = journal.entries().into_iter().map(|entry| entry.path).collect();
+        assert_eq!(created, [directory.path().join("empty"), empty.clone(), used.clone()]);
+
+        journal.undo_last().unwrap();
+        assert!(!directory.path().join("empty").exists());
+        assert!(used.join("user.txt").exists());
+    }
+
+    #[test]
+    fn create_dir_all_records_only_missing_folders() {
+        let directory = TempDir::new().unwrap();
+        let journal = Journal::new();
+        journal.create_dir_all("tool_1", directory.path()).unwrap();
+        assert!(journal.entries().is_empty());
+    }
+
+    #[test]
+    fn undo_reverts_every_change_of_the_last_tool() {
+        let directory = TempDir::new().unwrap();
+        let paths: Vec<PathBuf> = (0..4).map(|index| directory.path().join(format!("{index}.txt"))).collect();
+        let journal = Journal::new();
+        // two tools running at the same time
+        for (index, path) in paths.iter().enumerate() {
+            fs::write(path, "").unwrap();
+            journal.record(if index % 2 == 0 { "tool_1" } else { "tool_2" }, path, Change::CreatedFile);
+        }
+
+        let (tool_use_id, results) = journal.undo_last().unwrap();
+        assert_eq!(tool_use_id, "tool_2");
+        assert_eq!(results.len(), 2);
+        assert!(paths[0].exists() && !paths[1].exists() && paths[2].exists() && !paths[3].exists());
+
+        let remaining: Vec<String> = journal.entries().into_iter().map(|entry| entry.tool_use_id).collect();
+        assert_eq!(remaining, ["tool_1", "tool_1"]);
+        let (tool_use_id, _) = journal.undo_last().unwrap();
+        assert_eq!(tool_use_id, "tool_1");
+        assert!(!paths[0].exists() && !paths[2].exists());
+    }
+
+    #[test]
+    fn records_the_files_a_process_created_or_modified() {
+        let directory = TempDir::new().unwrap();
+        let modified = directory.path().join("data.csv");
+        let unchanged = directory.path().join("readme.md");
+        fs::write(&modified, "a").unwrap();
+        fs::write(&unchanged, "b").unwrap();
+        let before = FileSnapshot::new(directory.path());
+
+        let created = directory.path().join("chart.png");
+        fs::write(&created, "png").unwrap();
+        // a later modification time, as file systems may not see a change made in the same instant
+        File::options().write(true).open(&modified).unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(5)).unwrap();
+
+        let journal = Journal::new();
+        journal.record_snapshot_changes("tool_1", &before, directory.path());
+        let changes: Vec<(PathBuf, Change)> = journal.entries().into_iter().map(|entry| (entry.path, entry.change)).collect();
+        assert_eq!(changes, [(created, Change::CreatedFile), (modified, Change::ModifiedFile(None))]);
+    }
+}
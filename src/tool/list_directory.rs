@@ -0,0 +1,233 @@
+use super::{create_tool_result_block, get_bool, get_number, get_string, ToDocument, Tool, ToolJsonSchema};
+use super::context::ToolContext;
+
+use std::{fs::Metadata, path::{Path, PathBuf}};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use aws_sdk_bedrockruntime::types::{ToolResultBlock, ToolResultStatus};
+use aws_smithy_types::Document;
+use chrono::{DateTime, Local};
+use ignore::WalkBuilder;
+use serde_json::json;
+use tokio::task;
+
+
+// LIST_DIRECTORY tool
+pub const LIST_DIRECTORY_NAME: &str = "LIST_DIRECTORY";
+pub const LIST_DIRECTORY_DESCRIPTION: &str = "List the files and folders in a directory, with their sizes and modification times. Use this to explore a folder before reading its files. Files ignored by .gitignore and hidden files are left out by default.";
+
+pub const DEFAULT_DEPTH: usize = 1;
+pub const MAX_DEPTH: usize = 10;
+// entries returned at most, so a large folder does not fill the conversation
+pub const MAX_ENTRIES: usize = 500;
+
+pub fn list_directory_schema() -> Result<Document> {
+    let json_schema = json!({
+        "type": "object",
+        "properties": {
+            "path": {
+                "type": "string",
+                "description": "The path of the directory to list. Default to the current working directory."
+            },
+            "depth": {
+                "type": "number",
+                "description": format!("How many levels of sub folders to list, 1 for the directory itself only. Optional. The default value is {DEFAULT_DEPTH}, at most {MAX_DEPTH}.")
+            },
+            "includeIgnored": {
+                "type": "boolean",
+                "description": "Include files ignored by .gitignore and hidden files. Optional. The default value is false."
+            },
+        },
+        "required": [],
+    });
+    let document = ToolJsonSchema::new(json_schema)?.to_document();
+    Ok(document)
+}
+
+pub struct ListDirectoryTool;
+
+#[async_trait]
+impl Tool for ListDirectoryTool {
+    fn name(&self) -> &str {
+        LIST_DIRECTORY_NAME
+    }
+
+    fn description(&self) -> &str {
+        LIST_DIRECTORY_DESCRIPTION
+    }
+
+    fn input_schema(&self) -> Result<Document> {
+        list_directory_schema()
+    }
+
+    async fn execute(&self, id: &str, input: &Document, _context: &ToolContext) -> Result<ToolResultBlock> {
+        let id = id.to_owned();
+        let input = input.clone();
+        task::spawn_blocking(move || {
+            match list_directory(&input) {
+                Ok(content) => create_tool_result_block(&id, &content, ToolResultStatus::Success),
+                Err(err) => create_tool_result_block(&id, &err.to_string(), ToolResultStatus::Error),
+            }
+        }).await?
+    }
+}
+
+fn list_directory(input: &Document) -> Result<String> {
+    let path = PathBuf::from(get_string(input, "path").unwrap_or("."));
+    if !path.is_dir() {
+        bail!("{} is not a directory.", path.display())
+    }
+    let depth = match get_number(input, "depth") {
+        Some(depth) if depth < 1.0 => bail!("depth should be 1 or more."),
+        Some(depth) => (depth as usize).min(MAX_DEPTH),
+        None => DEFAULT_DEPTH,
+    };
+    let include_ignored = get_bool(input, "includeIgnored").unwrap_or(false);
+
+    let mut lines: Vec<String> = vec![];
+    let mut total = 0;
+    for entry in walk_builder(&path, Some(depth), include_ignored).build() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        // the directory itself
+        if entry.depth() == 0 {
+            continue;
+        }
+        total += 1;
+        if lines.len() >= MAX_ENTRIES {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(&path).unwrap_or(entry.path());
+        if let Ok(metadata) = entry.metadata() {
+            lines.push(describe_entry(relative, &metadata));
+        }
+    }
+
+    if total == 0 {
+        return Ok(format!("{} is empty.", path.display()));
+    }
+    let mut content = format!("Directory listed: {}. {} entries (size, modified, path).\n{}", path.display(), total, lines.join("\n"));
+    if total > lines.len() {
+        content.push_str(&format!("\n... {} more entries not shown. List a sub folder or use a smaller depth to see them.", total - lines.len()));
+    }
+    Ok(content)
+}
+
+// Walks the directory in path order, following .gitignore and skipping hidden files unless include_ignored.
+pub fn walk_builder(path: &Path, max_depth: Option<usize>, include_ignored: bool) -> WalkBuilder {
+    let mut builder = WalkBuilder::new(path);
+    builder
+        .max_depth(max_depth)
+        .standard_filters(!include_ignored)
+        // .gitignore is also followed outside a git repository
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b));
+    builder
+}
+
+// A line with the size, modification time and path of a file. Directories end with `/`.
+pub fn describe_entry(path: &Path, metadata: &Metadata) -> String {
+    let modified = match metadata.modified() {
+        Ok(modified) => DateTime::<Local>::from(modified).format("%Y-%m-%d %H:%M").to_string(),
+        Err(_) => "-".to_owned(),
+    };
+    if metadata.is_dir() {
+        format!("{:>9}  {}  {}/", "-", modified, path.display())
+    } else {
+        format!("{:>9}  {}  {}", format_size(metadata.len()), modified, path.display())
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    // the paths listed, without the header and the sizes
+    fn listed(content: &str) -> Vec<&str> {
+        content.lines().skip(1)
+            .filter(|line| !line.starts_with("..."))
+            .filter_map(|line| line.split("  ").last())
+            .collect()
+    }
+
+    fn list(directory: &Path, depth: Option<usize>, include_ignored: bool) -> Result<String> {
+        let mut input = json!({ "path": directory, "includeIgnored": include_ignored });
+        if let Some(depth) = depth {
+            input["depth"] = json!(depth);
+        }
+        list_directory(&input.to_document())
+    }
+
+    #[test]
+    fn lists_the_requested_depth() {
+        let directory = TempDir::new().unwrap();
+        fs::create_dir_all(directory.path().join("a/b")).unwrap();
+        fs::write(directory.path().join("a/b/notes.txt"), "notes").unwrap();
+        fs::write(directory.path().join("top.txt"), "top").unwrap();
+
+        assert_eq!(listed(&list(directory.path(), None, false).unwrap()), ["a/", "top.txt"]);
+        assert_eq!(listed(&list(directory.path(), Some(3), false).unwrap()), ["a/", "a/b/", "a/b/notes.txt", "top.txt"]);
+        assert_eq!(list(directory.path(), Some(0), false).unwrap_err().to_string(), "depth should be 1 or more.");
+    }
+
+    #[test]
+    fn caps_the_depth() {
+        let directory = TempDir::new().unwrap();
+        let deepest: PathBuf = (0..MAX_DEPTH + 2).map(|level| level.to_string()).collect();
+        fs::create_dir_all(directory.path().join(&deepest)).unwrap();
+
+        let content = list(directory.path(), Some(100), false).unwrap();
+        assert_eq!(listed(&content).len(), MAX_DEPTH);
+        assert!(content.contains(&format!("{} entries", MAX_DEPTH)));
+    }
+
+    #[test]
+    fn caps_the_number_of_entries() {
+        let directory = TempDir::new().unwrap();
+        for index in 0..MAX_ENTRIES + 10 {
+            fs::write(directory.path().join(format!("{index:04}.csv")), "").unwrap();
+        }
+
+        let content = list(directory.path(), None, false).unwrap();
+        assert_eq!(listed(&content).len(), MAX_ENTRIES);
+        assert!(content.contains(&format!("{} entries", MAX_ENTRIES + 10)));
+        assert!(content.ends_with("... 10 more entries not shown. List a sub folder or use a smaller depth to see them."));
+    }
+
+    #[test]
+    fn leaves_out_hidden_and_ignored_files_by_default() {
+        let directory = TempDir::new().unwrap();
+        fs::write(directory.path().join(".gitignore"), "*.log\n").unwrap();
+        fs::write(directory.path().join(".env"), "").unwrap();
+        fs::write(directory.path().join("debug.log"), "").unwrap();
+        fs::write(directory.path().join("report.md"), "").unwrap();
+
+        assert_eq!(listed(&list(directory.path(), None, false).unwrap()), ["report.md"]);
+        assert_eq!(listed(&list(directory.path(), None, true).unwrap()), [".env", ".gitignore", "debug.log", "report.md"]);
+    }
+
+    #[test]
+    fn reports_an_empty_directory() {
+        let directory = TempDir::new().unwrap();
+        assert_eq!(list(directory.path(), None, false).unwrap(), format!("{} is empty.", directory.path().display()));
+    }
+}
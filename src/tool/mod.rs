@@ -1,10 +1,29 @@
 
 pub mod read_file;
+pub mod list_directory;
+pub mod find_files;
+pub mod search_files;
+pub mod write_file;
+pub mod edit_file;
+pub mod file_change;
 pub mod generate_image;
+pub mod edit_image;
 pub mod image_generator_parameter;
+pub mod image_model;
 pub mod run_python;
+pub mod run_shell;
+pub mod registry;
+pub mod permission;
+pub mod sandbox;
+pub mod python_kernel;
+pub mod image_content;
+pub mod image_provenance;
+pub mod image_output;
+pub mod context;
+pub mod journal;
 
 use std::{collections::HashMap, fmt};
+use async_trait::async_trait;
 use aws_sdk_bedrockruntime::types::{ToolResultBlock, ToolResultContentBlock, ToolResultStatus};
 use anyhow::Result;
 use aws_smithy_types::Document;
@@ -12,6 +31,42 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::result::Result::Ok;
 
+use context::ToolContext;
+use permission::ToolPolicy;
+
+
+// A tool the model can call. Register it in a `ToolRegistry` to make it available to `BedrockService`.
+#[async_trait]
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn description(&self) -> &str;
+
+    fn input_schema(&self) -> Result<Document>;
+
+    // Extra guidance on when and how to use the tool, added to the system prompt.
+    fn instructions(&self) -> Option<String> {
+        None
+    }
+
+    // Policy used when the user has not configured one for the tool.
+    fn default_policy(&self) -> ToolPolicy {
+        ToolPolicy::Allow
+    }
+
+    // What the tool is about to do, shown when asking the user for approval.
+    fn preview(&self, input: &Document) -> String {
+        pretty_input(input)
+    }
+
+    async fn execute(&self, id: &str, input: &Document, context: &ToolContext) -> Result<ToolResultBlock>;
+}
+
+
+// the tool input as indented JSON
+pub fn pretty_input(input: &Document) -> String {
+    serde_json::to_string_pretty(&input.to_value()).unwrap_or_else(|_| format!("{:?}", input))
+}
 
 pub fn create_tool_result_block(id: &str, content: &str, status: ToolResultStatus ) -> Result<ToolResultBlock> {
     let tool_result = ToolResultBlock::builder()
@@ -23,10 +78,64 @@ pub fn create_tool_result_block(id: &str, content: &str, status: ToolResultStatu
 }
 
 
+pub fn append_tool_result_content(tool_result: ToolResultBlock, content: Vec<ToolResultContentBlock>) -> Result<ToolResultBlock> {
+    let mut contents = tool_result.content().to_vec();
+    contents.extend(content);
+    let mut builder = ToolResultBlock::builder()
+        .tool_use_id(tool_result.tool_use_id())
+        .set_content(Some(contents));
+    if let Some(status) = tool_result.status() {
+        builder = builder.status(status.clone());
+    }
+    Ok(builder.build()?)
+}
+
+// Keeps the start and the end of a long text, with a marker in place of the part left out.
+pub fn truncate_text(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_owned();
+    }
+    let mut head_end = max_bytes / 2;
+    while !text.is_char_boundary(head_end) {
+        head_end -= 1;
+    }
+    let mut tail_start = text.len() - max_bytes / 2;
+    while !text.is_char_boundary(tail_start) {
+        tail_start += 1;
+    }
+    format!(
+        "{}\n... [{} bytes truncated] ...\n{}",
+        &text[..head_end], tail_start - head_end, &text[tail_start..]
+    )
+}
+
+pub fn get_string<'a>(input: &'a Document, key: &str) -> Option<&'a str> {
+    input.as_object()?.get(key)?.as_string()
+}
+
+pub fn get_number(input: &Document, key: &str) -> Option<f64> {
+    Some(input.as_object()?.get(key)?.as_number()?.to_f64_lossy())
+}
+
+pub fn get_bool(input: &Document, key: &str) -> Option<bool> {
+    input.as_object()?.get(key)?.as_bool()
+}
+
+// the strings of an array, skipping the other values
+pub fn get_string_list(input: &Document, key: &str) -> Option<Vec<String>> {
+    let array = input.as_object()?.get(key)?.as_array()?;
+    Some(array.iter().filter_map(|value| value.as_string().map(str::to_owned)).collect())
+}
+
+
 pub trait ToDocument {
     fn to_document(&self) -> Document;
 }
 
+pub trait ToValue {
+    fn to_value(&self) -> Value;
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ToolJsonSchema {
     r#type: String,
@@ -100,7 +209,7 @@ impl ToDocument for Value {
             return Document::String(string.to_owned());
         }
 
-        if let Some(_) = self.as_null() {
+        if self.as_null().is_some() {
             return Document::Null
         }
 
@@ -128,6 +237,42 @@ impl ToDocument for Value {
             return Document::Object(doc_map);
         }
 
-        return Document::Null;
+        Document::Null
+    }
+}
+
+impl ToValue for Document {
+    fn to_value(&self) -> Value {
+        match self {
+            Document::Object(object) => {
+                Value::Object(object.iter().map(|(key, value)| (key.to_owned(), value.to_value())).collect())
+            },
+            Document::Array(array) => Value::Array(array.iter().map(|item| item.to_value()).collect()),
+            Document::Number(number) => {
+                match number {
+                    aws_smithy_types::Number::PosInt(int) => Value::from(*int),
+                    aws_smithy_types::Number::NegInt(int) => Value::from(*int),
+                    aws_smithy_types::Number::Float(float) => Value::from(*float),
+                }
+            },
+            Document::String(string) => Value::String(string.to_owned()),
+            Document::Bool(bool) => Value::Bool(*bool),
+            Document::Null => Value::Null,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn shows_the_input_as_indented_json() {
+        let input = json!({ "query": "rust async", "maxResults": 5 }).to_document();
+        let preview = pretty_input(&input);
+        assert_eq!(serde_json::from_str::<Value>(&preview).unwrap(), input.to_value());
+        assert!(preview.contains("\n  \"query\": \"rust async\""));
     }
 }
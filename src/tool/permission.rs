@@ -0,0 +1,112 @@
+use super::Tool;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{stdin, IsTerminal};
+use std::str::FromStr;
+use anyhow::{bail, Result};
+use aws_smithy_types::Document;
+
+use crate::terminal_service::TerminalService;
+
+
+// How a tool call is approved before it runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToolPolicy {
+    Allow,
+    Ask,
+    Deny
+}
+
+impl FromStr for ToolPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "allow" => Ok(ToolPolicy::Allow),
+            "ask" => Ok(ToolPolicy::Ask),
+            "deny" => Ok(ToolPolicy::Deny),
+            _ => bail!("Unknown tool policy: {s}. Possible value: allow, ask, deny.")
+        }
+    }
+}
+
+impl fmt::Display for ToolPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+// parse `NAME=policy` pairs separated by commas, for example `RUN_PYTHON=deny,GENERATE_IMAGE=allow`
+pub fn parse_tool_policies(string: &str) -> Result<HashMap<String, ToolPolicy>> {
+    let mut policies: HashMap<String, ToolPolicy> = HashMap::new();
+    for pair in string.split(',').filter(|pair| !pair.trim().is_empty()) {
+        let Some((name, policy)) = pair.split_once('=') else {
+            bail!("Tool policy should be in the format of NAME=policy: {pair}")
+        };
+        policies.insert(name.trim().to_owned(), policy.parse()?);
+    }
+    Ok(policies)
+}
+
+
+pub enum Approval {
+    Approved,
+    Denied(String)
+}
+
+#[derive(Debug)]
+pub struct PermissionManager {
+    policies: HashMap<String, ToolPolicy>,
+    assume_yes: bool,
+    // tools the user answered `always` for during this session
+    always_allowed: HashSet<String>,
+}
+
+impl PermissionManager {
+    pub fn new(policies: HashMap<String, ToolPolicy>, assume_yes: bool) -> Self {
+        Self {
+            policies,
+            assume_yes,
+            always_allowed: HashSet::new(),
+        }
+    }
+
+    pub fn policy(&self, tool: &dyn Tool) -> ToolPolicy {
+        if self.always_allowed.contains(tool.name()) {
+            return ToolPolicy::Allow;
+        }
+        let policy = self.policies.get(tool.name()).copied().unwrap_or(tool.default_policy());
+        if policy == ToolPolicy::Ask && self.assume_yes {
+            return ToolPolicy::Allow;
+        }
+        policy
+    }
+
+    pub fn check(&mut self, tool: &dyn Tool, input: &Document, terminal: &mut TerminalService) -> Result<Approval> {
+        let name = tool.name();
+        match self.policy(tool) {
+            ToolPolicy::Allow => Ok(Approval::Approved),
+            ToolPolicy::Deny => Ok(Approval::Denied(format!("{name} is disabled by the user's tool policy. Do not try to use it again."))),
+            ToolPolicy::Ask => {
+                if !stdin().is_terminal() {
+                    return Ok(Approval::Denied(format!("{name} needs the user's approval, but no terminal is available to ask for it.")));
+                }
+                loop {
+                    let Some(answer) = terminal.ask_approval(name, &tool.preview(input))? else {
+                        return Ok(Approval::Denied(format!("{name} needs the user's approval, but the terminal input was closed before the user answered.")));
+                    };
+                    match answer.trim().to_lowercase().as_str() {
+                        "y" | "yes" => return Ok(Approval::Approved),
+                        "a" | "always" => {
+                            self.always_allowed.insert(name.to_owned());
+                            return Ok(Approval::Approved);
+                        },
+                        "n" | "no" => return Ok(Approval::Denied(format!("The user denied running {name}. Ask the user how to proceed instead of retrying."))),
+                        _ => continue,
+                    }
+                }
+            },
+        }
+    }
+}
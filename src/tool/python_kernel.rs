@@ -0,0 +1,374 @@
+use super::sandbox::{is_memory_error, kill_process_group, termination_from_status, Sandbox, SandboxConfig, SandboxOutput, Termination};
+
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout};
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+
+
+// Runs each request in a namespace that lives as long as the process.
+// Requests are read from a copy of the original stdin and responses are written to a copy of the original stdout.
+// File descriptor 0 is pointed at /dev/null and 1 at stderr, so the code, its subprocesses or C extensions
+// cannot read requests or break the protocol.
+const KERNEL_SCRIPT: &str = r#"
+import contextlib, io, json, os, sys, traceback
+
+_requests = os.fdopen(os.dup(0), "r")
+_null = os.open(os.devnull, os.O_RDONLY)
+os.dup2(_null, 0)
+os.close(_null)
+_protocol = os.fdopen(os.dup(1), "w")
+os.dup2(2, 1)
+sys.stdout = sys.stderr
+
+def _new_namespace():
+    return {"__name__": "__main__", "__builtins__": __builtins__}
+
+_namespace = _new_namespace()
+
+# keeps the start and the end of long output, like the tool result, so a loop printing forever does not fill the memory
+class _CappedOutput(io.TextIOBase):
+    def __init__(self, limit=200_000):
+        self._half, self._head, self._tail, self._total = limit // 2, "", "", 0
+
+    def writable(self):
+        return True
+
+    def write(self, text):
+        length = len(text)
+        self._total += length
+        if len(self._head) < self._half:
+            room = self._half - len(self._head)
+            self._head += text[:room]
+            text = text[room:]
+        if text:
+            self._tail = (self._tail + text)[-self._half:]
+        return length
+
+    def getvalue(self):
+        truncated = self._total - len(self._head) - len(self._tail)
+        if truncated == 0:
+            return self._head + self._tail
+        return f"{self._head}\n... [{truncated} characters truncated] ...\n{self._tail}"
+
+for _line in _requests:
+    _request = json.loads(_line)
+    _stdout, _stderr = _CappedOutput(), _CappedOutput()
+    _exit_code = 0
+    if _request.get("reset"):
+        _namespace = _new_namespace()
+    else:
+        try:
+            os.chdir(_request["cwd"])
+            # input() gets an EOFError instead of waiting for a line that never comes
+            sys.stdin = io.StringIO()
+            with contextlib.redirect_stdout(_stdout), contextlib.redirect_stderr(_stderr):
+                exec(compile(_request["code"], _request["filename"], "exec"), _namespace)
+        except SystemExit as _exit:
+            # like the interpreter: None is a success, an integer is the exit status, anything else is printed and is a failure
+            if _exit.code is None:
+                _exit_code = 0
+            elif isinstance(_exit.code, int):
+                _exit_code = int(_exit.code) & 0xFF
+            else:
+                _stderr.write(str(_exit.code) + "\n")
+                _exit_code = 1
+        except BaseException as _error:
+            # the frame of this script is left out of the traceback
+            _stderr.write("".join(traceback.format_exception(type(_error), _error, _error.__traceback__.tb_next)))
+            _exit_code = 1
+    _protocol.write(json.dumps({"stdout": _stdout.getvalue(), "stderr": _stderr.getvalue(), "exitCode": _exit_code}) + "\n")
+    _protocol.flush()
+"#;
+
+// output written to stderr between requests that is kept, the oldest is dropped first
+const MAX_STDERR_BYTES: usize = 200_000;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KernelRequest<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cwd: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filename: Option<&'a str>,
+    reset: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KernelResponse {
+    stdout: String,
+    stderr: String,
+    exit_code: i32,
+}
+
+#[derive(Debug)]
+struct KernelProcess {
+    child: Child,
+    // saved when started, since the child forgets its id once it has exited
+    pid: Option<u32>,
+    stdin: ChildStdin,
+    responses: Lines<BufReader<ChildStdout>>,
+    // output written to the process' stderr outside of a request, such as warnings from C extensions
+    stderr: Arc<Mutex<String>>,
+}
+
+
+// A long-lived python process shared by the RUN_PYTHON calls of a conversation,
+// so variables, imports and loaded data are kept between calls like in a notebook.
+#[derive(Debug)]
+pub struct PythonKernel {
+    python: String,
+    sandbox: Sandbox,
+    process: Mutex<Option<KernelProcess>>,
+}
+
+impl PythonKernel {
+    pub fn new(python: &str, sandbox_config: SandboxConfig) -> Self {
+        // CPU time adds up over the life of the process, so only the per-call timeout applies
+        let sandbox_config = SandboxConfig {
+            cpu_limit_secs: 0,
+            ..sandbox_config
+        };
+        Self {
+            python: python.to_owned(),
+            sandbox: Sandbox::new(sandbox_config),
+            process: Mutex::new(None),
+        }
+    }
+
+    pub fn sandbox_config(&self) -> &SandboxConfig {
+        &self.sandbox.config
+    }
+
+    pub async fn execute(&self, code: &str, working_directory: &Path, filename: &str) -> Result<SandboxOutput> {
+        let working_directory = working_directory.canonicalize()?;
+        let request = KernelRequest {
+            code: Some(code),
+            cwd: Some(working_directory.to_str().context("working directory is not valid unicode")?),
+            filename: Some(filename),
+            reset: false,
+        };
+        self.send(&request).await
+    }
+
+    // clears all variables and imports, keeping the process
+    pub async fn reset(&self) -> Result<()> {
+        if self.process.lock().await.is_none() {
+            return Ok(());
+        }
+
+        let request = KernelRequest { code: None, cwd: None, filename: None, reset: true };
+        let output = self.send(&request).await?;
+        if output.termination != Termination::Exited {
+            bail!("The python kernel stopped while resetting.")
+        }
+        Ok(())
+    }
+
+    // stops the process, a new one is started on the next call
+    pub async fn restart(&self) {
+        let mut process = self.process.lock().await;
+        if let Some(mut kernel) = process.take() {
+            kill_process_group(kernel.pid);
+            let _ = kernel.child.wait().await;
+        }
+    }
+
+    async fn send(&self, request: &KernelRequest<'_>) -> Result<SandboxOutput> {
+        let mut process = self.process.lock().await;
+        if process.is_none() {
+            *process = Some(self.start()?);
+        }
+        let kernel = process.as_mut().context("python kernel is not running")?;
+
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        kernel.stdin.write_all(line.as_bytes()).await?;
+        kernel.stdin.flush().await?;
+
+        let response = match timeout(self.sandbox.config.timeout, kernel.responses.next_line()).await {
+            Ok(response) => response?,
+            Err(_) => {
+                let mut kernel = process.take().context("python kernel is not running")?;
+                kill_process_group(kernel.pid);
+                let _ = kernel.child.wait().await;
+                return Ok(
+                    SandboxOutput {
+                        stdout: "".to_owned(),
+                        stderr: "The python kernel was restarted, all variables and imports are lost.".to_owned(),
+                        exit_code: None,
+                        termination: Termination::TimedOut,
+                    }
+                )
+            },
+        };
+
+        let stderr = std::mem::take(&mut *kernel.stderr.lock().await);
+        match response {
+            Some(response) => {
+                let response: KernelResponse = serde_json::from_str(&response)?;
+                let termination = if response.exit_code != 0 && is_memory_error(&response.stderr, &self.sandbox.config) {
+                    Termination::MemoryLimitExceeded
+                } else {
+                    Termination::Exited
+                };
+                Ok(
+                    SandboxOutput {
+                        stdout: response.stdout,
+                        stderr: format!("{}{}", stderr, response.stderr),
+                        exit_code: Some(response.exit_code),
+                        termination,
+                    }
+                )
+            },
+            // the process ended, for example by exceeding the memory limit
+            None => {
+                let mut kernel = process.take().context("python kernel is not running")?;
+                let status = kernel.child.wait().await?;
+                kill_process_group(kernel.pid);
+                let stderr = format!("{}{}", stderr, std::mem::take(&mut *kernel.stderr.lock().await));
+                Ok(
+                    SandboxOutput {
+                        stdout: "".to_owned(),
+                        stderr: format!("{}\nThe python kernel stopped, all variables and imports are lost.", stderr),
+                        exit_code: status.code(),
+                        termination: termination_from_status(&status, &stderr, &self.sandbox.config),
+                    }
+                )
+            },
+        }
+    }
+
+    fn start(&self) -> Result<KernelProcess> {
+        let mut child = self.sandbox.command(&self.python, ["-u", "-c", KERNEL_SCRIPT], Path::new("."))?
+            .stdin(Stdio::piped())
+            .spawn()
+            .context(format!("error starting python kernel: {}", self.python))?;
+
+        let stdin = child.stdin.take().context("failed to open stdin of python kernel")?;
+        let stdout = child.stdout.take().context("failed to capture stdout of python kernel")?;
+        let mut stderr_pipe = child.stderr.take().context("failed to capture stderr of python kernel")?;
+
+        let stderr = Arc::new(Mutex::new(String::new()));
+        let stderr_buffer = stderr.clone();
+        tokio::spawn(async move {
+            let mut buffer = [0; 4096];
+            while let Ok(count) = stderr_pipe.read(&mut buffer).await {
+                if count == 0 {
+                    break;
+                }
+                let mut stderr = stderr_buffer.lock().await;
+                stderr.push_str(&String::from_utf8_lossy(&buffer[..count]));
+                keep_end(&mut stderr, MAX_STDERR_BYTES);
+            }
+        });
+
+        Ok(
+            KernelProcess {
+                pid: child.id(),
+                child,
+                stdin,
+                responses: BufReader::new(stdout).lines(),
+                stderr,
+            }
+        )
+    }
+}
+
+// drops the start of the text so it is at most `max_bytes`
+fn keep_end(text: &mut String, max_bytes: usize) {
+    if text.len() <= max_bytes {
+        return;
+    }
+    let mut start = text.len() - max_bytes;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    text.drain(..start);
+}
+
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn kernel(timeout: Duration) -> Option<PythonKernel> {
+        let available = std::process::Command::new("python3").arg("--version").output().is_ok_and(|output| output.status.success());
+        let config = SandboxConfig { timeout, memory_limit_mb: 0, cpu_limit_secs: 0, deny_network: false };
+        available.then(|| PythonKernel::new("python3", config))
+    }
+
+    #[tokio::test]
+    async fn keeps_variables_between_runs() {
+        let Some(kernel) = kernel(Duration::from_secs(30)) else {
+            return;
+        };
+        let directory = TempDir::new().unwrap();
+        kernel.execute("total = 41", directory.path(), "cell_1.py").await.unwrap();
+        let output = kernel.execute("print(total + 1)", directory.path(), "cell_2.py").await.unwrap();
+        assert_eq!(output.stdout, "42\n");
+        assert_eq!(output.exit_code, Some(0));
+
+        kernel.reset().await.unwrap();
+        let output = kernel.execute("print(total)", directory.path(), "cell_3.py").await.unwrap();
+        assert_eq!(output.exit_code, Some(1));
+        assert!(output.stderr.contains("NameError"));
+    }
+
+    #[tokio::test]
+    async fn maps_system_exit_to_the_exit_code() {
+        let Some(kernel) = kernel(Duration::from_secs(30)) else {
+            return;
+        };
+        let directory = TempDir::new().unwrap();
+        let output = kernel.execute("import sys\nsys.exit()", directory.path(), "cell.py").await.unwrap();
+        assert_eq!(output.exit_code, Some(0));
+        let output = kernel.execute("sys.exit(3)", directory.path(), "cell.py").await.unwrap();
+        assert_eq!(output.exit_code, Some(3));
+        let output = kernel.execute("sys.exit('no data')", directory.path(), "cell.py").await.unwrap();
+        assert_eq!(output.exit_code, Some(1));
+        assert_eq!(output.stderr, "no data\n");
+        assert_eq!(output.termination, Termination::Exited);
+    }
+
+    #[tokio::test]
+    async fn code_reading_stdin_does_not_read_the_requests() {
+        let Some(kernel) = kernel(Duration::from_secs(30)) else {
+            return;
+        };
+        let directory = TempDir::new().unwrap();
+        let output = kernel.execute("import sys\nprint(repr(sys.stdin.read()))\nprint(repr(open(0).read()))", directory.path(), "cell.py").await.unwrap();
+        assert_eq!(output.stdout, "''\n''\n");
+        let output = kernel.execute("input()", directory.path(), "cell.py").await.unwrap();
+        assert!(output.stderr.contains("EOFError"));
+        let output = kernel.execute("print('still running')", directory.path(), "cell.py").await.unwrap();
+        assert_eq!(output.stdout, "still running\n");
+    }
+
+    #[tokio::test]
+    async fn restarts_after_a_timeout() {
+        let Some(kernel) = kernel(Duration::from_millis(500)) else {
+            return;
+        };
+        let directory = TempDir::new().unwrap();
+        kernel.execute("total = 41", directory.path(), "cell_1.py").await.unwrap();
+        let output = kernel.execute("import time\ntime.sleep(30)", directory.path(), "cell_2.py").await.unwrap();
+        assert_eq!(output.termination, Termination::TimedOut);
+        assert_eq!(output.stderr, "The python kernel was restarted, all variables and imports are lost.");
+
+        let output = kernel.execute("print(total)", directory.path(), "cell_3.py").await.unwrap();
+        assert!(output.stderr.contains("NameError"));
+        let output = kernel.execute("print('restarted')", directory.path(), "cell_4.py").await.unwrap();
+        assert_eq!(output.stdout, "restarted\n");
+    }
+}
@@ -1,16 +1,27 @@
-use super::{create_tool_result_block, ToDocument, ToolJsonSchema};
+use super::{create_tool_result_block, ToDocument, Tool, ToolJsonSchema};
+use super::context::ToolContext;
+use super::image_content::{image_block_from_path, is_image_path};
 
 use core::str;
-use std::{borrow::Borrow, fs, path::PathBuf};
+use std::{borrow::Borrow, collections::HashMap, fs::{self, File}, io::{BufRead, BufReader}, path::{Path, PathBuf}};
 use anyhow::{bail,  Result};
+use async_trait::async_trait;
 use aws_sdk_bedrockruntime::types::{DocumentBlock, DocumentFormat, DocumentSource, ToolResultBlock, ToolResultContentBlock, ToolResultStatus};
 use aws_smithy_types::{Blob, Document};
 use serde_json::json;
+use tokio::task;
 
 
 // READ_FILE tool
 pub const READ_FILE_NAME: &str = "READ_FILE";
-pub const READ_FILE_DESCRIPTION: &str = "Read the contents of a file at the specified path. Use this when you need to examine the contents of an existing file.";
+pub const READ_FILE_DESCRIPTION: &str = "Read the contents of a file at the specified path. Use this when you need to examine the contents of an existing file. Images (png, jpg, gif, webp, svg) are returned as images you can see. Large text files, including csv, md, html and txt files, are returned in parts, use offset and limit to read the rest.";
+
+// text returned when maxBytes is not given, and the most that can be asked for
+pub const DEFAULT_MAX_BYTES: usize = 100_000;
+pub const MAX_BYTES: usize = 400_000;
+// Bedrock rejects larger documents
+pub const MAX_DOCUMENT_BYTES: u64 = 4_500_000;
+
 pub fn read_file_schema() -> Result<Document> {
     let json_schema = json!({
         "type": "object",
@@ -19,6 +30,18 @@ pub fn read_file_schema() -> Result<Document> {
                 "type": "string",
                 "description": "The path of the file to read."
             },
+            "offset": {
+                "type": "number",
+                "description": "For text files. The line number to start reading from, starting at 1. Optional. The default value is 1."
+            },
+            "limit": {
+                "type": "number",
+                "description": "For text files. The number of lines to read. Optional. By default, lines are read until maxBytes."
+            },
+            "maxBytes": {
+                "type": "number",
+                "description": "For text files. The maximum size of the text to return in bytes, up to ".to_owned() + &MAX_BYTES.to_string() + ". Optional. The default value is " + &DEFAULT_MAX_BYTES.to_string() + "."
+            },
         },
         "required": ["path"],
     });
@@ -26,6 +49,29 @@ pub fn read_file_schema() -> Result<Document> {
     Ok(document)
 }
 
+pub struct ReadFileTool;
+
+#[async_trait]
+impl Tool for ReadFileTool {
+    fn name(&self) -> &str {
+        READ_FILE_NAME
+    }
+
+    fn description(&self) -> &str {
+        READ_FILE_DESCRIPTION
+    }
+
+    fn input_schema(&self) -> Result<Document> {
+        read_file_schema()
+    }
+
+    async fn execute(&self, id: &str, input: &Document, _context: &ToolContext) -> Result<ToolResultBlock> {
+        let id = id.to_owned();
+        let input = input.clone();
+        task::spawn_blocking(move || read_file(&id, &input)).await?
+    }
+}
+
 pub fn read_file(id: &str, input: &Document) -> Result<ToolResultBlock> {
     let input_object = match input.as_object() {
         Some(object) => object,
@@ -49,16 +95,25 @@ pub fn read_file(id: &str, input: &Document) -> Result<ToolResultBlock> {
     };
 
     let path = PathBuf::from(path);
-    let bytes = match fs::read(&path) {
-        Ok(bytes) => bytes,
-        Err(err) => {
-            return create_tool_result_block(id, &err.to_string(), ToolResultStatus::Error)
-        },
-    };
+    if is_image_path(&path) {
+        return read_image(id, &path);
+    }
 
     if let Some(extension) = path.extension() {
         let str = extension.to_str().unwrap_or("");
-        if let Ok(format) = get_format_from_extension(str) {
+        let size = fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0);
+        let format = get_format_from_extension(str).ok()
+            .filter(|format| !(is_text_format(format) && (is_paged(input_object) || size > DEFAULT_MAX_BYTES as u64)));
+        if let Some(format) = format {
+            if size > MAX_DOCUMENT_BYTES {
+                return create_tool_result_block(id, &format!("{} is {} bytes, documents can be at most {} bytes.", path.display(), size, MAX_DOCUMENT_BYTES), ToolResultStatus::Error)
+            }
+            let bytes = match fs::read(&path) {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    return create_tool_result_block(id, &err.to_string(), ToolResultStatus::Error)
+                },
+            };
             let document = DocumentBlock::builder()
                 .name("file_read")
                 .format(format)
@@ -76,16 +131,127 @@ pub fn read_file(id: &str, input: &Document) -> Result<ToolResultBlock> {
         }
     }
 
-    let string = match str::from_utf8(&bytes) {
-        Ok(string) => string,
+    match read_text(&path, input_object) {
+        Ok(content) => create_tool_result_block(id, &content, ToolResultStatus::Success),
+        Err(err) => create_tool_result_block(id, &err.to_string(), ToolResultStatus::Error),
+    }
+}
+
+// The lines from offset, up to limit lines and maxBytes, with the total line count and size so the model can read the rest.
+fn read_text(path: &Path, input_object: &HashMap<String, Document>) -> Result<String> {
+    let get_number = |key: &str| input_object.get(key).and_then(|value| value.as_number()).map(|number| number.to_f64_lossy());
+    let offset = match get_number("offset") {
+        Some(offset) if offset < 1.0 => bail!("offset should be 1 or more."),
+        Some(offset) => offset as usize,
+        None => 1,
+    };
+    let limit = match get_number("limit") {
+        Some(limit) if limit < 1.0 => bail!("limit should be 1 or more."),
+        Some(limit) => Some(limit as usize),
+        None => None,
+    };
+    let max_bytes = match get_number("maxBytes") {
+        Some(max_bytes) if max_bytes < 1.0 => bail!("maxBytes should be 1 or more."),
+        Some(max_bytes) => (max_bytes as usize).min(MAX_BYTES),
+        None => DEFAULT_MAX_BYTES,
+    };
+
+    let file = File::open(path)?;
+    let total_bytes = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut content = String::new();
+    let mut total_lines = 0;
+    let mut last_line = 0;
+    // the line cut at maxBytes, when a single line is longer than that
+    let mut cut_line: Option<usize> = None;
+    let mut full = false;
+    let mut line: Vec<u8> = vec![];
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        total_lines += 1;
+        let in_range = total_lines >= offset && limit.is_none_or(|limit| total_lines < offset + limit);
+        if !in_range || full {
+            continue;
+        }
+        let text = match str::from_utf8(&line) {
+            Ok(text) => text,
+            Err(_) => bail!("{} is not a UTF-8 text file (line {}).", path.display(), total_lines),
+        };
+        if content.len() + text.len() > max_bytes {
+            full = true;
+            if content.is_empty() {
+                let mut end = max_bytes;
+                while !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                content.push_str(&text[..end]);
+                cut_line = Some(total_lines);
+                last_line = total_lines;
+            }
+            continue;
+        }
+        content.push_str(text);
+        last_line = total_lines;
+    }
+
+    if total_lines == 0 {
+        return Ok(format!("File read: {} is empty.", path.display()));
+    }
+    if offset > total_lines {
+        bail!("offset {} is past the end of {}, which has {} lines.", offset, path.display(), total_lines)
+    }
+
+    let mut header = format!(
+        "File read: {}. Lines {}-{} of {} lines, total size {} bytes.",
+        path.display(), offset, last_line, total_lines, total_bytes
+    );
+    if let Some(cut_line) = cut_line {
+        header.push_str(&format!(" Line {} is longer than maxBytes ({} bytes) and was cut.", cut_line, max_bytes));
+    }
+    let requested_last_line = match limit {
+        Some(limit) => (offset + limit - 1).min(total_lines),
+        None => total_lines,
+    };
+    if last_line < requested_last_line {
+        header.push_str(&format!(
+            " Truncated at maxBytes ({} bytes). To read more, use offset {}.",
+            max_bytes, last_line + 1
+        ));
+    } else if last_line < total_lines {
+        header.push_str(&format!(" To read more, use offset {}.", last_line + 1));
+    }
+    Ok(format!("{}\n\n{}", header, content))
+}
+
+// downscaled if needed, so it can be sent to the model
+fn read_image(id: &str, path: &Path) -> Result<ToolResultBlock> {
+    let image = match image_block_from_path(path) {
+        Ok(image) => image,
         Err(err) => {
-            return create_tool_result_block(id, &err.to_string(), ToolResultStatus::Error)
+            return create_tool_result_block(id, &format!("failed to read image {}: {}", path.display(), err), ToolResultStatus::Error)
         },
     };
-    let content = format!("File read with Content: {}", string);
-    return create_tool_result_block(id, &content, ToolResultStatus::Success)
+    let tool_result = ToolResultBlock::builder()
+        .tool_use_id(id.to_owned())
+        .content(ToolResultContentBlock::Text(format!("Image read: {}", path.display())))
+        .content(ToolResultContentBlock::Image(image))
+        .status(ToolResultStatus::Success)
+        .build()?;
+    Ok(tool_result)
+}
+
+// document formats that are plain text, which can also be read in parts
+fn is_text_format(format: &DocumentFormat) -> bool {
+    matches!(format, DocumentFormat::Csv | DocumentFormat::Html | DocumentFormat::Md | DocumentFormat::Txt)
 }
 
+fn is_paged(input_object: &HashMap<String, Document>) -> bool {
+    ["offset", "limit", "maxBytes"].iter().any(|key| input_object.contains_key(*key))
+}
 
 fn get_format_from_extension(extension: &str) -> Result<DocumentFormat> {
     match extension.to_lowercase().borrow() {
@@ -100,4 +266,96 @@ fn get_format_from_extension(extension: &str) -> Result<DocumentFormat> {
         "xlsx" => Ok(DocumentFormat::Xlsx),
         _ => bail!(format!("No format available for extension: {extension}"))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // a file in the temp folder with the given content, removed when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, content: &str) -> Self {
+            let path = env::temp_dir().join(format!("bedrock_assistant-read_file-{}-{}", std::process::id(), name));
+            fs::write(&path, content).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn input(values: &[(&str, f64)]) -> HashMap<String, Document> {
+        values.iter()
+            .map(|(key, value)| (key.to_string(), Document::Number(aws_smithy_types::Number::Float(*value))))
+            .collect()
+    }
+
+    fn numbered_lines(count: usize) -> String {
+        (1..=count).map(|line| format!("line {line}\n")).collect()
+    }
+
+    #[test]
+    fn reads_the_whole_small_file() {
+        let file = TempFile::new("whole.txt", &numbered_lines(3));
+        let text = read_text(&file.0, &input(&[])).unwrap();
+        assert!(text.contains("Lines 1-3 of 3 lines"));
+        assert!(text.ends_with("line 1\nline 2\nline 3\n"));
+        assert!(!text.contains("To read more"));
+    }
+
+    #[test]
+    fn reads_lines_from_offset_up_to_limit() {
+        let file = TempFile::new("offset.txt", &numbered_lines(10));
+        let text = read_text(&file.0, &input(&[("offset", 4.0), ("limit", 2.0)])).unwrap();
+        assert!(text.contains("Lines 4-5 of 10 lines"));
+        assert!(text.ends_with("\n\nline 4\nline 5\n"));
+        assert!(text.contains("To read more, use offset 6."));
+    }
+
+    #[test]
+    fn stops_at_max_bytes_on_a_line_boundary() {
+        let file = TempFile::new("max_bytes.txt", &numbered_lines(10));
+        // each line is 7 bytes
+        let text = read_text(&file.0, &input(&[("maxBytes", 20.0)])).unwrap();
+        assert!(text.contains("Lines 1-2 of 10 lines"));
+        assert!(text.contains("Truncated at maxBytes (20 bytes). To read more, use offset 3."));
+        assert!(text.ends_with("\n\nline 1\nline 2\n"));
+    }
+
+    #[test]
+    fn cuts_a_line_longer_than_max_bytes() {
+        let file = TempFile::new("long_line.txt", &format!("{}\nshort\n", "é".repeat(10)));
+        let text = read_text(&file.0, &input(&[("maxBytes", 5.0)])).unwrap();
+        assert!(text.contains("Line 1 is longer than maxBytes (5 bytes) and was cut."));
+        assert!(text.ends_with("\n\néé"));
+    }
+
+    #[test]
+    fn rejects_invalid_paging() {
+        let file = TempFile::new("invalid.txt", &numbered_lines(2));
+        assert!(read_text(&file.0, &input(&[("offset", 0.0)])).is_err());
+        assert!(read_text(&file.0, &input(&[("limit", 0.0)])).is_err());
+        let err = read_text(&file.0, &input(&[("offset", 5.0)])).unwrap_err();
+        assert_eq!(err.to_string(), format!("offset 5 is past the end of {}, which has 2 lines.", file.0.display()));
+    }
+
+    #[test]
+    fn pages_text_documents() {
+        let file = TempFile::new("paged.csv", &numbered_lines(10));
+        let input = Document::Object(HashMap::from([
+            ("path".to_owned(), Document::String(file.0.display().to_string())),
+            ("limit".to_owned(), Document::Number(aws_smithy_types::Number::PosInt(1))),
+        ]));
+        let result = read_file("id", &input).unwrap();
+        match result.content() {
+            [ToolResultContentBlock::Text(text)] => assert!(text.ends_with("\n\nline 1\n")),
+            content => panic!("unexpected content: {content:?}"),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use super::Tool;
+use super::read_file::ReadFileTool;
+use super::list_directory::ListDirectoryTool;
+use super::find_files::FindFilesTool;
+use super::search_files::SearchFilesTool;
+use super::write_file::WriteFileTool;
+use super::edit_file::EditFileTool;
+use super::generate_image::GenerateImageTool;
+use super::edit_image::EditImageTool;
+use super::run_python::RunPythonTool;
+use super::run_shell::RunShellTool;
+use super::python_kernel::PythonKernel;
+
+use std::{fmt, sync::Arc};
+use anyhow::{bail, Result};
+use aws_sdk_bedrockruntime::Client;
+use aws_sdk_bedrockruntime::types::{Tool as BedrockTool, ToolConfiguration, ToolInputSchema, ToolSpecification};
+
+use crate::config::Config;
+use crate::terminal_service::TerminalService;
+
+
+#[derive(Clone, Default)]
+pub struct ToolRegistry {
+    tools: Vec<Arc<dyn Tool>>
+}
+
+impl fmt::Debug for ToolRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.tools.iter().map(|tool| tool.name())).finish()
+    }
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // READ_FILE, LIST_DIRECTORY, FIND_FILES, SEARCH_FILES, WRITE_FILE, EDIT_FILE, GENERATE_IMAGE, EDIT_IMAGE, RUN_PYTHON and RUN_SHELL.
+    // The image tools are left out, with a warning, when the image model is not supported.
+    pub fn with_builtin_tools(client: &Client, config: &Config, python_kernel: Option<Arc<PythonKernel>>) -> Result<Self> {
+        let mut registry = Self::new();
+        registry.register(ReadFileTool)?;
+        registry.register(ListDirectoryTool)?;
+        registry.register(FindFilesTool)?;
+        registry.register(SearchFilesTool)?;
+        registry.register(WriteFileTool::default())?;
+        registry.register(EditFileTool::default())?;
+        match (GenerateImageTool::new(client), EditImageTool::new(client)) {
+            (Ok(generate_image), Ok(edit_image)) => {
+                registry.register(generate_image)?;
+                registry.register(edit_image)?;
+            },
+            (Err(err), _) | (_, Err(err)) => {
+                TerminalService::new().log_error(&format!("{err} Image generation and editing are disabled."))?;
+            },
+        }
+        registry.register(RunPythonTool::new(config.python_sandbox.clone(), python_kernel))?;
+        registry.register(RunShellTool::new(config.shell.clone()))?;
+        Ok(registry)
+    }
+
+    pub fn register(&mut self, tool: impl Tool + 'static) -> Result<()> {
+        if self.get(tool.name()).is_some() {
+            bail!("A tool with name {} is already registered", tool.name())
+        }
+        self.tools.push(Arc::new(tool));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Tool>> {
+        self.tools.iter().find(|tool| tool.name() == name).cloned()
+    }
+
+    pub fn tool_configuration(&self) -> Result<ToolConfiguration> {
+        let mut tools: Vec<BedrockTool> = vec![];
+        for tool in &self.tools {
+            let specification = ToolSpecification::builder()
+                .name(tool.name())
+                .description(tool.description())
+                .input_schema(ToolInputSchema::Json(tool.input_schema()?))
+                .build()?;
+            tools.push(BedrockTool::ToolSpec(specification));
+        }
+
+        let tool_configuration = ToolConfiguration::builder()
+            .set_tools(Some(tools))
+            .build()?;
+        Ok(tool_configuration)
+    }
+
+    // system prompt section for tools that provide their own instructions
+    pub fn instructions(&self) -> String {
+        self.tools.iter()
+            .filter_map(|tool| tool.instructions().map(|instructions| format!("- {}: {}", tool.name(), instructions)))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
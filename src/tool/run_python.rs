@@ -1,16 +1,28 @@
 use crate::model_constants::{BEDROCK_ASSISTANT_PYTHON, BEDROCK_ASSISTANT_PYTHON_KEY};
+use crate::terminal_service::TerminalService;
 
-use super::{create_tool_result_block, ToDocument, ToolJsonSchema};
+use super::{append_tool_result_content, create_tool_result_block, get_string, get_string_list, ToDocument, Tool, ToolJsonSchema};
+use super::image_content::{image_block_from_path, is_image_path};
+use super::context::ToolContext;
+use super::file_change::backup_file;
+use super::journal::Change;
+use super::permission::ToolPolicy;
+use super::sandbox::{FileSnapshot, Sandbox, SandboxConfig};
+use super::python_kernel::PythonKernel;
 
 use core::str;
-use std::{env, fs::{self, File}, io::Write, path::Path};
+use std::{env, fs::{self, File}, io::Write, path::{self, Path, PathBuf}, sync::Arc};
 use anyhow::Result;
+use async_trait::async_trait;
 use aws_sdk_bedrockruntime::types::{ToolResultBlock, ToolResultContentBlock, ToolResultStatus};
 use aws_smithy_types::Document;
 use serde_json::json;
 
 
-// READ_FILE tool
+// RUN_PYTHON tool
+// images created by the code that are sent back to the model, so it can see the plots it drew
+const MAX_RESULT_IMAGES: usize = 4;
+
 pub const RUN_PYTHON_NAME: &str = "RUN_PYTHON";
 pub const RUN_PYTHON_DESCRIPTION: &str = "Run Python code for data analysis, data exploration, math, etc.";
 pub fn run_python_schema() -> Result<Document> {
@@ -31,7 +43,12 @@ pub fn run_python_schema() -> Result<Document> {
             },
             "path": {
                 "type": "string",
-                "description": "The path of the folder where the code sandbox should be created. It should be the same as all the artifacts generated by the code. Default to the current working directory. "
+                "description": "The path of the folder where the code and the files it creates are saved. The code runs in an empty scratch folder, and the files it saves there are copied to this folder when it ends. Default to the current working directory. "
+            },
+            "outputs": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "Names of the files to copy to `path`, relative to the scratch folder. Optional. Default to every file the code saves."
             },
         },
         "required": ["title", "description", "code", "path"],
@@ -41,7 +58,62 @@ pub fn run_python_schema() -> Result<Document> {
     Ok(document)
 }
 
-pub fn run_python(id: &str, input: &Document) -> Result<ToolResultBlock> {
+pub fn python_executable() -> String {
+    env::var(BEDROCK_ASSISTANT_PYTHON_KEY).unwrap_or(BEDROCK_ASSISTANT_PYTHON.to_owned())
+}
+
+pub struct RunPythonTool {
+    sandbox: Sandbox,
+    // runs the code in a persistent session instead of a new process per call
+    kernel: Option<Arc<PythonKernel>>,
+}
+
+impl RunPythonTool {
+    pub fn new(sandbox_config: SandboxConfig, kernel: Option<Arc<PythonKernel>>) -> Self {
+        Self {
+            sandbox: Sandbox::new(sandbox_config),
+            kernel,
+        }
+    }
+}
+
+#[async_trait]
+impl Tool for RunPythonTool {
+    fn name(&self) -> &str {
+        RUN_PYTHON_NAME
+    }
+
+    fn description(&self) -> &str {
+        RUN_PYTHON_DESCRIPTION
+    }
+
+    fn input_schema(&self) -> Result<Document> {
+        run_python_schema()
+    }
+
+    fn instructions(&self) -> Option<String> {
+        self.kernel.as_ref()?;
+        Some("Python code runs in a persistent session. Variables, imports and data loaded by earlier calls are still available, so do not load them again.".to_owned())
+    }
+
+    fn default_policy(&self) -> ToolPolicy {
+        ToolPolicy::Ask
+    }
+
+    fn preview(&self, input: &Document) -> String {
+        format!(
+            "Folder: {}\n\n{}",
+            get_string(input, "path").unwrap_or(""),
+            get_string(input, "code").unwrap_or("")
+        )
+    }
+
+    async fn execute(&self, id: &str, input: &Document, context: &ToolContext) -> Result<ToolResultBlock> {
+        run_python(id, input, &self.sandbox, self.kernel.as_deref(), context).await
+    }
+}
+
+pub async fn run_python(id: &str, input: &Document, sandbox: &Sandbox, kernel: Option<&PythonKernel>, context: &ToolContext) -> Result<ToolResultBlock> {
     let input_object = match input.as_object() {
         Some(object) => object,
         None => {
@@ -115,7 +187,7 @@ pub fn run_python(id: &str, input: &Document) -> Result<ToolResultBlock> {
         }
     }
     if !path.to_str().unwrap_or("").is_empty() {
-        match fs::create_dir_all(path) {
+        match context.journal.create_dir_all(id, path) {
             Ok(_) => {},
             Err(err) => {
                 return create_tool_result_block(id, &err.to_string(), ToolResultStatus::Error)
@@ -123,51 +195,164 @@ pub fn run_python(id: &str, input: &Document) -> Result<ToolResultBlock> {
         };
     }
 
-    match open::that_detached(path) {
-        Ok(_) => {},
-        Err(_) => {},
-    };
+    context.open_folder(path);
 
     let file_name = Path::new(&format!("{}.py", id)).to_owned();
-    let file_path = path.join(file_name);
+    let file_path = path.join(&file_name);
 
+    let change = match replace_change(id, &file_path, context) {
+        Ok(change) => change,
+        Err(err) => {
+            return create_tool_result_block(id, &format!("{:#}", err), ToolResultStatus::Error)
+        },
+    };
     let mut file = File::create(file_path.clone())?;
 
     let content = format!("#{}\n#{}\n\n{}", title, description, code);
     file.write_all(content.as_bytes())?;
+    context.journal.record(id, &file_path, change);
+
+    context.open_file(&file_path);
 
-    match open::that_detached(file_path.clone()) {
-        Ok(_) => {},
-        Err(_) => {},
+    // each run starts in an empty folder, so the code does not touch the user's files by relative path
+    let scratch = match scratch_directory(id) {
+        Ok(scratch) => scratch,
+        Err(err) => {
+            return create_tool_result_block(id, &format!("failed to create the scratch folder: {}", err), ToolResultStatus::Error)
+        },
+    };
+    let script_path = path::absolute(&file_path)?;
+    let snapshot = FileSnapshot::new(&scratch);
+    let mut terminal = TerminalService::new();
+    let (output, sandbox_config) = match kernel {
+        Some(kernel) => {
+            let filename = script_path.to_string_lossy();
+            (kernel.execute(&content, &scratch, &filename).await, kernel.sandbox_config())
+        },
+        None => {
+            (sandbox.run(&python_executable(), [script_path.as_os_str()], &scratch).await, &sandbox.config)
+        },
+    };
+    let output = match output {
+        Ok(output) => output,
+        Err(err) => {
+            let _ = fs::remove_dir_all(&scratch);
+            terminal.log_error(&format!("error running command: {}", err))?;
+            return create_tool_result_block(id, &format!("error running command: {}", err), ToolResultStatus::Error)
+        },
     };
 
-    let python = env::var(BEDROCK_ASSISTANT_PYTHON_KEY).unwrap_or(BEDROCK_ASSISTANT_PYTHON.to_owned());
-    let output = match std::process::Command::new(python)
-        .arg(file_path.clone())
-        .output() {
-            Ok(output) => {
-                println!("{:?}", output);
-                let string = match str::from_utf8(&output.stdout) {
-                    Ok(string) => string.to_owned(),
-                    Err(_) => "".to_owned(),
-                };
-                string
-            },
-            Err(err) => {
-                println!("error running command: {}", err.to_string());
-                return create_tool_result_block(id, &format!("error running command: {}", err.to_string()), ToolResultStatus::Error)
-            },
-        };
+    terminal.log_process_output(title, output.exit_code, &output.stdout, &output.stderr, output.termination_message(sandbox_config).as_deref())?;
+    let mut tool_result = output.to_tool_result_block(id, sandbox_config)?;
+
+    let outputs = get_string_list(input, "outputs");
+    let copied = copy_outputs(id, &scratch, &snapshot, path, outputs.as_deref(), context);
+    let _ = fs::remove_dir_all(&scratch);
+    let images = match copied {
+        Ok((copied, missing)) => {
+            if !missing.is_empty() {
+                tool_result = append_tool_result_content(tool_result, vec![
+                    ToolResultContentBlock::Text(format!("Outputs not found in the scratch folder: {}.", missing.join(", ")))
+                ])?;
+            }
+            copied.into_iter().filter(|path| is_image_path(path)).collect::<Vec<PathBuf>>()
+        },
+        Err(err) => {
+            return append_tool_result_content(tool_result, vec![
+                ToolResultContentBlock::Text(format!("Failed to copy the files created by the code: {}", err))
+            ])
+        },
+    };
+
+    if images.is_empty() {
+        return Ok(tool_result);
+    }
+    for image in images.iter().take(MAX_RESULT_IMAGES) {
+        let _ = terminal.log_image_preview(image, context.image_preview);
+        context.open_file(image);
+    }
+    append_tool_result_content(tool_result, image_contents(&images))
+}
+
+// `<temp folder>/bedrock_assistant/python-<tool use id>`, emptied if it already exists
+fn scratch_directory(id: &str) -> Result<PathBuf> {
+    let scratch = env::temp_dir().join("bedrock_assistant").join(format!("python-{id}"));
+    if scratch.exists() {
+        fs::remove_dir_all(&scratch)?;
+    }
+    fs::create_dir_all(&scratch)?;
+    Ok(scratch)
+}
 
-    let content = if output.is_empty() {
-        "Code executed".to_owned()
-    } else {
-        format!("Code executed with output: {}", output)
+// Copies the files the code saved in the scratch folder, or only `outputs` when given, to `destination`.
+// Returns the copied files and the outputs that were not found.
+fn copy_outputs(id: &str, scratch: &Path, snapshot: &FileSnapshot, destination: &Path, outputs: Option<&[String]>, context: &ToolContext) -> Result<(Vec<PathBuf>, Vec<String>)> {
+    let created: Vec<PathBuf> = snapshot.changed_files(scratch)
+        .into_iter()
+        .filter_map(|path| path.strip_prefix(scratch).ok().map(Path::to_path_buf))
+        .collect();
+    let (selected, missing) = match outputs {
+        Some(outputs) => {
+            let outputs: Vec<PathBuf> = outputs.iter().map(PathBuf::from).collect();
+            let missing = outputs.iter()
+                .filter(|output| !created.contains(output))
+                .map(|output| output.display().to_string())
+                .collect();
+            (created.into_iter().filter(|path| outputs.contains(path)).collect(), missing)
+        },
+        None => (created, vec![]),
     };
-    let tool_result = ToolResultBlock::builder()
-        .tool_use_id(id.to_owned())
-        .content(ToolResultContentBlock::Text(content))
-        .status(ToolResultStatus::Success)
-        .build()?;
-    Ok(tool_result)
+
+    let mut copied: Vec<PathBuf> = vec![];
+    for relative in selected {
+        let target = destination.join(&relative);
+        if let Some(parent) = target.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            context.journal.create_dir_all(id, parent)?;
+        }
+        let change = replace_change(id, &target, context)?;
+        fs::copy(scratch.join(&relative), &target)?;
+        context.journal.record(id, &target, change);
+        copied.push(target);
+    }
+    Ok((copied, missing))
+}
+
+// A file about to be replaced is backed up first, so `/undo` can restore it.
+fn replace_change(id: &str, path: &Path, context: &ToolContext) -> Result<Change> {
+    match path.exists() {
+        true => Ok(Change::ModifiedFile(Some(backup_file(path, id, context)?))),
+        false => Ok(Change::CreatedFile),
+    }
+}
+
+// the images, with a text block listing them and any image that could not be attached
+fn image_contents(images: &[PathBuf]) -> Vec<ToolResultContentBlock> {
+    let mut attached: Vec<String> = vec![];
+    let mut skipped: Vec<String> = vec![];
+    let mut contents: Vec<ToolResultContentBlock> = vec![];
+
+    for path in images {
+        let name = path.display().to_string();
+        if contents.len() >= MAX_RESULT_IMAGES {
+            skipped.push(format!("{name} (limit of {MAX_RESULT_IMAGES} images reached)"));
+            continue;
+        }
+        match image_block_from_path(path) {
+            Ok(image) => {
+                attached.push(name);
+                contents.push(ToolResultContentBlock::Image(image));
+            },
+            Err(err) => skipped.push(format!("{name} ({err})")),
+        }
+    }
+
+    let mut text: Vec<String> = vec![];
+    if !attached.is_empty() {
+        text.push(format!("Images created by the code: {}.", attached.join(", ")));
+    }
+    if !skipped.is_empty() {
+        text.push(format!("Images created by the code but not attached: {}.", skipped.join(", ")));
+    }
+    contents.insert(0, ToolResultContentBlock::Text(text.join(" ")));
+    contents
 }
@@ -0,0 +1,583 @@
+use crate::terminal_service::TerminalService;
+
+use super::{create_tool_result_block, get_number, get_string, ToDocument, Tool, ToolJsonSchema};
+use super::context::ToolContext;
+use super::permission::ToolPolicy;
+use super::sandbox::{FileSnapshot, Sandbox, SandboxConfig};
+
+use std::{path::{Path, PathBuf}, time::Duration};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use aws_sdk_bedrockruntime::types::{ToolResultBlock, ToolResultStatus};
+use aws_smithy_types::Document;
+use serde_json::json;
+
+
+// RUN_SHELL tool
+pub const RUN_SHELL_NAME: &str = "RUN_SHELL";
+pub const RUN_SHELL_DESCRIPTION: &str = "Run a shell command, such as `ls -la`, `git log --oneline -5` or `du -sh *`, and return its exit code, stdout and stderr. Use this for quick commands instead of running them from python.";
+
+// programs that cannot run unless the user configures their own deny list
+pub const DEFAULT_SHELL_DENY: [&str; 8] = ["sudo", "su", "doas", "shutdown", "reboot", "halt", "mkfs", "dd"];
+
+// shell words that come before the program in a command
+const SHELL_KEYWORDS: [&str; 13] = ["if", "then", "else", "elif", "fi", "do", "done", "while", "until", "!", "{", "}", "time"];
+// segments starting with these do not run a program
+const LOOP_KEYWORDS: [&str; 5] = ["for", "case", "esac", "select", "in"];
+// programs running the program given in their arguments, with their options taking a value
+const WRAPPERS: [(&str, &[&str]); 11] = [
+    ("env", &["-u", "--unset", "-C", "--chdir"]),
+    ("command", &[]),
+    ("builtin", &[]),
+    ("exec", &["-a"]),
+    ("nohup", &[]),
+    ("setsid", &[]),
+    ("nice", &["-n", "--adjustment"]),
+    ("stdbuf", &["-i", "-o", "-e"]),
+    ("xargs", &["-I", "-n", "-P", "-L", "-d", "-E", "-s", "-a", "--max-args", "--max-procs", "--delimiter", "--arg-file"]),
+    ("sudo", &["-u", "-g", "-p", "-C", "-D", "-h", "-r", "-t", "-U"]),
+    ("doas", &["-u", "-C"]),
+];
+// programs running a script given with `-c`
+const SHELLS: [&str; 6] = ["sh", "bash", "dash", "zsh", "ksh", "su"];
+// how deep `sh -c "sh -c ..."` is followed
+const MAX_NESTING: usize = 4;
+
+// Which commands RUN_SHELL may run, and for how long.
+#[derive(Clone, Debug)]
+pub struct ShellConfig {
+    pub timeout: Duration,
+    // when not empty, only these programs can run
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl ShellConfig {
+    // Checks every program of the command, including the ones after pipes, `&&`, `;`, in `$(...)`,
+    // and the ones run by wrappers such as `env`, `xargs` or `sh -c`.
+    // The deny list is best-effort: a program can still be run in ways that cannot be seen before running the command.
+    pub fn check(&self, command: &str) -> Result<()> {
+        for program in command_programs(command) {
+            let Some(program) = program else {
+                if !self.allow.is_empty() {
+                    bail!("The command runs a program that cannot be known before running it, such as `$command`. Only the programs in the user's shell allow list can run: {}.", self.allow.join(", "))
+                }
+                continue;
+            };
+            if self.deny.contains(&program) {
+                bail!("{program} is not allowed by the user's shell deny list. Do not try to run it another way.")
+            }
+            if !self.allow.is_empty() && !self.allow.contains(&program) {
+                bail!("{program} is not in the user's shell allow list. Allowed programs: {}.", self.allow.join(", "))
+            }
+        }
+        Ok(())
+    }
+}
+
+// parse program names separated by commas, for example `git,ls,du`
+pub fn parse_program_list(string: &str) -> Vec<String> {
+    string.split(',')
+        .map(|program| program.trim().to_owned())
+        .filter(|program| !program.is_empty())
+        .collect()
+}
+
+// The programs a command runs, by the name of their executable.
+// None for a program that depends on an expansion, such as `$command` or `$(which git)`.
+pub fn command_programs(command: &str) -> Vec<Option<String>> {
+    nested_command_programs(command, 0)
+}
+
+fn nested_command_programs(command: &str, depth: usize) -> Vec<Option<String>> {
+    if depth > MAX_NESTING {
+        return vec![None];
+    }
+    let mut programs = vec![];
+    for segment in split_commands(command) {
+        segment_programs(&segment, depth, &mut programs);
+    }
+    programs
+}
+
+// A word of a command with its quotes and escapes removed.
+#[derive(Clone, Debug, Default)]
+struct ShellWord {
+    text: String,
+    // contains `$`, so its value is only known when the command runs
+    dynamic: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Quote {
+    None,
+    Single,
+    Double,
+}
+
+// A `$(...)` or backtick substitution being read, with what to go back to when it is closed.
+struct Substitution {
+    closing: char,
+    quote: Quote,
+    segment: Vec<ShellWord>,
+    word: ShellWord,
+}
+
+// Splits a command into simple commands at `|`, `&`, `;`, new lines, parentheses and command substitutions.
+fn split_commands(command: &str) -> Vec<Vec<ShellWord>> {
+    let mut segments: Vec<Vec<ShellWord>> = vec![];
+    let mut segment: Vec<ShellWord> = vec![];
+    let mut word: Option<ShellWord> = None;
+    let mut quote = Quote::None;
+    let mut substitutions: Vec<Substitution> = vec![];
+
+    fn end_word(word: &mut Option<ShellWord>, segment: &mut Vec<ShellWord>) {
+        if let Some(word) = word.take() {
+            segment.push(word);
+        }
+    }
+    fn end_segment(word: &mut Option<ShellWord>, segment: &mut Vec<ShellWord>, segments: &mut Vec<Vec<ShellWord>>) {
+        end_word(word, segment);
+        if !segment.is_empty() {
+            segments.push(std::mem::take(segment));
+        }
+    }
+
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        // the output of a substitution is part of the word it appears in
+        let opens_substitution = c == '`' || (c == '$' && chars.peek() == Some(&'('));
+        if quote != Quote::Single && opens_substitution && substitutions.last().is_none_or(|substitution| substitution.closing != c) {
+            if c == '$' {
+                chars.next();
+                // `$((1 + 2))` is arithmetic, not a command
+                if chars.next_if_eq(&'(').is_some() {
+                    let mut depth = 2;
+                    while depth > 0 {
+                        match chars.next() {
+                            Some('(') => depth += 1,
+                            Some(')') => depth -= 1,
+                            Some(_) => {},
+                            None => break,
+                        }
+                    }
+                    word.get_or_insert_with(ShellWord::default).dynamic = true;
+                    continue;
+                }
+            }
+            let mut outer = word.take().unwrap_or_default();
+            outer.dynamic = true;
+            substitutions.push(Substitution {
+                closing: if c == '$' { ')' } else { '`' },
+                quote,
+                segment: std::mem::take(&mut segment),
+                word: outer,
+            });
+            quote = Quote::None;
+            continue;
+        }
+
+        match quote {
+            Quote::Single => match c {
+                '\'' => quote = Quote::None,
+                c => word.get_or_insert_with(ShellWord::default).text.push(c),
+            },
+            Quote::Double => match c {
+                '"' => quote = Quote::None,
+                '\\' => {
+                    if let Some(next) = chars.next() {
+                        word.get_or_insert_with(ShellWord::default).text.push(next);
+                    }
+                },
+                c => {
+                    let current = word.get_or_insert_with(ShellWord::default);
+                    current.dynamic |= c == '$';
+                    current.text.push(c);
+                },
+            },
+            Quote::None => match c {
+                '\'' => {
+                    word.get_or_insert_with(ShellWord::default);
+                    quote = Quote::Single;
+                },
+                '"' => {
+                    word.get_or_insert_with(ShellWord::default);
+                    quote = Quote::Double;
+                },
+                '\\' => match chars.next() {
+                    // a line continuation
+                    Some('\n') | None => {},
+                    Some(next) => word.get_or_insert_with(ShellWord::default).text.push(next),
+                },
+                '#' if word.is_none() => {
+                    while chars.next_if(|next| *next != '\n').is_some() {}
+                },
+                '`' | ')' if substitutions.last().is_some_and(|substitution| substitution.closing == c) => {
+                    end_segment(&mut word, &mut segment, &mut segments);
+                    if let Some(substitution) = substitutions.pop() {
+                        segment = substitution.segment;
+                        word = Some(substitution.word);
+                        quote = substitution.quote;
+                    }
+                },
+                '&' if word.as_ref().is_some_and(|word| word.text.ends_with(['<', '>'])) || chars.peek() == Some(&'>') => {
+                    word.get_or_insert_with(ShellWord::default).text.push(c);
+                },
+                '|' | '&' | ';' | '\n' | '(' | ')' => end_segment(&mut word, &mut segment, &mut segments),
+                '<' | '>' => {
+                    // a redirection is its own word, with the number of the file descriptor before it
+                    if word.as_ref().is_some_and(|word| !word.text.chars().all(|c| c.is_ascii_digit() || c == '<' || c == '>')) {
+                        end_word(&mut word, &mut segment);
+                    }
+                    word.get_or_insert_with(ShellWord::default).text.push(c);
+                },
+                c if c.is_whitespace() => end_word(&mut word, &mut segment),
+                c => {
+                    let current = word.get_or_insert_with(ShellWord::default);
+                    current.dynamic |= c == '$';
+                    current.text.push(c);
+                },
+            },
+        }
+    }
+    // substitutions left open are still checked
+    end_segment(&mut word, &mut segment, &mut segments);
+    while let Some(substitution) = substitutions.pop() {
+        segment = substitution.segment;
+        word = Some(substitution.word);
+        end_segment(&mut word, &mut segment, &mut segments);
+    }
+    segments
+}
+
+fn is_assignment(word: &ShellWord) -> bool {
+    match word.text.split_once('=') {
+        Some((name, _)) => !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') && !name.starts_with(|c: char| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+// `>out`, `2>&1`, or an operator such as `>` whose target is the next word
+fn redirection_length(word: &ShellWord) -> Option<usize> {
+    let operator = word.text.trim_start_matches(|c: char| c.is_ascii_digit() || c == '&');
+    if !operator.starts_with(['<', '>']) {
+        return None;
+    }
+    match operator.trim_start_matches(['<', '>', '&', '|']).is_empty() {
+        true => Some(2),
+        false => Some(1),
+    }
+}
+
+fn program_name(word: &str) -> String {
+    Path::new(word).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or(word.to_owned())
+}
+
+// Adds the programs run by a simple command, including the ones it runs through its arguments.
+fn segment_programs(words: &[ShellWord], depth: usize, programs: &mut Vec<Option<String>>) {
+    let mut index = 0;
+    while let Some(word) = words.get(index) {
+        if let Some(length) = redirection_length(word) {
+            index += length;
+        } else if !word.dynamic && (is_assignment(word) || SHELL_KEYWORDS.contains(&word.text.as_str())) {
+            index += 1;
+        } else {
+            break;
+        }
+    }
+    let Some(word) = words.get(index) else {
+        return;
+    };
+    if word.dynamic {
+        programs.push(None);
+        return;
+    }
+    if LOOP_KEYWORDS.contains(&word.text.as_str()) {
+        return;
+    }
+    let program = program_name(&word.text);
+    let arguments = &words[index + 1..];
+    programs.push(Some(program.clone()));
+
+    if let Some((_, options)) = WRAPPERS.iter().find(|(wrapper, _)| *wrapper == program) {
+        // `env -S` splits its argument into a command
+        if program == "env" && arguments.iter().any(|word| word.text.starts_with("-S") || word.text.starts_with("--split-string")) {
+            programs.push(None);
+            return;
+        }
+        let mut start = 0;
+        while let Some(word) = arguments.get(start) {
+            if word.text == "--" {
+                start += 1;
+                break;
+            }
+            if !word.text.starts_with('-') || word.text == "-" {
+                break;
+            }
+            start += if options.contains(&word.text.as_str()) { 2 } else { 1 };
+        }
+        segment_programs(arguments.get(start..).unwrap_or_default(), depth, programs);
+    } else if SHELLS.contains(&program.as_str()) {
+        if let Some(script) = shell_script(&program, arguments) {
+            match script.dynamic {
+                true => programs.push(None),
+                false => programs.extend(nested_command_programs(&script.text, depth + 1)),
+            }
+        }
+    } else if program == "eval" {
+        match arguments.iter().any(|word| word.dynamic) {
+            true => programs.push(None),
+            false => {
+                let script: Vec<&str> = arguments.iter().map(|word| word.text.as_str()).collect();
+                programs.extend(nested_command_programs(&script.join(" "), depth + 1));
+            },
+        }
+    } else if program == "find" {
+        for (position, word) in arguments.iter().enumerate() {
+            if ["-exec", "-execdir", "-ok", "-okdir"].contains(&word.text.as_str()) {
+                let rest = &arguments[position + 1..];
+                let end = rest.iter().position(|word| word.text == ";" || word.text == "+").unwrap_or(rest.len());
+                segment_programs(&rest[..end], depth, programs);
+            }
+        }
+    }
+}
+
+// the script of `sh -c <script>` or `su -c <script>`
+fn shell_script<'a>(program: &str, arguments: &'a [ShellWord]) -> Option<&'a ShellWord> {
+    if program == "su" {
+        let position = arguments.iter().position(|word| word.text == "-c" || word.text == "--command")?;
+        return arguments.get(position + 1);
+    }
+    let mut has_script_flag = false;
+    for word in arguments {
+        if word.text.starts_with('-') && word.text != "-" && word.text != "--" {
+            has_script_flag |= !word.text.starts_with("--") && word.text.contains('c');
+            continue;
+        }
+        return if has_script_flag { Some(word) } else { None };
+    }
+    None
+}
+
+pub fn run_shell_schema(max_timeout: Duration) -> Result<Document> {
+    let json_schema = json!({
+        "type": "object",
+        "properties": {
+            "command": {
+                "type": "string",
+                "description": if cfg!(windows) { "The command to run with cmd." } else { "The command to run with sh. Pipes, `&&` and redirections can be used." }
+            },
+            "workingDirectory": {
+                "type": "string",
+                "description": "The directory to run the command in. Default to the current working directory."
+            },
+            "timeout": {
+                "type": "number",
+                "description": format!("Seconds before the command is killed. Optional. The default and maximum value is {}.", max_timeout.as_secs())
+            },
+        },
+        "required": ["command"],
+    });
+    let document = ToolJsonSchema::new(json_schema)?.to_document();
+    Ok(document)
+}
+
+pub struct RunShellTool {
+    config: ShellConfig,
+}
+
+impl RunShellTool {
+    pub fn new(config: ShellConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl Tool for RunShellTool {
+    fn name(&self) -> &str {
+        RUN_SHELL_NAME
+    }
+
+    fn description(&self) -> &str {
+        RUN_SHELL_DESCRIPTION
+    }
+
+    fn input_schema(&self) -> Result<Document> {
+        run_shell_schema(self.config.timeout)
+    }
+
+    fn default_policy(&self) -> ToolPolicy {
+        ToolPolicy::Ask
+    }
+
+    fn preview(&self, input: &Document) -> String {
+        let command = get_string(input, "command").unwrap_or("");
+        let mut preview = format!(
+            "Folder: {}\n\n$ {}",
+            get_string(input, "workingDirectory").unwrap_or("."),
+            command
+        );
+        if let Err(err) = self.config.check(command) {
+            preview.push_str(&format!("\n\nThe command will not run: {err}"));
+        }
+        preview
+    }
+
+    async fn execute(&self, id: &str, input: &Document, context: &ToolContext) -> Result<ToolResultBlock> {
+        run_shell(id, input, &self.config, context).await
+    }
+}
+
+pub async fn run_shell(id: &str, input: &Document, config: &ShellConfig, context: &ToolContext) -> Result<ToolResultBlock> {
+    let command = match get_string(input, "command") {
+        Some(command) if !command.trim().is_empty() => command,
+        _ => {
+            return create_tool_result_block(id, "command to run is not provided", ToolResultStatus::Error)
+        },
+    };
+    if let Err(err) = config.check(command) {
+        return create_tool_result_block(id, &err.to_string(), ToolResultStatus::Error)
+    }
+
+    let working_directory = PathBuf::from(get_string(input, "workingDirectory").unwrap_or("."));
+    if !working_directory.is_dir() {
+        return create_tool_result_block(id, &format!("{} is not a directory.", working_directory.display()), ToolResultStatus::Error)
+    }
+    let timeout = match get_number(input, "timeout") {
+        Some(seconds) if seconds > 0.0 => Duration::from_secs_f64(seconds).min(config.timeout),
+        Some(_) | None => config.timeout,
+    };
+
+    // only the time is limited, commands such as `git` need more memory than python code
+    let sandbox = Sandbox::new(SandboxConfig {
+        timeout,
+        memory_limit_mb: 0,
+        cpu_limit_secs: 0,
+        deny_network: false,
+    });
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+
+    let snapshot = FileSnapshot::new(&working_directory);
+    let mut terminal = TerminalService::new();
+    let output = match sandbox.run(shell, [flag, command], &working_directory).await {
+        Ok(output) => output,
+        Err(err) => {
+            terminal.log_error(&format!("error running command: {}", err))?;
+            return create_tool_result_block(id, &format!("error running command: {}", err), ToolResultStatus::Error)
+        },
+    };
+
+    terminal.log_process_output(&format!("$ {command}"), output.exit_code, &output.stdout, &output.stderr, output.termination_message(&sandbox.config).as_deref())?;
+    // only files under the working directory are found
+    context.journal.record_snapshot_changes(id, &snapshot, &working_directory);
+    output.to_tool_result_block(id, &sandbox.config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // programs of the command, with `?` for the ones that cannot be known
+    fn programs(command: &str) -> Vec<String> {
+        command_programs(command).into_iter().map(|program| program.unwrap_or("?".to_owned())).collect()
+    }
+
+    fn config(allow: &[&str], deny: &[&str]) -> ShellConfig {
+        ShellConfig {
+            timeout: Duration::from_secs(1),
+            allow: allow.iter().map(|program| program.to_string()).collect(),
+            deny: deny.iter().map(|program| program.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn finds_programs_of_lists_and_pipelines() {
+        assert_eq!(programs("ls -la | grep foo && git status; du -sh *"), ["ls", "grep", "git", "du"]);
+        assert_eq!(programs("LANG=C /usr/bin/ls"), ["ls"]);
+        assert_eq!(programs("for f in *; do rm $f; done"), ["rm"]);
+        assert_eq!(programs("if true; then echo yes; fi"), ["true", "echo"]);
+        assert_eq!(programs("ls # rm -rf x"), ["ls"]);
+    }
+
+    #[test]
+    fn ignores_separators_in_quotes() {
+        assert_eq!(programs("echo 'a | b; c' \"d && e\""), ["echo"]);
+        assert_eq!(programs("git commit -m \"fix (again)\""), ["git"]);
+    }
+
+    #[test]
+    fn skips_redirections() {
+        assert_eq!(programs("ls 2>&1 | grep x"), ["ls", "grep"]);
+        assert_eq!(programs("</dev/null sudo reboot"), ["sudo", "reboot"]);
+        assert_eq!(programs("> out.txt echo hi"), ["echo"]);
+        assert_eq!(programs("cat<in.txt>out.txt"), ["cat"]);
+        assert_eq!(programs("ls &> /dev/null"), ["ls"]);
+    }
+
+    #[test]
+    fn finds_programs_in_substitutions() {
+        assert_eq!(programs("echo $(git rev-parse HEAD)"), ["git", "echo"]);
+        assert_eq!(programs("echo \"today is `date`\""), ["date", "echo"]);
+        assert_eq!(programs("echo $((1 + 2))"), ["echo"]);
+        assert_eq!(programs("(cd src && ls)"), ["cd", "ls"]);
+    }
+
+    #[test]
+    fn removes_quotes_and_escapes_from_names() {
+        assert_eq!(programs("su''do reboot"), ["sudo", "reboot"]);
+        assert_eq!(programs("\\sudo reboot"), ["sudo", "reboot"]);
+        assert_eq!(programs("\"rm\" -rf x"), ["rm"]);
+    }
+
+    #[test]
+    fn programs_from_expansions_are_unknown() {
+        assert_eq!(programs("c=rm; $c -rf x"), ["?"]);
+        assert_eq!(programs("$(which rm) -rf x"), ["which", "?"]);
+        assert_eq!(programs("\"$HOME/bin/tool\""), ["?"]);
+    }
+
+    #[test]
+    fn unwraps_wrappers() {
+        assert_eq!(programs("env -u BAR FOO=1 sudo reboot"), ["env", "sudo", "reboot"]);
+        assert_eq!(programs("nohup rm -rf x &"), ["nohup", "rm"]);
+        assert_eq!(programs("ls | xargs -n 1 rm"), ["ls", "xargs", "rm"]);
+        assert_eq!(programs("sudo -u root rm x"), ["sudo", "rm"]);
+        assert_eq!(programs("exec -a name command nice -n 5 dd if=x"), ["exec", "command", "nice", "dd"]);
+        assert_eq!(programs("env -S 'rm -rf x'"), ["env", "?"]);
+        assert_eq!(programs(r"find . -name '*.tmp' -exec rm {} \; -print"), ["find", "rm"]);
+    }
+
+    #[test]
+    fn reads_scripts_of_shells_and_eval() {
+        assert_eq!(programs("sh -c 'sudo reboot'"), ["sh", "sudo", "reboot"]);
+        assert_eq!(programs("bash -lc \"rm -rf x\""), ["bash", "rm"]);
+        assert_eq!(programs("bash script.sh"), ["bash"]);
+        assert_eq!(programs("su -c 'rm x' root"), ["su", "rm"]);
+        assert_eq!(programs("eval 'rm x'"), ["eval", "rm"]);
+        assert_eq!(programs("sh -c \"$SCRIPT\""), ["sh", "?"]);
+    }
+
+    #[test]
+    fn allow_list_rejects_other_and_unknown_programs() {
+        let config = config(&["git", "grep"], &[]);
+        assert!(config.check("git log --oneline | grep fix").is_ok());
+        assert!(config.check("git status && ls").is_err());
+        assert!(config.check("c=rm; $c -rf x").is_err());
+        assert!(config.check("</dev/null sudo reboot").is_err());
+        assert!(config.check("env git status").is_err());
+        assert!(config.check("sh -c 'git status'").is_err());
+    }
+
+    #[test]
+    fn deny_list_rejects_wrapped_programs() {
+        let config = config(&[], &DEFAULT_SHELL_DENY);
+        assert!(config.check("ls -la").is_ok());
+        assert!(config.check("env sudo reboot").is_err());
+        assert!(config.check("sh -c 'sudo reboot'").is_err());
+        assert!(config.check("echo x | xargs sudo rm").is_err());
+        assert!(config.check("nohup dd if=/dev/zero of=x").is_err());
+        // without an allow list, programs that cannot be known are let through
+        assert!(config.check("$EDITOR notes.txt").is_ok());
+    }
+}
@@ -0,0 +1,441 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+use anyhow::{bail, Context, Result};
+use aws_sdk_bedrockruntime::types::{ToolResultBlock, ToolResultContentBlock, ToolResultStatus};
+use serde_json::json;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+use super::{truncate_text, ToDocument};
+
+
+// per stream, so huge outputs do not fill up the context window
+const MAX_OUTPUT_BYTES: usize = 20_000;
+
+// how far into a working directory files are tracked, so a run in a large folder stays cheap
+const SNAPSHOT_MAX_DEPTH: usize = 3;
+const SNAPSHOT_MAX_FILES: usize = 10_000;
+
+// time left to read the rest of the output once the process has ended
+const PIPE_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+
+#[derive(Clone, Debug)]
+pub struct SandboxConfig {
+    // wall-clock time before the whole process group is killed
+    pub timeout: Duration,
+    // address space limit in megabytes, 0 for no limit
+    pub memory_limit_mb: u64,
+    // CPU time limit in seconds, 0 for no limit
+    pub cpu_limit_secs: u64,
+    pub deny_network: bool,
+}
+
+// How a sandboxed process ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Termination {
+    Exited,
+    TimedOut,
+    CpuLimitExceeded,
+    MemoryLimitExceeded,
+    Signaled(i32),
+}
+
+#[derive(Clone, Debug)]
+pub struct SandboxOutput {
+    pub stdout: String,
+    pub stderr: String,
+    // None when the process was ended by a signal
+    pub exit_code: Option<i32>,
+    pub termination: Termination,
+}
+
+impl SandboxOutput {
+    // message for the model when the process did not finish on its own
+    pub fn termination_message(&self, config: &SandboxConfig) -> Option<String> {
+        match self.termination {
+            Termination::Exited => None,
+            Termination::TimedOut => Some(format!("Execution timed out after {} seconds and was killed.", config.timeout.as_secs())),
+            Termination::CpuLimitExceeded => Some(format!("Execution exceeded the CPU time limit of {} seconds and was killed.", config.cpu_limit_secs)),
+            Termination::MemoryLimitExceeded => Some(format!("Execution exceeded the memory limit of {} MB.", config.memory_limit_mb)),
+            Termination::Signaled(signal) => Some(format!("Execution was killed by signal {signal}.")),
+        }
+    }
+
+    // stdout, stderr and exit code as JSON, with an error status if the process failed
+    pub fn to_tool_result_block(&self, id: &str, config: &SandboxConfig) -> Result<ToolResultBlock> {
+        let error = self.termination_message(config);
+        let succeeded = error.is_none() && self.exit_code == Some(0);
+
+        let mut result = json!({
+            "exitCode": self.exit_code,
+            "stdout": truncate_text(&self.stdout, MAX_OUTPUT_BYTES),
+            "stderr": truncate_text(&self.stderr, MAX_OUTPUT_BYTES),
+        });
+        if let Some(error) = error {
+            result["error"] = json!(error);
+        }
+
+        let tool_result = ToolResultBlock::builder()
+            .tool_use_id(id.to_owned())
+            .content(ToolResultContentBlock::Json(result.to_document()))
+            .status(if succeeded { ToolResultStatus::Success } else { ToolResultStatus::Error })
+            .build()?;
+        Ok(tool_result)
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    pub config: SandboxConfig
+}
+
+impl Sandbox {
+    pub fn new(config: SandboxConfig) -> Self {
+        Self { config }
+    }
+
+    // Command running `program` in its own process group inside `working_directory`, with the configured limits applied.
+    pub fn command<I, S>(&self, program: &str, args: I, working_directory: &Path) -> Result<Command>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut command = if self.config.deny_network {
+            if !network_isolation_available() {
+                bail!("Network access should be denied, but network namespaces (unshare) are not available on this system.")
+            }
+            let mut command = Command::new("unshare");
+            command.args(["--user", "--map-root-user", "--net", "--"]).arg(program);
+            command
+        } else {
+            Command::new(program)
+        };
+
+        command
+            .args(args)
+            .current_dir(working_directory)
+            // plots are saved to files instead of blocking on a window
+            .env("MPLBACKEND", "Agg")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            command.as_std_mut().process_group(0);
+            let memory_limit = self.config.memory_limit_mb * 1024 * 1024;
+            let cpu_limit = self.config.cpu_limit_secs;
+            // SAFETY: only async-signal-safe libc calls are made between fork and exec.
+            unsafe {
+                command.pre_exec(move || {
+                    if memory_limit > 0 {
+                        check_os_result(libc::setrlimit(libc::RLIMIT_AS, &rlimit(memory_limit, memory_limit)))?;
+                    }
+                    if cpu_limit > 0 {
+                        // the soft limit sends SIGXCPU, the hard limit one second later SIGKILL
+                        check_os_result(libc::setrlimit(libc::RLIMIT_CPU, &rlimit(cpu_limit, cpu_limit + 1)))?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+
+        Ok(command)
+    }
+
+    pub async fn run<I, S>(&self, program: &str, args: I, working_directory: &Path) -> Result<SandboxOutput>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut child = self.command(program, args, working_directory)?
+            .spawn()
+            .context(format!("error running command: {program}"))?;
+
+        // the process group id, saved before `wait` since the child forgets its id once it has exited
+        let pid = child.id();
+        let started = Instant::now();
+        let (stdout_task, stdout) = read_pipe(child.stdout.take().context("failed to capture stdout")?);
+        let (stderr_task, stderr) = read_pipe(child.stderr.take().context("failed to capture stderr")?);
+
+        let (status, timed_out) = match timeout(self.config.timeout, child.wait()).await {
+            Ok(status) => (status?, false),
+            Err(_) => {
+                kill_process_group(pid);
+                (child.wait().await?, true)
+            },
+        };
+        // background processes started by the code are not left behind
+        kill_process_group(pid);
+
+        // a process that left the group can keep the pipes open, so reading stops at the timeout
+        let remaining = self.config.timeout.saturating_sub(started.elapsed()).max(PIPE_GRACE_PERIOD);
+        let _ = timeout(remaining, async {
+            let _ = stdout_task.await;
+            let _ = stderr_task.await;
+        }).await;
+        let stdout = String::from_utf8_lossy(&take_buffer(&stdout)).into_owned();
+        let stderr = String::from_utf8_lossy(&take_buffer(&stderr)).into_owned();
+        let termination = if timed_out {
+            Termination::TimedOut
+        } else {
+            termination_from_status(&status, &stderr, &self.config)
+        };
+
+        Ok(
+            SandboxOutput {
+                stdout,
+                stderr,
+                exit_code: status.code(),
+                termination,
+            }
+        )
+    }
+}
+
+
+// Reads a pipe into a buffer in the background. The buffer keeps what was read if the task is stopped early.
+fn read_pipe<R>(mut pipe: R) -> (JoinHandle<()>, Arc<Mutex<Vec<u8>>>)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let bytes = Arc::new(Mutex::new(vec![]));
+    let buffer = bytes.clone();
+    let task = tokio::spawn(async move {
+        let mut chunk = [0; 4096];
+        while let Ok(count) = pipe.read(&mut chunk).await {
+            if count == 0 {
+                break;
+            }
+            buffer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).extend_from_slice(&chunk[..count]);
+        }
+    });
+    (task, bytes)
+}
+
+fn take_buffer(buffer: &Mutex<Vec<u8>>) -> Vec<u8> {
+    std::mem::take(&mut *buffer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+}
+
+// `pid` is the id of the process group leader, saved when it was spawned.
+pub fn kill_process_group(pid: Option<u32>) {
+    #[cfg(unix)]
+    if let Some(pid) = pid {
+        // SAFETY: the child is the leader of its own process group, so only its processes are signaled.
+        unsafe {
+            libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = pid;
+}
+
+// How a process that was not timed out ended, given the limits it ran with.
+pub fn termination_from_status(status: &ExitStatus, stderr: &str, config: &SandboxConfig) -> Termination {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return match signal {
+                libc::SIGXCPU if config.cpu_limit_secs > 0 => Termination::CpuLimitExceeded,
+                // the hard CPU limit kills processes that ignore SIGXCPU, the sandbox itself only kills on timeout
+                libc::SIGKILL if config.cpu_limit_secs > 0 => Termination::CpuLimitExceeded,
+                _ => Termination::Signaled(signal),
+            };
+        }
+    }
+
+    if !status.success() && is_memory_error(stderr, config) {
+        return Termination::MemoryLimitExceeded;
+    }
+    Termination::Exited
+}
+
+// Without a memory limit, a MemoryError is an ordinary failure of the code.
+pub fn is_memory_error(stderr: &str, config: &SandboxConfig) -> bool {
+    config.memory_limit_mb > 0 && (stderr.contains("MemoryError") || stderr.contains("Cannot allocate memory"))
+}
+
+#[cfg(unix)]
+fn rlimit(soft: u64, hard: u64) -> libc::rlimit {
+    libc::rlimit {
+        rlim_cur: soft as libc::rlim_t,
+        rlim_max: hard as libc::rlim_t,
+    }
+}
+
+#[cfg(unix)]
+fn check_os_result(result: libc::c_int) -> std::io::Result<()> {
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// unprivileged network namespaces, checked once
+fn network_isolation_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        if !cfg!(target_os = "linux") {
+            return false;
+        }
+        std::process::Command::new("unshare")
+            .args(["--user", "--map-root-user", "--net", "true"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false)
+    })
+}
+
+
+// Modification times of the files in a working directory, to find the files a run created or changed.
+#[derive(Clone, Debug, Default)]
+pub struct FileSnapshot {
+    files: HashMap<PathBuf, SystemTime>
+}
+
+impl FileSnapshot {
+    pub fn new(directory: &Path) -> Self {
+        let mut snapshot = Self::default();
+        snapshot.visit(directory, 0);
+        snapshot
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+    }
+
+    // files that are new or modified since `self`, sorted by path
+    pub fn changed_files(&self, directory: &Path) -> Vec<PathBuf> {
+        let current = Self::new(directory);
+        let mut changed: Vec<PathBuf> = current.files.into_iter()
+            .filter(|(path, modified)| self.files.get(path) != Some(modified))
+            .map(|(path, _)| path)
+            .collect();
+        changed.sort();
+        changed
+    }
+
+    fn visit(&mut self, directory: &Path, depth: usize) {
+        let Ok(entries) = fs::read_dir(directory) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if self.files.len() >= SNAPSHOT_MAX_FILES {
+                return;
+            }
+            let path = entry.path();
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                let hidden = entry.file_name().to_string_lossy().starts_with('.');
+                if !hidden && depth < SNAPSHOT_MAX_DEPTH {
+                    self.visit(&path, depth + 1);
+                }
+            } else if let Ok(modified) = metadata.modified() {
+                self.files.insert(path, modified);
+            }
+        }
+    }
+}
+
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn config() -> SandboxConfig {
+        SandboxConfig {
+            timeout: Duration::from_secs(10),
+            memory_limit_mb: 0,
+            cpu_limit_secs: 0,
+            deny_network: false,
+        }
+    }
+
+    fn python_available() -> bool {
+        std::process::Command::new("python3").arg("--version").output().is_ok_and(|output| output.status.success())
+    }
+
+    // true once the process has ended, a zombie waiting for its parent counts as ended
+    fn has_ended(pid: &str) -> bool {
+        match fs::read_to_string(format!("/proc/{pid}/stat")) {
+            Ok(stat) => stat.rsplit_once(')').is_some_and(|(_, rest)| rest.trim_start().starts_with('Z')),
+            Err(_) => true,
+        }
+    }
+
+    #[tokio::test]
+    async fn kills_a_process_that_runs_past_the_timeout() {
+        let directory = TempDir::new().unwrap();
+        let config = SandboxConfig { timeout: Duration::from_millis(300), ..config() };
+        let started = Instant::now();
+        let output = Sandbox::new(config.clone()).run("sh", ["-c", "echo started; sleep 30"], directory.path()).await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(output.termination, Termination::TimedOut);
+        assert_eq!(output.stdout, "started\n");
+        assert_eq!(output.termination_message(&config).unwrap(), "Execution timed out after 0 seconds and was killed.");
+    }
+
+    #[tokio::test]
+    async fn kills_the_processes_left_in_the_background() {
+        let directory = TempDir::new().unwrap();
+        let output = Sandbox::new(config()).run("sh", ["-c", "sleep 30 > /dev/null 2>&1 & echo $!"], directory.path()).await.unwrap();
+
+        assert_eq!(output.termination, Termination::Exited);
+        let pid = output.stdout.trim();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !has_ended(pid) && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(has_ended(pid), "background process {pid} is still running");
+    }
+
+    #[tokio::test]
+    async fn reports_the_cpu_limit() {
+        let directory = TempDir::new().unwrap();
+        let config = SandboxConfig { cpu_limit_secs: 1, ..config() };
+        let sandbox = Sandbox::new(config.clone());
+
+        let output = sandbox.run("sh", ["-c", "while :; do :; done"], directory.path()).await.unwrap();
+        assert_eq!(output.termination, Termination::CpuLimitExceeded);
+        assert_eq!(output.termination_message(&config).unwrap(), "Execution exceeded the CPU time limit of 1 seconds and was killed.");
+
+        // SIGXCPU is ignored, so the hard limit kills the process
+        let output = sandbox.run("sh", ["-c", "trap '' XCPU; while :; do :; done"], directory.path()).await.unwrap();
+        assert_eq!(output.termination, Termination::CpuLimitExceeded);
+    }
+
+    #[tokio::test]
+    async fn reports_the_memory_limit_only_when_one_is_set() {
+        if !python_available() {
+            return;
+        }
+        let directory = TempDir::new().unwrap();
+        let limited = SandboxConfig { memory_limit_mb: 256, ..config() };
+        let output = Sandbox::new(limited.clone()).run("python3", ["-c", "data = bytearray(1024 ** 3)"], directory.path()).await.unwrap();
+        assert_eq!(output.termination, Termination::MemoryLimitExceeded);
+        assert_eq!(output.termination_message(&limited).unwrap(), "Execution exceeded the memory limit of 256 MB.");
+
+        let output = Sandbox::new(config()).run("python3", ["-c", "raise MemoryError"], directory.path()).await.unwrap();
+        assert_eq!(output.termination, Termination::Exited);
+        assert_eq!(output.exit_code, Some(1));
+        assert!(output.termination_message(&config()).is_none());
+    }
+}
@@ -0,0 +1,339 @@
+use super::{create_tool_result_block, get_bool, get_number, get_string, ToDocument, Tool, ToolJsonSchema};
+use super::context::ToolContext;
+use super::find_files::PathPattern;
+use super::list_directory::walk_builder;
+
+use std::{fs, path::{Path, PathBuf}};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use aws_sdk_bedrockruntime::types::{ToolResultBlock, ToolResultStatus};
+use aws_smithy_types::Document;
+use regex::{Regex, RegexBuilder};
+use serde_json::json;
+use tokio::task;
+
+
+// SEARCH_FILES tool
+pub const SEARCH_FILES_NAME: &str = "SEARCH_FILES";
+pub const SEARCH_FILES_DESCRIPTION: &str = "Search the contents of files for a regular expression or a literal text, like grep. Returns the matching lines as path:line references, optionally with the lines around them. Use this to find where something is defined or used, then READ_FILE with offset to read more. Files ignored by .gitignore, hidden files and binary files are left out by default.";
+
+pub const DEFAULT_MAX_MATCHES: usize = 100;
+pub const MAX_MATCHES: usize = 1000;
+pub const MAX_CONTEXT_LINES: usize = 10;
+// longer lines, such as minified code, are cut
+const MAX_LINE_LENGTH: usize = 300;
+// larger files are skipped
+const MAX_FILE_BYTES: u64 = 10_000_000;
+
+pub fn search_files_schema() -> Result<Document> {
+    let json_schema = json!({
+        "type": "object",
+        "properties": {
+            "pattern": {
+                "type": "string",
+                "description": "The regular expression to search for, in Rust regex syntax. For example `retry_?config` or `fn \\w+_image`."
+            },
+            "literal": {
+                "type": "boolean",
+                "description": "Search for the pattern as plain text instead of a regular expression. Optional. The default value is false."
+            },
+            "ignoreCase": {
+                "type": "boolean",
+                "description": "Match regardless of case. Optional. The default value is false."
+            },
+            "path": {
+                "type": "string",
+                "description": "The path of the directory or the file to search in. Default to the current working directory."
+            },
+            "include": {
+                "type": "string",
+                "description": "Only search files matching this glob pattern, such as `*.rs` or `src/**/*.toml`. Optional."
+            },
+            "exclude": {
+                "type": "string",
+                "description": "Skip files matching this glob pattern, such as `*.lock`. Optional."
+            },
+            "contextLines": {
+                "type": "number",
+                "description": format!("The number of lines to show before and after each match. Optional. The default value is 0, at most {MAX_CONTEXT_LINES}.")
+            },
+            "maxResults": {
+                "type": "number",
+                "description": format!("The maximum number of matching lines to return. Optional. The default value is {DEFAULT_MAX_MATCHES}, at most {MAX_MATCHES}.")
+            },
+            "includeIgnored": {
+                "type": "boolean",
+                "description": "Include files ignored by .gitignore and hidden files. Optional. The default value is false."
+            },
+        },
+        "required": ["pattern"],
+    });
+    let document = ToolJsonSchema::new(json_schema)?.to_document();
+    Ok(document)
+}
+
+pub struct SearchFilesTool;
+
+#[async_trait]
+impl Tool for SearchFilesTool {
+    fn name(&self) -> &str {
+        SEARCH_FILES_NAME
+    }
+
+    fn description(&self) -> &str {
+        SEARCH_FILES_DESCRIPTION
+    }
+
+    fn input_schema(&self) -> Result<Document> {
+        search_files_schema()
+    }
+
+    async fn execute(&self, id: &str, input: &Document, _context: &ToolContext) -> Result<ToolResultBlock> {
+        let id = id.to_owned();
+        let input = input.clone();
+        task::spawn_blocking(move || {
+            match search_files(&input) {
+                Ok(content) => create_tool_result_block(&id, &content, ToolResultStatus::Success),
+                Err(err) => create_tool_result_block(&id, &err.to_string(), ToolResultStatus::Error),
+            }
+        }).await?
+    }
+}
+
+struct Search {
+    regex: Regex,
+    context_lines: usize,
+    max_matches: usize,
+    matches: usize,
+    files: usize,
+    output: Vec<String>,
+}
+
+fn search_files(input: &Document) -> Result<String> {
+    let pattern = match get_string(input, "pattern") {
+        Some(pattern) if !pattern.is_empty() => pattern,
+        _ => bail!("pattern to search for is not provided."),
+    };
+    let literal = get_bool(input, "literal").unwrap_or(false);
+    let regex = RegexBuilder::new(&if literal { regex::escape(pattern) } else { pattern.to_owned() })
+        .case_insensitive(get_bool(input, "ignoreCase").unwrap_or(false))
+        .build()
+        .context(format!("invalid regular expression: {pattern}. Set literal to true to search for it as plain text."))?;
+
+    let path = PathBuf::from(get_string(input, "path").unwrap_or("."));
+    if !path.exists() {
+        bail!("{} does not exist.", path.display())
+    }
+    let include = get_string(input, "include").map(PathPattern::new).transpose()?;
+    let exclude = get_string(input, "exclude").map(PathPattern::new).transpose()?;
+    let context_lines = match get_number(input, "contextLines") {
+        Some(lines) if lines < 0.0 => bail!("contextLines should be 0 or more."),
+        Some(lines) => (lines as usize).min(MAX_CONTEXT_LINES),
+        None => 0,
+    };
+    let max_matches = match get_number(input, "maxResults") {
+        Some(max_results) if max_results < 1.0 => bail!("maxResults should be 1 or more."),
+        Some(max_results) => (max_results as usize).min(MAX_MATCHES),
+        None => DEFAULT_MAX_MATCHES,
+    };
+    let include_ignored = get_bool(input, "includeIgnored").unwrap_or(false);
+
+    let mut search = Search { regex, context_lines, max_matches, matches: 0, files: 0, output: vec![] };
+    for entry in walk_builder(&path, None, include_ignored).build() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+            continue;
+        }
+        // a file given as the path is matched by its name
+        let relative = match entry.path().strip_prefix(&path) {
+            Ok(relative) if relative.as_os_str().is_empty() => Path::new(entry.file_name()),
+            Ok(relative) => relative,
+            Err(_) => entry.path(),
+        };
+        if include.as_ref().is_some_and(|include| !include.is_match(relative)) ||
+            exclude.as_ref().is_some_and(|exclude| exclude.is_match(relative)) {
+            continue;
+        }
+        if entry.metadata().is_ok_and(|metadata| metadata.len() > MAX_FILE_BYTES) {
+            continue;
+        }
+        search.search_file(entry.path());
+    }
+
+    if search.matches == 0 {
+        return Ok(format!("No matches for {} in {}.", pattern, path.display()));
+    }
+    let shown = search.matches.min(max_matches);
+    let mut content = format!(
+        "Found {} matching lines in {} files for {}. Matching lines are `path:line: text`, context lines are `path-line- text`.\n{}",
+        search.matches, search.files, pattern, search.output.join("\n")
+    );
+    if search.matches > shown {
+        content.push_str(&format!(
+            "\n... {} more matching lines not shown. Use a more specific pattern, include or path, or a larger maxResults to see them.",
+            search.matches - shown
+        ));
+    }
+    Ok(content)
+}
+
+impl Search {
+    // Counts every match, but only keeps the lines of the first max_matches.
+    fn search_file(&mut self, path: &Path) {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(_) => return,
+        };
+        // binary file
+        if bytes.iter().take(8000).any(|byte| *byte == 0) {
+            return;
+        }
+        let text = String::from_utf8_lossy(&bytes);
+        let lines: Vec<&str> = text.lines().collect();
+        let matching: Vec<usize> = (0..lines.len()).filter(|index| self.regex.is_match(lines[*index])).collect();
+        if matching.is_empty() {
+            return;
+        }
+        self.files += 1;
+
+        // the last line written for this file, so overlapping context is not repeated
+        let mut written: Option<usize> = None;
+        for index in matching {
+            self.matches += 1;
+            if self.matches > self.max_matches {
+                continue;
+            }
+            let start = index.saturating_sub(self.context_lines);
+            let start = match written {
+                Some(written) if written >= start => written + 1,
+                Some(_) | None => {
+                    if self.context_lines > 0 && !self.output.is_empty() {
+                        self.output.push("--".to_owned());
+                    }
+                    start
+                },
+            };
+            let end = (index + self.context_lines).min(lines.len() - 1);
+            for (number, line) in lines.iter().enumerate().take(end + 1).skip(start) {
+                let separator = if self.regex.is_match(line) { ':' } else { '-' };
+                self.output.push(format!("{}{separator}{}{separator} {}", path.display(), number + 1, cut_line(line)));
+            }
+            written = Some(end);
+        }
+    }
+}
+
+fn cut_line(line: &str) -> String {
+    if line.len() <= MAX_LINE_LENGTH {
+        return line.to_owned();
+    }
+    let mut end = MAX_LINE_LENGTH;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{} ...", &line[..end])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use tempfile::TempDir;
+
+    const SOURCE: &str = "fn main() {\n    let retry_config = RetryConfig::new();\n    let retries = 3;\n    run(retry_config);\n}\n";
+
+    // the result lines after the header, relative to the directory
+    fn results(content: &str, directory: &Path) -> Vec<String> {
+        let prefix = format!("{}/", directory.display());
+        content.lines().skip(1).map(|line| line.replacen(&prefix, "", 1)).collect()
+    }
+
+    fn search(directory: &Path, pattern: &str, mut options: Value) -> Result<String> {
+        options["path"] = json!(directory);
+        options["pattern"] = json!(pattern);
+        search_files(&options.to_document())
+    }
+
+    #[test]
+    fn searches_for_a_regular_expression() {
+        let directory = TempDir::new().unwrap();
+        fs::write(directory.path().join("main.rs"), SOURCE).unwrap();
+
+        let content = search(directory.path(), r"retry_?config", json!({})).unwrap();
+        assert!(content.starts_with("Found 2 matching lines in 1 files for retry_?config."));
+        assert_eq!(results(&content, directory.path()), [
+            "main.rs:2:     let retry_config = RetryConfig::new();",
+            "main.rs:4:     run(retry_config);",
+        ]);
+    }
+
+    #[test]
+    fn searches_for_a_literal_text_and_ignores_case() {
+        let directory = TempDir::new().unwrap();
+        fs::write(directory.path().join("main.rs"), SOURCE).unwrap();
+
+        let content = search(directory.path(), "RetryConfig::new()", json!({ "literal": true })).unwrap();
+        assert_eq!(results(&content, directory.path()), ["main.rs:2:     let retry_config = RetryConfig::new();"]);
+        let err = search(directory.path(), "(", json!({})).unwrap_err();
+        assert!(err.to_string().starts_with("invalid regular expression: (."));
+
+        let content = search(directory.path(), "retryconfig", json!({})).unwrap();
+        assert_eq!(content, format!("No matches for retryconfig in {}.", directory.path().display()));
+        let content = search(directory.path(), "retryconfig", json!({ "ignoreCase": true })).unwrap();
+        assert_eq!(results(&content, directory.path()), ["main.rs:2:     let retry_config = RetryConfig::new();"]);
+    }
+
+    #[test]
+    fn merges_overlapping_context() {
+        let directory = TempDir::new().unwrap();
+        let lines: Vec<String> = (1..=12).map(|number| format!("line {number}")).collect();
+        fs::write(directory.path().join("notes.txt"), lines.join("\n")).unwrap();
+
+        let content = search(directory.path(), r"^line (3|5|11)$", json!({ "contextLines": 1 })).unwrap();
+        assert_eq!(results(&content, directory.path()), [
+            "notes.txt-2- line 2",
+            "notes.txt:3: line 3",
+            "notes.txt-4- line 4",
+            "notes.txt:5: line 5",
+            "notes.txt-6- line 6",
+            "--",
+            "notes.txt-10- line 10",
+            "notes.txt:11: line 11",
+            "notes.txt-12- line 12",
+        ]);
+    }
+
+    #[test]
+    fn caps_the_number_of_matches() {
+        let directory = TempDir::new().unwrap();
+        fs::write(directory.path().join("log.txt"), "error\n".repeat(5)).unwrap();
+
+        let content = search(directory.path(), "error", json!({ "maxResults": 2 })).unwrap();
+        assert!(content.starts_with("Found 5 matching lines in 1 files"));
+        assert_eq!(results(&content, directory.path()), [
+            "log.txt:1: error",
+            "log.txt:2: error",
+            "... 3 more matching lines not shown. Use a more specific pattern, include or path, or a larger maxResults to see them.",
+        ]);
+    }
+
+    #[test]
+    fn skips_binary_and_oversized_files() {
+        let directory = TempDir::new().unwrap();
+        fs::write(directory.path().join("text.txt"), "needle\n").unwrap();
+        fs::write(directory.path().join("data.bin"), b"needle\n\0\x01\x02").unwrap();
+        let mut large = "needle\n".to_owned();
+        while (large.len() as u64) <= MAX_FILE_BYTES {
+            large.push_str(&"haystack ".repeat(1000));
+            large.push('\n');
+        }
+        fs::write(directory.path().join("large.txt"), large).unwrap();
+
+        let content = search(directory.path(), "needle", json!({})).unwrap();
+        assert_eq!(results(&content, directory.path()), ["text.txt:1: needle"]);
+    }
+}
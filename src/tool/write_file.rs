@@ -0,0 +1,104 @@
+use super::{create_tool_result_block, get_string, ToDocument, Tool, ToolJsonSchema};
+use super::context::ToolContext;
+use super::file_change::{FileChange, PlannedChanges};
+use super::permission::ToolPolicy;
+
+use std::path::PathBuf;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use aws_sdk_bedrockruntime::types::{ToolResultBlock, ToolResultStatus};
+use aws_smithy_types::Document;
+use serde_json::json;
+use tokio::task;
+
+
+// WRITE_FILE tool
+pub const WRITE_FILE_NAME: &str = "WRITE_FILE";
+pub const WRITE_FILE_DESCRIPTION: &str = "Create a text file, or replace the whole content of an existing one. Missing folders are created. To change part of an existing file, use EDIT_FILE instead. The user reviews the change before it is written, and replaced files are backed up.";
+
+pub fn write_file_schema() -> Result<Document> {
+    let json_schema = json!({
+        "type": "object",
+        "properties": {
+            "path": {
+                "type": "string",
+                "description": "The path of the file to write."
+            },
+            "content": {
+                "type": "string",
+                "description": "The full content of the file."
+            },
+        },
+        "required": ["path", "content"],
+    });
+    let document = ToolJsonSchema::new(json_schema)?.to_document();
+    Ok(document)
+}
+
+#[derive(Debug, Default)]
+pub struct WriteFileTool {
+    // the changes previewed when asking for approval
+    planned: PlannedChanges,
+}
+
+#[async_trait]
+impl Tool for WriteFileTool {
+    fn name(&self) -> &str {
+        WRITE_FILE_NAME
+    }
+
+    fn description(&self) -> &str {
+        WRITE_FILE_DESCRIPTION
+    }
+
+    fn input_schema(&self) -> Result<Document> {
+        write_file_schema()
+    }
+
+    fn default_policy(&self) -> ToolPolicy {
+        ToolPolicy::Ask
+    }
+
+    fn preview(&self, input: &Document) -> String {
+        match planned_change(input) {
+            Ok(change) => {
+                let preview = change.preview();
+                self.planned.insert(input, change);
+                preview
+            },
+            Err(err) => format!("The file cannot be written: {err}"),
+        }
+    }
+
+    async fn execute(&self, id: &str, input: &Document, context: &ToolContext) -> Result<ToolResultBlock> {
+        let id = id.to_owned();
+        let input = input.clone();
+        let context = context.clone();
+        let planned = self.planned.take(&input);
+        task::spawn_blocking(move || {
+            let change = match planned {
+                Some(change) => Ok(change),
+                None => planned_change(&input),
+            };
+            let result = change.and_then(|change| {
+                let backup = change.write(&id, &context)?;
+                Ok(change.summary(backup.as_deref()))
+            });
+            match result {
+                Ok(summary) => create_tool_result_block(&id, &summary, ToolResultStatus::Success),
+                Err(err) => create_tool_result_block(&id, &err.to_string(), ToolResultStatus::Error),
+            }
+        }).await?
+    }
+}
+
+fn planned_change(input: &Document) -> Result<FileChange> {
+    let path = match get_string(input, "path") {
+        Some(path) if !path.trim().is_empty() => PathBuf::from(path),
+        _ => bail!("path to write the file to is not provided."),
+    };
+    let Some(content) = get_string(input, "content") else {
+        bail!("content of the file is not provided.")
+    };
+    FileChange::new(&path, content.to_owned())
+}